@@ -1,5 +1,6 @@
 use chrono::{DateTime, Utc};
 use clickhouse::{Client, Row};
+use log_multiline::text_level;
 use serde::{Deserialize, Serialize};
 
 #[derive(Row, Serialize, Deserialize, Debug, Clone)]
@@ -10,6 +11,24 @@ pub struct LogEntry {
     message: String,
 }
 
+impl LogEntry {
+    /// A log for text that arrived without a JSON envelope, such as a stack trace. The level is
+    /// read from its first words, and is ERROR when none is named.
+    pub fn from_text(text: &str) -> Self {
+        LogEntry {
+            timestamp: Utc::now(),
+            level: text_level(text).unwrap_or_else(|| "ERROR".to_string()),
+            message: text.to_string(),
+        }
+    }
+
+    /// Adds the lines that followed this log, e.g. its stack trace, to the message.
+    pub fn append(&mut self, lines: &str) {
+        self.message.push('\n');
+        self.message.push_str(lines);
+    }
+}
+
 #[derive(Row, Debug, Serialize, Deserialize, Clone)]
 pub struct InfoLog {
     #[serde(serialize_with = "clickhouse::serde::chrono::datetime64::nanos::serialize")]
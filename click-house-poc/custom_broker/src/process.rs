@@ -1,34 +1,43 @@
-use crate::log::{Log};
+use crate::log::{Log, LogEntry};
 use clickhouse::Client;
+use log_multiline::{MultilineAggregator, MultilineConfig};
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::net::TcpStream;
+use tokio::time::{Instant, sleep_until};
 pub async fn process_stream(
     stream: TcpStream,
     client: Client,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut reader = BufReader::new(stream);
-    let mut line = String::new();
+    let mut line = Vec::new();
+    // Lines of a stack trace that follow a log are joined with it into one event
+    let mut multiline = MultilineAggregator::new(&MultilineConfig::default())?;
 
     loop {
-        // Read one line (one log message) from the TCP stream
-        match reader.read_line(&mut line).await {
+        let deadline = multiline.deadline().map(Instant::from_std);
+        // read_until keeps partial bytes in `line` if the timeout fires first
+        let read = tokio::select! {
+            read = reader.read_until(b'\n', &mut line) => read,
+            _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                if let Some(event) = multiline.flush_expired(std::time::Instant::now()) {
+                    handle_event(&event, &client).await;
+                }
+                continue;
+            }
+        };
+        match read {
             Ok(0) => {
                 // 0 bytes read means the connection was closed by the client
                 println!("Client disconnected.");
                 break;
             }
             Ok(_) => {
-                // Attempt to deserialize the received line into our LogEntry struct
-                let trimmed_line = line.trim();
-                if trimmed_line.is_empty() {
-                    line.clear();
-                    continue;
-                }
-                println!("trimmedline{}", trimmed_line);
-                if let Ok(log_variant) = serde_json::from_str::<Log>(trimmed_line) {
-                    // 2. DISPATCH CALL (Exactly once!)
-                    if let Err(e) = log_variant.handle(&client).await {
-                        eprintln!("Failed to handle log: {}", e);
+                let text = String::from_utf8_lossy(&line);
+                let trimmed_line = text.trim_end();
+                if !trimmed_line.trim_start().is_empty() {
+                    println!("trimmedline{}", trimmed_line);
+                    if let Some(event) = multiline.push(trimmed_line) {
+                        handle_event(&event, &client).await;
                     }
                 }
                 line.clear(); // Important: clear the buffer for the next line
@@ -40,5 +49,43 @@ pub async fn process_stream(
             }
         }
     }
+    if let Some(event) = multiline.finish() {
+        handle_event(&event, &client).await;
+    }
     Ok(())
 }
+
+/// Stores one event: a JSON log, possibly followed by a stack trace that is appended to its
+/// message, or several lines of plain text such as a bare stack trace, stored as a `LogEntry`.
+async fn handle_event(event: &str, client: &Client) {
+    let (first, rest) = match event.split_once('\n') {
+        Some((first, rest)) => (first, Some(rest)),
+        None => (event, None),
+    };
+    let log_variant = match serde_json::from_str::<Log>(first.trim()) {
+        Ok(Log::Entry(mut entry)) => {
+            if let Some(rest) = rest {
+                entry.append(rest);
+            }
+            Log::Entry(entry)
+        }
+        Ok(log_variant) => {
+            // Only LogEntry has a message to hold the trace; keep it as a log of its own
+            if let Some(rest) = rest {
+                handle_log(Log::Entry(LogEntry::from_text(rest)), client).await;
+            }
+            log_variant
+        }
+        // A single line that is not a log is ignored
+        Err(_) if rest.is_none() => return,
+        Err(_) => Log::Entry(LogEntry::from_text(event)),
+    };
+    handle_log(log_variant, client).await;
+}
+
+async fn handle_log(log_variant: Log, client: &Client) {
+    // 2. DISPATCH CALL (Exactly once!)
+    if let Err(e) = log_variant.handle(client).await {
+        eprintln!("Failed to handle log: {}", e);
+    }
+}
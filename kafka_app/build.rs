@@ -0,0 +1,8 @@
+// Generates the Protobuf log messages in `src/proto.rs` from `proto/logs.proto`. protox parses the
+// file, so building does not need `protoc`.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto/logs.proto");
+    let descriptors = protox::compile(["logs.proto"], ["proto"])?;
+    prost_build::Config::new().compile_fds(descriptors)?;
+    Ok(())
+}
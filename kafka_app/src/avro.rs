@@ -0,0 +1,688 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, bail};
+use apache_avro::Schema;
+use apache_avro::reader::datum::GenericDatumReader;
+use apache_avro::types::Value as AvroValue;
+use apache_avro::writer::datum::GenericDatumWriter;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio::sync::RwLock;
+
+use crate::constant::{REGISTRY_CONNECT_TIMEOUT_SECS, REGISTRY_REQUEST_TIMEOUT_SECS};
+use crate::helper::json_text;
+use crate::models::LogType;
+
+/// Prefix of the Confluent wire format: `[0x00][u32 schema id, big endian][avro datum]`.
+const MAGIC_BYTE: u8 = 0;
+const SCHEMA_NAMESPACE: &str = "kafka_app.logs";
+const REGISTRY_CONTENT_TYPE: &str = "application/vnd.schemaregistry.v1+json";
+
+const INFO_LOG_SCHEMA: &str = r#"{
+  "type": "record", "name": "InfoLog", "namespace": "kafka_app.logs",
+  "fields": [
+    {"name": "level", "type": "string"},
+    {"name": "message", "type": "string"},
+    {"name": "hostname", "type": "string"},
+    {"name": "timestamp", "type": "string"},
+    {"name": "target", "type": ["null", "string"], "default": null},
+    {"name": "spans", "type": {"type": "array", "items": "string"}, "default": []},
+    {"name": "fields", "type": {"type": "map", "values": "string"}, "default": {}}
+  ]
+}"#;
+
+const WARN_LOG_SCHEMA: &str = r#"{
+  "type": "record", "name": "WarnLog", "namespace": "kafka_app.logs",
+  "fields": [
+    {"name": "level", "type": "string"},
+    {"name": "message", "type": "string"},
+    {"name": "hostname", "type": "string"},
+    {"name": "timestamp", "type": "string"},
+    {"name": "reason", "type": "string"},
+    {"name": "target", "type": ["null", "string"], "default": null},
+    {"name": "spans", "type": {"type": "array", "items": "string"}, "default": []},
+    {"name": "fields", "type": {"type": "map", "values": "string"}, "default": {}}
+  ]
+}"#;
+
+const ERROR_LOG_SCHEMA: &str = r#"{
+  "type": "record", "name": "ErrorLog", "namespace": "kafka_app.logs",
+  "fields": [
+    {"name": "level", "type": "string"},
+    {"name": "message", "type": "string"},
+    {"name": "hostname", "type": "string"},
+    {"name": "timestamp", "type": "string"},
+    {"name": "error_code", "type": "long"},
+    {"name": "target", "type": ["null", "string"], "default": null},
+    {"name": "spans", "type": {"type": "array", "items": "string"}, "default": []},
+    {"name": "fields", "type": {"type": "map", "values": "string"}, "default": {}}
+  ]
+}"#;
+
+/// How the registry subject is derived, mirroring the Confluent serializer strategies.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SubjectNameStrategy {
+    /// `<topic>-value`
+    #[default]
+    TopicName,
+    /// `kafka_app.logs.<Record>`
+    RecordName,
+    /// `<topic>-kafka_app.logs.<Record>`
+    TopicRecordName,
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct SchemaRegistryConfig {
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub subject_strategy: SubjectNameStrategy,
+}
+
+impl fmt::Debug for SchemaRegistryConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SchemaRegistryConfig")
+            .field("url", &self.url)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("subject_strategy", &self.subject_strategy)
+            .finish()
+    }
+}
+
+/// Minimal client for a Confluent-compatible Schema Registry.
+pub struct SchemaRegistryClient {
+    http: reqwest::Client,
+    cfg: SchemaRegistryConfig,
+}
+
+#[derive(Deserialize)]
+struct RegisterResponse {
+    id: u32,
+}
+
+#[derive(Deserialize)]
+struct SchemaResponse {
+    schema: String,
+}
+
+impl SchemaRegistryClient {
+    pub fn new(cfg: SchemaRegistryConfig) -> anyhow::Result<Self> {
+        if cfg.url.is_empty() {
+            bail!("schema registry url must be set");
+        }
+        Ok(Self {
+            http: reqwest::Client::builder()
+                .connect_timeout(Duration::from_secs(REGISTRY_CONNECT_TIMEOUT_SECS))
+                .timeout(Duration::from_secs(REGISTRY_REQUEST_TIMEOUT_SECS))
+                .build()?,
+            cfg,
+        })
+    }
+
+    /// Registers `schema` under `subject` and returns its id. Registering an identical schema
+    /// again returns the existing id.
+    pub async fn register(&self, subject: &str, schema: &str) -> anyhow::Result<u32> {
+        let url = format!(
+            "{}/subjects/{}/versions",
+            self.cfg.url.trim_end_matches('/'),
+            subject
+        );
+        let mut request = self
+            .http
+            .post(&url)
+            .header(reqwest::header::CONTENT_TYPE, REGISTRY_CONTENT_TYPE)
+            .json(&json!({ "schema": schema }));
+        if let Some(username) = &self.cfg.username {
+            request = request.basic_auth(username, self.cfg.password.as_ref());
+        }
+
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("registering schema for subject {}", subject))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            bail!(
+                "schema registry rejected subject {}: {} {}",
+                subject,
+                status,
+                body
+            );
+        }
+        Ok(response.json::<RegisterResponse>().await?.id)
+    }
+
+    /// Fetches the schema registered under `id`.
+    pub async fn schema(&self, id: u32) -> anyhow::Result<String> {
+        let url = format!("{}/schemas/ids/{}", self.cfg.url.trim_end_matches('/'), id);
+        let mut request = self.http.get(&url);
+        if let Some(username) = &self.cfg.username {
+            request = request.basic_auth(username, self.cfg.password.as_ref());
+        }
+
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("fetching schema {}", id))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            bail!("schema registry has no schema {}: {} {}", id, status, body);
+        }
+        Ok(response.json::<SchemaResponse>().await?.schema)
+    }
+}
+
+/// Encodes logs as Avro in the Schema Registry wire format, registering each schema the first
+/// time it is used for a subject.
+pub struct AvroEncoder {
+    registry: SchemaRegistryClient,
+    strategy: SubjectNameStrategy,
+    schemas: HashMap<LogType, (Schema, &'static str)>,
+    ids: RwLock<HashMap<String, u32>>,
+}
+
+impl AvroEncoder {
+    pub fn new(cfg: SchemaRegistryConfig) -> anyhow::Result<Self> {
+        let strategy = cfg.subject_strategy;
+        let mut schemas = HashMap::new();
+        for (log_type, raw) in [
+            (LogType::Info, INFO_LOG_SCHEMA),
+            (LogType::Warn, WARN_LOG_SCHEMA),
+            (LogType::Error, ERROR_LOG_SCHEMA),
+        ] {
+            schemas.insert(log_type, (Schema::parse_str(raw)?, raw));
+        }
+
+        Ok(Self {
+            registry: SchemaRegistryClient::new(cfg)?,
+            strategy,
+            schemas,
+            ids: RwLock::new(HashMap::new()),
+        })
+    }
+
+    pub async fn encode(
+        &self,
+        topic: &str,
+        log_type: LogType,
+        doc: &Value,
+    ) -> anyhow::Result<Vec<u8>> {
+        let (schema, raw) = &self.schemas[&log_type];
+        let schema_id = self.schema_id(topic, log_type, raw).await?;
+
+        let value = to_avro(doc, schema)?;
+        let datum = GenericDatumWriter::builder(schema)
+            .build()?
+            .write_value_to_vec(value)?;
+
+        let mut payload = Vec::with_capacity(5 + datum.len());
+        payload.push(MAGIC_BYTE);
+        payload.extend_from_slice(&schema_id.to_be_bytes());
+        payload.extend_from_slice(&datum);
+        Ok(payload)
+    }
+
+    async fn schema_id(&self, topic: &str, log_type: LogType, raw: &str) -> anyhow::Result<u32> {
+        let subject = self.subject(topic, log_type);
+        if let Some(id) = self.ids.read().await.get(&subject) {
+            return Ok(*id);
+        }
+        let id = self.registry.register(&subject, raw).await?;
+        self.ids.write().await.insert(subject, id);
+        Ok(id)
+    }
+
+    fn subject(&self, topic: &str, log_type: LogType) -> String {
+        let record = format!("{}.{}", SCHEMA_NAMESPACE, record_name(log_type));
+        match self.strategy {
+            SubjectNameStrategy::TopicName => format!("{}-value", topic),
+            SubjectNameStrategy::RecordName => record,
+            SubjectNameStrategy::TopicRecordName => format!("{}-{}", topic, record),
+        }
+    }
+}
+
+/// Decodes payloads in the Schema Registry wire format into the JSON form of the log, fetching
+/// each writer schema by the id in the payload the first time it is seen.
+pub struct AvroDecoder {
+    registry: SchemaRegistryClient,
+    schemas: RwLock<HashMap<u32, Arc<Schema>>>,
+}
+
+impl AvroDecoder {
+    pub fn new(cfg: SchemaRegistryConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            registry: SchemaRegistryClient::new(cfg)?,
+            schemas: RwLock::new(HashMap::new()),
+        })
+    }
+
+    pub async fn decode(&self, payload: &[u8]) -> anyhow::Result<Value> {
+        let (id, mut datum) = match payload {
+            [MAGIC_BYTE, a, b, c, d, datum @ ..] => (u32::from_be_bytes([*a, *b, *c, *d]), datum),
+            _ => bail!("not in the schema registry wire format"),
+        };
+        let schema = self.writer_schema(id).await?;
+        let value = GenericDatumReader::builder(&schema)
+            .build()?
+            .read_value(&mut datum)
+            .with_context(|| format!("decoding a datum of schema {}", id))?;
+
+        let mut doc = Value::try_from(value)?;
+        // Unset optional fields, like `target`, are left out as in the JSON encoding.
+        if let Some(object) = doc.as_object_mut() {
+            object.retain(|_, value| !value.is_null());
+        }
+        Ok(doc)
+    }
+
+    async fn writer_schema(&self, id: u32) -> anyhow::Result<Arc<Schema>> {
+        if let Some(schema) = self.schemas.read().await.get(&id) {
+            return Ok(schema.clone());
+        }
+        let raw = self.registry.schema(id).await?;
+        let schema =
+            Arc::new(Schema::parse_str(&raw).with_context(|| format!("parsing schema {}", id))?);
+        self.schemas.write().await.insert(id, schema.clone());
+        Ok(schema)
+    }
+}
+
+fn record_name(log_type: LogType) -> &'static str {
+    match log_type {
+        LogType::Info => "InfoLog",
+        LogType::Warn => "WarnLog",
+        LogType::Error => "ErrorLog",
+    }
+}
+
+/// Converts the JSON form of a log into an Avro value for `schema`. Only the shapes used by the
+/// log schemas are supported; anything that is not a string where a string is expected is
+/// written as its JSON text.
+fn to_avro(doc: &Value, schema: &Schema) -> anyhow::Result<AvroValue> {
+    let value = match schema {
+        Schema::Record(record) => {
+            let mut fields = Vec::with_capacity(record.fields.len());
+            for field in &record.fields {
+                let value = doc.get(&field.name).unwrap_or(&Value::Null);
+                fields.push((field.name.clone(), to_avro(value, &field.schema)?));
+            }
+            AvroValue::Record(fields)
+        }
+        Schema::String => AvroValue::String(json_text(doc)),
+        Schema::Long => match (doc.as_i64(), doc.as_u64()) {
+            (Some(v), _) => AvroValue::Long(v),
+            (None, Some(v)) => bail!("{} is out of range for an Avro long", v),
+            (None, None) => bail!("expected an integer, got {}", doc),
+        },
+        Schema::Union(union) => {
+            let null_index = union
+                .variants()
+                .iter()
+                .position(|s| matches!(s, Schema::Null));
+            match (doc, null_index) {
+                (Value::Null, Some(index)) => {
+                    AvroValue::Union(index as u32, Box::new(AvroValue::Null))
+                }
+                _ => {
+                    let (index, schema) = union
+                        .variants()
+                        .iter()
+                        .enumerate()
+                        .find(|(_, s)| !matches!(s, Schema::Null))
+                        .context("union has no non-null branch")?;
+                    AvroValue::Union(index as u32, Box::new(to_avro(doc, schema)?))
+                }
+            }
+        }
+        Schema::Array(array) => {
+            let items = doc.as_array().map(Vec::as_slice).unwrap_or_default();
+            AvroValue::Array(
+                items
+                    .iter()
+                    .map(|item| to_avro(item, &array.items))
+                    .collect::<anyhow::Result<_>>()?,
+            )
+        }
+        Schema::Map(map) => {
+            let mut entries = HashMap::new();
+            if let Some(object) = doc.as_object() {
+                for (key, value) in object {
+                    entries.insert(key.clone(), to_avro(value, &map.types)?);
+                }
+            }
+            AvroValue::Map(entries)
+        }
+        Schema::Null => AvroValue::Null,
+        other => bail!("unsupported schema type {:?}", other),
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::extract::{Path, State};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+
+    use super::*;
+
+    /// Registrations received by the mock registry: subject, schema and authorization header.
+    type Registrations = Arc<Mutex<Vec<(String, String, Option<String>)>>>;
+
+    /// Serves `POST /subjects/{subject}/versions`, handing out ids from 100 on, and
+    /// `GET /schemas/ids/{id}` for the schemas registered. Subjects starting with `rejected` get
+    /// a 409.
+    async fn mock_registry() -> (String, Registrations) {
+        let registrations = Registrations::default();
+        let app = Router::new()
+            .route(
+                "/subjects/{subject}/versions",
+                post(
+                    |State(registrations): State<Registrations>,
+                     Path(subject): Path<String>,
+                     headers: HeaderMap,
+                     Json(body): Json<Value>| async move {
+                        if subject.starts_with("rejected") {
+                            return Err((StatusCode::CONFLICT, "incompatible schema"));
+                        }
+                        let mut registrations = registrations.lock().unwrap();
+                        let auth = headers
+                            .get("authorization")
+                            .and_then(|value| value.to_str().ok())
+                            .map(String::from);
+                        let schema = body["schema"].as_str().unwrap_or_default().to_string();
+                        registrations.push((subject, schema, auth));
+                        Ok(Json(json!({ "id": 99 + registrations.len() })))
+                    },
+                ),
+            )
+            .route(
+                "/schemas/ids/{id}",
+                get(
+                    |State(registrations): State<Registrations>, Path(id): Path<usize>| async move {
+                        let registrations = registrations.lock().unwrap();
+                        match id
+                            .checked_sub(100)
+                            .and_then(|index| registrations.get(index))
+                        {
+                            Some((_, schema, _)) => Ok(Json(json!({ "schema": schema }))),
+                            None => Err((StatusCode::NOT_FOUND, "schema not found")),
+                        }
+                    },
+                ),
+            )
+            .with_state(registrations.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, registrations)
+    }
+
+    fn encoder(url: &str, subject_strategy: SubjectNameStrategy) -> AvroEncoder {
+        AvroEncoder::new(SchemaRegistryConfig {
+            url: url.into(),
+            subject_strategy,
+            ..SchemaRegistryConfig::default()
+        })
+        .unwrap()
+    }
+
+    fn error_doc(error_code: Value) -> Value {
+        json!({
+            "level": "ERROR",
+            "message": "disk failed",
+            "hostname": "host-a",
+            "timestamp": "2024-01-02T03:04:05Z",
+            "error_code": error_code,
+            "fields": { "attempt": 3, "path": "/var" },
+        })
+    }
+
+    #[tokio::test]
+    async fn encodes_in_the_wire_format() {
+        let (url, registrations) = mock_registry().await;
+        let encoder = encoder(&url, SubjectNameStrategy::TopicName);
+
+        let payload = encoder
+            .encode("logs.error", LogType::Error, &error_doc(json!(500)))
+            .await
+            .unwrap();
+        assert_eq!(payload[0], MAGIC_BYTE);
+        assert_eq!(u32::from_be_bytes(payload[1..5].try_into().unwrap()), 100);
+
+        let schema = Schema::parse_str(ERROR_LOG_SCHEMA).unwrap();
+        let AvroValue::Record(fields) = GenericDatumReader::builder(&schema)
+            .build()
+            .unwrap()
+            .read_value(&mut &payload[5..])
+            .unwrap()
+        else {
+            panic!("expected a record");
+        };
+        let fields: HashMap<_, _> = fields.into_iter().collect();
+        assert_eq!(fields["message"], AvroValue::String("disk failed".into()));
+        assert_eq!(fields["error_code"], AvroValue::Long(500));
+        assert_eq!(
+            fields["target"],
+            AvroValue::Union(0, Box::new(AvroValue::Null))
+        );
+        assert_eq!(
+            fields["fields"],
+            AvroValue::Map(HashMap::from([
+                ("attempt".to_string(), AvroValue::String("3".into())),
+                ("path".to_string(), AvroValue::String("/var".into())),
+            ]))
+        );
+
+        let registrations = registrations.lock().unwrap();
+        assert_eq!(registrations.len(), 1);
+        assert_eq!(registrations[0].0, "logs.error-value");
+        assert_eq!(registrations[0].1, ERROR_LOG_SCHEMA);
+    }
+
+    #[tokio::test]
+    async fn schema_ids_are_cached_per_subject() {
+        let (url, registrations) = mock_registry().await;
+        let encoder = encoder(&url, SubjectNameStrategy::TopicName);
+        let doc = error_doc(json!(1));
+
+        for _ in 0..3 {
+            encoder
+                .encode("logs.error", LogType::Error, &doc)
+                .await
+                .unwrap();
+        }
+        let other = encoder
+            .encode("logs.audit", LogType::Error, &doc)
+            .await
+            .unwrap();
+        assert_eq!(u32::from_be_bytes(other[1..5].try_into().unwrap()), 101);
+
+        let subjects: Vec<_> = registrations
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(subject, _, _)| subject.clone())
+            .collect();
+        assert_eq!(subjects, ["logs.error-value", "logs.audit-value"]);
+    }
+
+    #[tokio::test]
+    async fn registration_sends_basic_auth() {
+        let (url, registrations) = mock_registry().await;
+        let encoder = AvroEncoder::new(SchemaRegistryConfig {
+            url,
+            username: Some("user".into()),
+            password: Some("secret".into()),
+            ..SchemaRegistryConfig::default()
+        })
+        .unwrap();
+        encoder
+            .encode("logs.error", LogType::Error, &error_doc(json!(1)))
+            .await
+            .unwrap();
+        // base64("user:secret")
+        assert_eq!(
+            registrations.lock().unwrap()[0].2.as_deref(),
+            Some("Basic dXNlcjpzZWNyZXQ=")
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_registrations_fail_the_encoding() {
+        let (url, registrations) = mock_registry().await;
+        let encoder = encoder(&url, SubjectNameStrategy::TopicName);
+        let err = encoder
+            .encode("rejected", LogType::Error, &error_doc(json!(1)))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("409"), "{}", err);
+        assert!(registrations.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn subjects_follow_the_strategy() {
+        let subject =
+            |strategy| encoder("http://registry", strategy).subject("logs.warn", LogType::Warn);
+        assert_eq!(subject(SubjectNameStrategy::TopicName), "logs.warn-value");
+        assert_eq!(
+            subject(SubjectNameStrategy::RecordName),
+            "kafka_app.logs.WarnLog"
+        );
+        assert_eq!(
+            subject(SubjectNameStrategy::TopicRecordName),
+            "logs.warn-kafka_app.logs.WarnLog"
+        );
+    }
+
+    #[test]
+    fn error_codes_out_of_range_are_rejected() {
+        let schema = Schema::parse_str(ERROR_LOG_SCHEMA).unwrap();
+        assert!(to_avro(&error_doc(json!(i64::MAX)), &schema).is_ok());
+        assert!(to_avro(&error_doc(json!(-1)), &schema).is_ok());
+        let err = to_avro(&error_doc(json!(u64::MAX)), &schema).unwrap_err();
+        assert!(err.to_string().contains("out of range"), "{}", err);
+        assert!(to_avro(&error_doc(json!("500")), &schema).is_err());
+    }
+
+    fn decoder(url: &str) -> AvroDecoder {
+        AvroDecoder::new(SchemaRegistryConfig {
+            url: url.into(),
+            ..SchemaRegistryConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn decodes_what_was_encoded() {
+        let (url, _) = mock_registry().await;
+        let encoder = encoder(&url, SubjectNameStrategy::TopicName);
+        let decoder = decoder(&url);
+
+        let payload = encoder
+            .encode("logs.error", LogType::Error, &error_doc(json!(500)))
+            .await
+            .unwrap();
+        assert_eq!(
+            decoder.decode(&payload).await.unwrap(),
+            json!({
+                "level": "ERROR",
+                "message": "disk failed",
+                "hostname": "host-a",
+                "timestamp": "2024-01-02T03:04:05Z",
+                "error_code": 500,
+                "spans": [],
+                "fields": { "attempt": "3", "path": "/var" },
+            })
+        );
+
+        let warn = json!({
+            "level": "WARN",
+            "message": "slow",
+            "hostname": "host-a",
+            "timestamp": "2024-01-02T03:04:05Z",
+            "reason": "p99",
+            "target": "app::db",
+        });
+        let payload = encoder
+            .encode("logs.warn", LogType::Warn, &warn)
+            .await
+            .unwrap();
+        let decoded = decoder.decode(&payload).await.unwrap();
+        assert_eq!(decoded["reason"], "p99");
+        assert_eq!(decoded["target"], "app::db");
+    }
+
+    #[tokio::test]
+    async fn writer_schemas_are_cached_by_id() {
+        let (url, registrations) = mock_registry().await;
+        let payload = encoder(&url, SubjectNameStrategy::TopicName)
+            .encode("logs.error", LogType::Error, &error_doc(json!(1)))
+            .await
+            .unwrap();
+        let decoder = decoder(&url);
+        decoder.decode(&payload).await.unwrap();
+
+        // The registry forgets the schema; the decoder does not need it again.
+        registrations.lock().unwrap().clear();
+        decoder.decode(&payload).await.unwrap();
+        assert_eq!(decoder.schemas.read().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn payloads_that_cannot_be_decoded_are_errors() {
+        let (url, _) = mock_registry().await;
+        let decoder = decoder(&url);
+
+        assert!(decoder.decode(b"{}").await.is_err());
+        assert!(decoder.decode(&[MAGIC_BYTE, 0, 0]).await.is_err());
+        let err = decoder
+            .decode(&[MAGIC_BYTE, 0, 0, 0, 7, 2])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("404"), "{}", err);
+
+        let payload = encoder(&url, SubjectNameStrategy::TopicName)
+            .encode("logs.error", LogType::Error, &error_doc(json!(1)))
+            .await
+            .unwrap();
+        assert!(decoder.decode(&payload[..payload.len() - 3]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn payload_decoder_needs_a_registry_for_avro() {
+        let (url, _) = mock_registry().await;
+        let payload = encoder(&url, SubjectNameStrategy::TopicName)
+            .encode("logs.error", LogType::Error, &error_doc(json!(1)))
+            .await
+            .unwrap();
+        let content_type = Some(crate::encoding::AVRO_CONTENT_TYPE);
+
+        let without = crate::encoding::PayloadDecoder::new(None).unwrap();
+        assert!(without.decode(content_type, &payload).await.is_err());
+        assert_eq!(
+            without.decode(None, b"{\"a\":1}").await.unwrap(),
+            json!({"a": 1})
+        );
+
+        let with = crate::encoding::PayloadDecoder::new(Some(SchemaRegistryConfig {
+            url,
+            ..SchemaRegistryConfig::default()
+        }))
+        .unwrap();
+        let doc = with.decode(content_type, &payload).await.unwrap();
+        assert_eq!(doc["error_code"], 1);
+    }
+}
@@ -0,0 +1,539 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::producer::FutureRecord;
+use rdkafka::producer::future_producer::DeliveryFuture;
+use serde::Deserialize;
+use tokio::time::sleep;
+
+use crate::models::LogType;
+use crate::producer::InnerProducer;
+
+/// How long to wait before retrying a send while librdkafka's queue is full.
+const QUEUE_FULL_BACKOFF: Duration = Duration::from_millis(10);
+
+/// What a send does when librdkafka's local queue is full.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BackpressurePolicy {
+    /// Waits for room until [`BackpressureConfig::block_ms`] has passed, then fails.
+    #[default]
+    Block,
+    /// Waits like `Block`, but at most [`BackpressureConfig::max_waiting`] logs of the type wait
+    /// at once. When one more arrives, the log that has waited longest is dropped.
+    DropOldest,
+    /// The log is dropped at once.
+    DropNewest,
+    /// The record is written to the spool and replayed later. Needs a spool.
+    Spill,
+}
+
+impl BackpressurePolicy {
+    pub fn parse(raw: &str) -> anyhow::Result<Self> {
+        match raw.to_ascii_lowercase().replace('-', "_").as_str() {
+            "block" => Ok(BackpressurePolicy::Block),
+            "drop_oldest" => Ok(BackpressurePolicy::DropOldest),
+            "drop_newest" => Ok(BackpressurePolicy::DropNewest),
+            "spill" => Ok(BackpressurePolicy::Spill),
+            other => anyhow::bail!("unsupported backpressure policy {:?}", other),
+        }
+    }
+}
+
+/// The `[backpressure]` section of the config file. Does not apply to exactly-once sends,
+/// which always block.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct BackpressureConfig {
+    pub default: BackpressurePolicy,
+    /// Per log type overrides of `default`, e.g. `{ error = "block", info = "drop_newest" }`.
+    pub log_types: HashMap<LogType, BackpressurePolicy>,
+    /// How long `block` and `drop_oldest` wait for room. Defaults to the send timeout.
+    pub block_ms: Option<u64>,
+    /// Logs of one type waiting for room at once under `drop_oldest`.
+    pub max_waiting: usize,
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+}
+
+impl Default for BackpressureConfig {
+    fn default() -> Self {
+        Self {
+            default: BackpressurePolicy::default(),
+            log_types: HashMap::new(),
+            block_ms: None,
+            max_waiting: 1000,
+            circuit_breaker: None,
+        }
+    }
+}
+
+impl BackpressureConfig {
+    pub fn policy_for(&self, log_type: LogType) -> BackpressurePolicy {
+        self.log_types
+            .get(&log_type)
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    pub fn spills(&self) -> bool {
+        self.default == BackpressurePolicy::Spill
+            || self
+                .log_types
+                .values()
+                .any(|policy| *policy == BackpressurePolicy::Spill)
+    }
+}
+
+/// Fails sends fast once the broker keeps failing, instead of letting each one wait out its
+/// timeout. After `open_ms` one send is let through as a probe; the circuit closes again when
+/// a delivery succeeds. With a spool, logs are spooled while the circuit is open.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failed deliveries that open the circuit. Errors specific to a record, like
+    /// one that is too large, do not count.
+    pub failure_threshold: u32,
+    pub open_ms: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_ms: 30_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CircuitState {
+    #[default]
+    Closed,
+    Open,
+    /// A probe was let through and its delivery has not been reported yet.
+    HalfOpen,
+}
+
+/// Counters of a producer's backpressure since it was created.
+#[derive(Debug, Default, Clone)]
+pub struct BackpressureMetrics {
+    pub dropped_oldest: u64,
+    pub dropped_newest: u64,
+    pub spilled: u64,
+    /// Sends that failed because the queue stayed full for the whole wait.
+    pub block_timeouts: u64,
+    /// `None` without a circuit breaker.
+    pub circuit: Option<CircuitState>,
+    pub circuit_opened: u64,
+    /// Sends refused while the circuit was open.
+    pub fast_failed: u64,
+}
+
+/// Why a record was not handed to librdkafka.
+pub(crate) enum Refused {
+    /// Dropped by `drop_oldest` or `drop_newest`.
+    Dropped,
+    /// To be written to the spool.
+    Spill,
+    CircuitOpen,
+    Failed(KafkaError),
+}
+
+#[derive(Default)]
+struct Breaker {
+    consecutive_failures: u32,
+    /// When the circuit opened, or when the last probe was let through.
+    opened_at: Option<Instant>,
+    probing: bool,
+}
+
+/// Applies a [`BackpressureConfig`] to the records a [`crate::producer::KafkaProducer`] sends.
+pub(crate) struct Backpressure {
+    cfg: BackpressureConfig,
+    /// Under `drop_oldest`, the logs of each type waiting for room, oldest first. A waiter is
+    /// dropped by setting its flag.
+    waiting: Mutex<HashMap<LogType, VecDeque<Arc<AtomicBool>>>>,
+    breaker: Mutex<Breaker>,
+    dropped_oldest: AtomicU64,
+    dropped_newest: AtomicU64,
+    spilled: AtomicU64,
+    block_timeouts: AtomicU64,
+    circuit_opened: AtomicU64,
+    fast_failed: AtomicU64,
+}
+
+impl Backpressure {
+    pub fn new(cfg: &BackpressureConfig) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            cfg.max_waiting > 0,
+            "backpressure max_waiting must be positive"
+        );
+        if let Some(breaker) = &cfg.circuit_breaker {
+            anyhow::ensure!(
+                breaker.failure_threshold > 0,
+                "circuit breaker failure_threshold must be positive"
+            );
+        }
+        Ok(Self {
+            cfg: cfg.clone(),
+            waiting: Mutex::new(HashMap::new()),
+            breaker: Mutex::new(Breaker::default()),
+            dropped_oldest: AtomicU64::new(0),
+            dropped_newest: AtomicU64::new(0),
+            spilled: AtomicU64::new(0),
+            block_timeouts: AtomicU64::new(0),
+            circuit_opened: AtomicU64::new(0),
+            fast_failed: AtomicU64::new(0),
+        })
+    }
+
+    /// Hands the record to librdkafka, applying the log type's policy while its queue is full.
+    /// `send_timeout` is how long to wait for room unless `block_ms` is set.
+    pub async fn enqueue(
+        &self,
+        producer: &InnerProducer,
+        mut record: FutureRecord<'_, Vec<u8>, Vec<u8>>,
+        log_type: LogType,
+        send_timeout: Duration,
+    ) -> Result<DeliveryFuture, Refused> {
+        if !self.allow() {
+            self.fast_failed.fetch_add(1, Ordering::Relaxed);
+            return Err(Refused::CircuitOpen);
+        }
+        let policy = self.cfg.policy_for(log_type);
+        let deadline = Instant::now()
+            + self
+                .cfg
+                .block_ms
+                .map_or(send_timeout, Duration::from_millis);
+        let mut waiter = None;
+        let result = loop {
+            let err = match producer.send_result(record) {
+                Ok(delivery) => break Ok(delivery),
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), returned)) => {
+                    record = returned;
+                    KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull)
+                }
+                Err((err, _)) => break Err(Refused::Failed(err)),
+            };
+            match policy {
+                BackpressurePolicy::DropNewest => {
+                    self.dropped_newest.fetch_add(1, Ordering::Relaxed);
+                    break Err(Refused::Dropped);
+                }
+                BackpressurePolicy::Spill => {
+                    self.spilled.fetch_add(1, Ordering::Relaxed);
+                    break Err(Refused::Spill);
+                }
+                BackpressurePolicy::DropOldest => {
+                    let waiter = waiter.get_or_insert_with(|| self.wait_in_line(log_type));
+                    if waiter.load(Ordering::SeqCst) {
+                        break Err(Refused::Dropped);
+                    }
+                }
+                BackpressurePolicy::Block => {}
+            }
+            if Instant::now() >= deadline {
+                self.block_timeouts.fetch_add(1, Ordering::Relaxed);
+                break Err(Refused::Failed(err));
+            }
+            sleep(QUEUE_FULL_BACKOFF).await;
+        };
+        if let Some(waiter) = waiter {
+            self.leave_line(log_type, &waiter);
+        }
+        result
+    }
+
+    /// Joins the line of logs of the type waiting for room, dropping the oldest when it is full.
+    fn wait_in_line(&self, log_type: LogType) -> Arc<AtomicBool> {
+        let waiter = Arc::new(AtomicBool::new(false));
+        let mut waiting = self.waiting.lock().unwrap_or_else(|e| e.into_inner());
+        let line = waiting.entry(log_type).or_default();
+        if line.len() >= self.cfg.max_waiting
+            && let Some(oldest) = line.pop_front()
+        {
+            oldest.store(true, Ordering::SeqCst);
+            self.dropped_oldest.fetch_add(1, Ordering::Relaxed);
+        }
+        line.push_back(waiter.clone());
+        waiter
+    }
+
+    fn leave_line(&self, log_type: LogType, waiter: &Arc<AtomicBool>) {
+        let mut waiting = self.waiting.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(line) = waiting.get_mut(&log_type) {
+            line.retain(|other| !Arc::ptr_eq(other, waiter));
+        }
+    }
+
+    /// Whether a send may go ahead. While the circuit is open, one send per `open_ms` is let
+    /// through as a probe.
+    fn allow(&self) -> bool {
+        let Some(cfg) = &self.cfg.circuit_breaker else {
+            return true;
+        };
+        let mut breaker = self.breaker.lock().unwrap_or_else(|e| e.into_inner());
+        match breaker.opened_at {
+            None => true,
+            Some(at) if at.elapsed() >= Duration::from_millis(cfg.open_ms) => {
+                breaker.opened_at = Some(Instant::now());
+                breaker.probing = true;
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    /// Reports a delivery the broker acknowledged. Closes the circuit.
+    pub fn delivered(&self) {
+        if self.cfg.circuit_breaker.is_none() {
+            return;
+        }
+        *self.breaker.lock().unwrap_or_else(|e| e.into_inner()) = Breaker::default();
+    }
+
+    /// Reports a delivery that failed because of the broker or the connection to it.
+    pub fn broker_failed(&self) {
+        let Some(cfg) = &self.cfg.circuit_breaker else {
+            return;
+        };
+        let mut breaker = self.breaker.lock().unwrap_or_else(|e| e.into_inner());
+        breaker.consecutive_failures += 1;
+        if breaker.probing {
+            breaker.probing = false;
+            breaker.opened_at = Some(Instant::now());
+        } else if breaker.opened_at.is_none()
+            && breaker.consecutive_failures >= cfg.failure_threshold
+        {
+            breaker.opened_at = Some(Instant::now());
+            self.circuit_opened.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn metrics(&self) -> BackpressureMetrics {
+        let circuit = self.cfg.circuit_breaker.as_ref().map(|_| {
+            let breaker = self.breaker.lock().unwrap_or_else(|e| e.into_inner());
+            match (breaker.opened_at, breaker.probing) {
+                (None, _) => CircuitState::Closed,
+                (Some(_), true) => CircuitState::HalfOpen,
+                (Some(_), false) => CircuitState::Open,
+            }
+        });
+        BackpressureMetrics {
+            dropped_oldest: self.dropped_oldest.load(Ordering::Relaxed),
+            dropped_newest: self.dropped_newest.load(Ordering::Relaxed),
+            spilled: self.spilled.load(Ordering::Relaxed),
+            block_timeouts: self.block_timeouts.load(Ordering::Relaxed),
+            circuit,
+            circuit_opened: self.circuit_opened.load(Ordering::Relaxed),
+            fast_failed: self.fast_failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rdkafka::ClientConfig;
+
+    use super::*;
+    use crate::metrics::StatsContext;
+
+    const OPEN_MS: u64 = 50;
+
+    fn breaker(threshold: u32) -> Backpressure {
+        Backpressure::new(&BackpressureConfig {
+            circuit_breaker: Some(CircuitBreakerConfig {
+                failure_threshold: threshold,
+                open_ms: OPEN_MS,
+            }),
+            ..BackpressureConfig::default()
+        })
+        .unwrap()
+    }
+
+    fn circuit(backpressure: &Backpressure) -> CircuitState {
+        backpressure.metrics().circuit.unwrap()
+    }
+
+    /// A producer with room for a single message, which it already holds: there is no broker to
+    /// deliver it to.
+    fn full_producer() -> InnerProducer {
+        let producer: InnerProducer = ClientConfig::new()
+            .set("bootstrap.servers", "127.0.0.1:1")
+            .set("queue.buffering.max.messages", "1")
+            .set("message.timeout.ms", "30000")
+            .create_with_context(StatsContext::default())
+            .unwrap();
+        producer
+            .send_result(FutureRecord::<(), _>::to("logs").payload("first"))
+            .map_err(|(err, _)| err)
+            .unwrap();
+        producer
+    }
+
+    fn policy(policy: BackpressurePolicy, block_ms: u64, max_waiting: usize) -> Backpressure {
+        Backpressure::new(&BackpressureConfig {
+            default: policy,
+            block_ms: Some(block_ms),
+            max_waiting,
+            ..BackpressureConfig::default()
+        })
+        .unwrap()
+    }
+
+    async fn enqueue(
+        backpressure: &Backpressure,
+        producer: &InnerProducer,
+    ) -> Result<DeliveryFuture, Refused> {
+        let payload = b"log".to_vec();
+        let record = FutureRecord::to("logs").payload(&payload);
+        backpressure
+            .enqueue(producer, record, LogType::Info, Duration::from_secs(5))
+            .await
+    }
+
+    #[test]
+    fn the_circuit_opens_at_the_threshold() {
+        let backpressure = breaker(3);
+        backpressure.broker_failed();
+        backpressure.broker_failed();
+        assert!(backpressure.allow());
+        assert_eq!(circuit(&backpressure), CircuitState::Closed);
+
+        backpressure.broker_failed();
+        assert_eq!(circuit(&backpressure), CircuitState::Open);
+        assert!(!backpressure.allow());
+        assert_eq!(backpressure.metrics().circuit_opened, 1);
+    }
+
+    #[test]
+    fn a_success_resets_the_failure_count() {
+        let backpressure = breaker(2);
+        backpressure.broker_failed();
+        backpressure.delivered();
+        backpressure.broker_failed();
+        assert_eq!(circuit(&backpressure), CircuitState::Closed);
+    }
+
+    #[test]
+    fn one_probe_goes_through_after_open_ms() {
+        let backpressure = breaker(1);
+        backpressure.broker_failed();
+        assert!(!backpressure.allow());
+
+        std::thread::sleep(Duration::from_millis(OPEN_MS + 10));
+        assert!(backpressure.allow());
+        assert_eq!(circuit(&backpressure), CircuitState::HalfOpen);
+        // Only one probe at a time.
+        assert!(!backpressure.allow());
+    }
+
+    #[test]
+    fn a_successful_probe_closes_the_circuit() {
+        let backpressure = breaker(1);
+        backpressure.broker_failed();
+        std::thread::sleep(Duration::from_millis(OPEN_MS + 10));
+        assert!(backpressure.allow());
+
+        backpressure.delivered();
+        assert_eq!(circuit(&backpressure), CircuitState::Closed);
+        assert!(backpressure.allow());
+    }
+
+    #[test]
+    fn a_failed_probe_opens_the_circuit_again() {
+        let backpressure = breaker(1);
+        backpressure.broker_failed();
+        std::thread::sleep(Duration::from_millis(OPEN_MS + 10));
+        assert!(backpressure.allow());
+
+        backpressure.broker_failed();
+        assert_eq!(circuit(&backpressure), CircuitState::Open);
+        assert!(!backpressure.allow());
+        // The next probe waits a full `open_ms` again.
+        std::thread::sleep(Duration::from_millis(OPEN_MS + 10));
+        assert!(backpressure.allow());
+    }
+
+    #[tokio::test]
+    async fn an_open_circuit_fails_sends_fast() {
+        let backpressure = breaker(1);
+        backpressure.broker_failed();
+        let producer = full_producer();
+        assert!(matches!(
+            enqueue(&backpressure, &producer).await,
+            Err(Refused::CircuitOpen)
+        ));
+        assert_eq!(backpressure.metrics().fast_failed, 1);
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_room_then_fails() {
+        let backpressure = policy(BackpressurePolicy::Block, 100, 1000);
+        let producer = full_producer();
+        let started = Instant::now();
+        assert!(matches!(
+            enqueue(&backpressure, &producer).await,
+            Err(Refused::Failed(KafkaError::MessageProduction(
+                RDKafkaErrorCode::QueueFull
+            )))
+        ));
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert_eq!(backpressure.metrics().block_timeouts, 1);
+    }
+
+    #[tokio::test]
+    async fn drop_newest_drops_at_once() {
+        let backpressure = policy(BackpressurePolicy::DropNewest, 5000, 1000);
+        let producer = full_producer();
+        let started = Instant::now();
+        assert!(matches!(
+            enqueue(&backpressure, &producer).await,
+            Err(Refused::Dropped)
+        ));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(backpressure.metrics().dropped_newest, 1);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_drops_the_longest_waiting_log() {
+        let backpressure = policy(BackpressurePolicy::DropOldest, 300, 1);
+        let producer = full_producer();
+        let (oldest, newest) = tokio::join!(enqueue(&backpressure, &producer), async {
+            sleep(Duration::from_millis(50)).await;
+            enqueue(&backpressure, &producer).await
+        });
+        assert!(matches!(oldest, Err(Refused::Dropped)));
+        // The newer log kept waiting until the block timeout.
+        assert!(matches!(newest, Err(Refused::Failed(_))));
+        let metrics = backpressure.metrics();
+        assert_eq!(metrics.dropped_oldest, 1);
+        assert_eq!(metrics.block_timeouts, 1);
+    }
+
+    #[tokio::test]
+    async fn spill_hands_the_record_to_the_spool() {
+        let backpressure = policy(BackpressurePolicy::Spill, 5000, 1000);
+        let producer = full_producer();
+        assert!(matches!(
+            enqueue(&backpressure, &producer).await,
+            Err(Refused::Spill)
+        ));
+        assert_eq!(backpressure.metrics().spilled, 1);
+    }
+
+    #[tokio::test]
+    async fn policies_only_apply_when_the_queue_is_full() {
+        let backpressure = policy(BackpressurePolicy::DropNewest, 5000, 1000);
+        let producer: InnerProducer = ClientConfig::new()
+            .set("bootstrap.servers", "127.0.0.1:1")
+            .create_with_context(StatsContext::default())
+            .unwrap();
+        assert!(enqueue(&backpressure, &producer).await.is_ok());
+        assert_eq!(backpressure.metrics().dropped_newest, 0);
+    }
+}
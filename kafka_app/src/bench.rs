@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::{bail, ensure};
+use chrono::Utc;
+use clap::Args;
+use kafka_app::helper::get_hostname;
+use kafka_app::{ErrorLog, InfoLog, KafkaProducer, LogRecord, LogType, Sent, WarnLog};
+use tokio::time::{Instant, sleep_until};
+
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    /// Target messages per second across all workers. Unlimited when not set.
+    #[arg(long)]
+    pub rate: Option<f64>,
+    /// How long to generate load for, in seconds.
+    #[arg(long, default_value_t = 10)]
+    pub duration: u64,
+    /// Relative weight of each log type, e.g. `info=70,warn=20,error=10`.
+    #[arg(long, default_value = "info=1,warn=1,error=1", value_parser = parse_mix)]
+    pub mix: Mix,
+    /// Message size in bytes, either fixed (`256`) or a range (`128-2048`).
+    #[arg(long, default_value = "256", value_parser = parse_size)]
+    pub payload_size: PayloadSize,
+    /// Number of sends awaited at once.
+    #[arg(long, default_value_t = 100)]
+    pub concurrency: usize,
+    /// Overrides the configured compression codec, to compare codecs on the same load.
+    #[arg(long)]
+    pub compression: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Mix(Vec<(LogType, u32)>);
+
+impl Mix {
+    fn pick(&self, roll: u64) -> LogType {
+        let total: u64 = self.0.iter().map(|(_, weight)| *weight as u64).sum();
+        let mut roll = roll % total;
+        for (log_type, weight) in &self.0 {
+            if roll < *weight as u64 {
+                return *log_type;
+            }
+            roll -= *weight as u64;
+        }
+        LogType::Info
+    }
+}
+
+fn parse_mix(raw: &str) -> anyhow::Result<Mix> {
+    let mut weights = Vec::new();
+    for part in raw.split(',') {
+        let Some((name, weight)) = part.split_once('=') else {
+            bail!("expected <type>=<weight>, got {:?}", part);
+        };
+        let log_type = match name.trim() {
+            "info" => LogType::Info,
+            "warn" => LogType::Warn,
+            "error" => LogType::Error,
+            other => bail!("unknown log type {:?}", other),
+        };
+        weights.push((log_type, weight.trim().parse()?));
+    }
+    ensure!(
+        weights.iter().any(|(_, weight)| *weight > 0),
+        "at least one log type needs a weight above zero"
+    );
+    Ok(Mix(weights))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PayloadSize {
+    min: usize,
+    max: usize,
+}
+
+impl PayloadSize {
+    fn pick(&self, roll: u64) -> usize {
+        self.min + (roll % (self.max - self.min + 1) as u64) as usize
+    }
+}
+
+fn parse_size(raw: &str) -> anyhow::Result<PayloadSize> {
+    let (min, max) = match raw.split_once('-') {
+        Some((min, max)) => (min.trim().parse()?, max.trim().parse()?),
+        None => {
+            let size = raw.trim().parse()?;
+            (size, size)
+        }
+    };
+    ensure!(min <= max, "payload size range {} is empty", raw);
+    Ok(PayloadSize { min, max })
+}
+
+/// What one worker saw. Merged into the final report.
+#[derive(Default)]
+struct WorkerStats {
+    latencies_us: Vec<u64>,
+    bytes: u64,
+    spooled: u64,
+    suppressed: u64,
+    dropped: u64,
+    errors: HashMap<String, u64>,
+}
+
+impl WorkerStats {
+    fn merge(&mut self, other: WorkerStats) {
+        self.latencies_us.extend(other.latencies_us);
+        self.bytes += other.bytes;
+        self.spooled += other.spooled;
+        self.suppressed += other.suppressed;
+        self.dropped += other.dropped;
+        for (error, count) in other.errors {
+            *self.errors.entry(error).or_default() += count;
+        }
+    }
+}
+
+/// Generates load with `args` and prints throughput, latency percentiles and errors.
+pub async fn run(
+    producer: Arc<KafkaProducer>,
+    args: BenchArgs,
+    send_timeout: Duration,
+) -> anyhow::Result<()> {
+    ensure!(args.concurrency > 0, "concurrency must be at least 1");
+    if let Some(rate) = args.rate {
+        ensure!(rate > 0.0, "rate must be positive");
+    }
+    let args = Arc::new(args);
+    let hostname = Arc::new(get_hostname());
+    let next = Arc::new(AtomicU64::new(0));
+    let start = Instant::now();
+    let end = start + Duration::from_secs(args.duration);
+
+    let workers: Vec<_> = (0..args.concurrency)
+        .map(|_| {
+            let producer = producer.clone();
+            let args = args.clone();
+            let hostname = hostname.clone();
+            let next = next.clone();
+            tokio::spawn(async move {
+                let mut stats = WorkerStats::default();
+                loop {
+                    let seq = next.fetch_add(1, Ordering::Relaxed);
+                    // With a target rate every message has a slot; without one, send until the end.
+                    let due = match args.rate {
+                        Some(rate) => start + Duration::from_secs_f64(seq as f64 / rate),
+                        None => Instant::now(),
+                    };
+                    // Slots the workers fell behind on are dropped once the time is up.
+                    if due >= end || Instant::now() >= end {
+                        break;
+                    }
+                    sleep_until(due).await;
+
+                    let record = generate(&args, &hostname, seq);
+                    let bytes = serde_json::to_vec(&record).map_or(0, |json| json.len() as u64);
+                    let sent_at = Instant::now();
+                    match producer.send(record, &send_timeout).await {
+                        Ok(Sent::Delivered) => {
+                            stats
+                                .latencies_us
+                                .push(sent_at.elapsed().as_micros() as u64);
+                            stats.bytes += bytes;
+                        }
+                        Ok(Sent::Spooled) => stats.spooled += 1,
+                        Ok(Sent::Suppressed) => stats.suppressed += 1,
+                        Ok(Sent::Dropped) => stats.dropped += 1,
+                        Err(e) => *stats.errors.entry(e.to_string()).or_default() += 1,
+                    }
+                }
+                stats
+            })
+        })
+        .collect();
+
+    let mut stats = WorkerStats::default();
+    for worker in workers {
+        stats.merge(worker.await?);
+    }
+    let elapsed = start.elapsed();
+    print_report(&args, stats, elapsed);
+    Ok(())
+}
+
+fn generate(args: &BenchArgs, hostname: &str, seq: u64) -> LogRecord {
+    let roll = splitmix(seq);
+    let size = args.payload_size.pick(roll >> 32);
+    let mut message = format!("bench #{} ", seq);
+    message.extend(std::iter::repeat_n('x', size.saturating_sub(message.len())));
+    message.truncate(size);
+
+    match args.mix.pick(roll) {
+        LogType::Info => InfoLog::new("INFO".into(), message, hostname.into(), Utc::now()).into(),
+        LogType::Warn => WarnLog::new(
+            "WARN".into(),
+            message,
+            hostname.into(),
+            Utc::now(),
+            "benchmark".into(),
+        )
+        .into(),
+        LogType::Error => ErrorLog::new(
+            "ERROR".into(),
+            message,
+            hostname.into(),
+            Utc::now(),
+            seq % 600,
+        )
+        .into(),
+    }
+}
+
+/// Cheap deterministic mixing so log types and sizes vary without a random number generator.
+fn splitmix(seq: u64) -> u64 {
+    let mut z = seq.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+fn print_report(args: &BenchArgs, mut stats: WorkerStats, elapsed: Duration) {
+    stats.latencies_us.sort_unstable();
+    let delivered = stats.latencies_us.len() as u64;
+    let failed: u64 = stats.errors.values().sum();
+    let secs = elapsed.as_secs_f64();
+
+    println!("\nBenchmark finished in {:.2?}", elapsed);
+    println!(
+        "  target rate:  {}",
+        args.rate
+            .map(|rate| format!("{:.0} msg/s", rate))
+            .unwrap_or_else(|| "unlimited".into())
+    );
+    println!("  concurrency:  {}", args.concurrency);
+    println!(
+        "  delivered:    {} ({:.2} msg/s)",
+        delivered,
+        delivered as f64 / secs
+    );
+    println!(
+        "  throughput:   {:.2} KiB/s",
+        stats.bytes as f64 / 1024.0 / secs
+    );
+    println!("  spooled:      {}", stats.spooled);
+    println!("  suppressed:   {}", stats.suppressed);
+    println!("  dropped:      {}", stats.dropped);
+    println!("  failed:       {}", failed);
+    if !stats.latencies_us.is_empty() {
+        println!(
+            "  latency:      p50 {:.2?}  p90 {:.2?}  p99 {:.2?}  max {:.2?}",
+            percentile(&stats.latencies_us, 50.0),
+            percentile(&stats.latencies_us, 90.0),
+            percentile(&stats.latencies_us, 99.0),
+            percentile(&stats.latencies_us, 100.0),
+        );
+    }
+    for (error, count) in &stats.errors {
+        println!("  error x{}: {}", count, error);
+    }
+}
+
+/// Nearest-rank percentile of sorted latencies.
+fn percentile(sorted_us: &[u64], pct: f64) -> Duration {
+    let rank = ((pct / 100.0) * sorted_us.len() as f64).ceil() as usize;
+    Duration::from_micros(sorted_us[rank.clamp(1, sorted_us.len()) - 1])
+}
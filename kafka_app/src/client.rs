@@ -0,0 +1,483 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure};
+use rdkafka::ClientConfig;
+use serde::Deserialize;
+
+const COMPRESSION_CODECS: [&str; 5] = ["none", "gzip", "snappy", "lz4", "zstd"];
+const ACKS: [&str; 4] = ["0", "1", "all", "-1"];
+/// Parts of a property name that mark its value as a credential, e.g. `sasl.password`,
+/// `ssl.key.pem` or `sasl.oauthbearer.client.secret`.
+const SECRET_PROPERTY_PARTS: [&str; 6] = [
+    "password",
+    "secret",
+    "token",
+    "key",
+    "jaas",
+    "oauthbearer.config",
+];
+
+/// What happens to a log when the producer retries or a broker fails over.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeliveryGuarantee {
+    /// Never retried, so a log is lost rather than written twice.
+    AtMostOnce,
+    /// `acks=all` with idempotence: retries cannot duplicate or reorder logs.
+    AtLeastOnce,
+    /// Idempotent and transactional. Logs are only visible to `read_committed` consumers once
+    /// their transaction commits. Needs `transactional_id`.
+    ExactlyOnce,
+}
+
+impl DeliveryGuarantee {
+    pub fn parse(raw: &str) -> anyhow::Result<Self> {
+        match raw.to_ascii_lowercase().replace('_', "-").as_str() {
+            "at-most-once" => Ok(DeliveryGuarantee::AtMostOnce),
+            "at-least-once" => Ok(DeliveryGuarantee::AtLeastOnce),
+            "exactly-once" => Ok(DeliveryGuarantee::ExactlyOnce),
+            other => bail!("unsupported delivery guarantee {:?}", other),
+        }
+    }
+}
+
+/// librdkafka producer settings. `properties` is applied last, so any librdkafka property can be
+/// set (or overridden) there by its dotted name.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ClientSettings {
+    pub acks: String,
+    pub linger_ms: u64,
+    pub batch_size: u64,
+    pub compression: String,
+    /// Overrides `acks` and the retry settings when set.
+    pub guarantee: Option<DeliveryGuarantee>,
+    /// Required by [`DeliveryGuarantee::ExactlyOnce`]. Must be unique per producer instance.
+    pub transactional_id: Option<String>,
+    pub properties: BTreeMap<String, String>,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        Self {
+            acks: "1".into(),
+            linger_ms: 100,     // Wait up to 100ms to accumulate messages
+            batch_size: 131072, // Max batch size 128KB
+            compression: "snappy".into(),
+            guarantee: None,
+            transactional_id: None,
+            properties: BTreeMap::new(),
+        }
+    }
+}
+
+impl fmt::Debug for ClientSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let properties: BTreeMap<&str, &str> = self
+            .properties
+            .iter()
+            .map(|(name, value)| {
+                let value = if is_secret_property(name) {
+                    "<redacted>"
+                } else {
+                    value.as_str()
+                };
+                (name.as_str(), value)
+            })
+            .collect();
+        f.debug_struct("ClientSettings")
+            .field("acks", &self.acks)
+            .field("linger_ms", &self.linger_ms)
+            .field("batch_size", &self.batch_size)
+            .field("compression", &self.compression)
+            .field("guarantee", &self.guarantee)
+            .field("transactional_id", &self.transactional_id)
+            .field("properties", &properties)
+            .finish()
+    }
+}
+
+fn is_secret_property(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SECRET_PROPERTY_PARTS.iter().any(|part| name.contains(part))
+}
+
+impl ClientSettings {
+    pub fn apply(&self, client: &mut ClientConfig) {
+        client
+            .set("acks", &self.acks)
+            .set("linger.ms", self.linger_ms.to_string())
+            .set("batch.size", self.batch_size.to_string())
+            .set("compression.codec", &self.compression);
+        match self.guarantee {
+            None => {}
+            Some(DeliveryGuarantee::AtMostOnce) => {
+                client
+                    .set("enable.idempotence", "false")
+                    .set("message.send.max.retries", "0");
+            }
+            Some(DeliveryGuarantee::AtLeastOnce) => {
+                client.set("acks", "all").set("enable.idempotence", "true");
+            }
+            Some(DeliveryGuarantee::ExactlyOnce) => {
+                client.set("acks", "all").set("enable.idempotence", "true");
+                if let Some(id) = &self.transactional_id {
+                    client.set("transactional.id", id);
+                }
+            }
+        }
+        for (name, value) in &self.properties {
+            client.set(name, value);
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            ACKS.contains(&self.acks.as_str()),
+            "acks must be one of {:?}, got {:?}",
+            ACKS,
+            self.acks
+        );
+        ensure!(
+            COMPRESSION_CODECS.contains(&self.compression.as_str()),
+            "compression must be one of {:?}, got {:?}",
+            COMPRESSION_CODECS,
+            self.compression
+        );
+        if self.guarantee == Some(DeliveryGuarantee::ExactlyOnce) {
+            ensure!(
+                self.transactional_id
+                    .as_deref()
+                    .is_some_and(|id| !id.is_empty()),
+                "the exactly-once delivery guarantee needs a transactional_id"
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct SslConfig {
+    pub ca_location: Option<PathBuf>,
+    pub certificate_location: Option<PathBuf>,
+    pub key_location: Option<PathBuf>,
+    pub key_password: Option<String>,
+    /// Set to `false` to skip broker hostname verification (development only).
+    pub verify_hostname: Option<bool>,
+}
+
+impl fmt::Debug for SslConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SslConfig")
+            .field("ca_location", &self.ca_location)
+            .field("certificate_location", &self.certificate_location)
+            .field("key_location", &self.key_location)
+            .field(
+                "key_password",
+                &self.key_password.as_ref().map(|_| "<redacted>"),
+            )
+            .field("verify_hostname", &self.verify_hostname)
+            .finish()
+    }
+}
+
+impl SslConfig {
+    fn apply(&self, client: &mut ClientConfig) {
+        if let Some(path) = &self.ca_location {
+            client.set("ssl.ca.location", path.display().to_string());
+        }
+        if let Some(path) = &self.certificate_location {
+            client.set("ssl.certificate.location", path.display().to_string());
+        }
+        if let Some(path) = &self.key_location {
+            client.set("ssl.key.location", path.display().to_string());
+        }
+        if let Some(password) = &self.key_password {
+            client.set("ssl.key.password", password);
+        }
+        if self.verify_hostname == Some(false) {
+            client.set("ssl.endpoint.identification.algorithm", "none");
+        }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        for (name, path) in [
+            ("ca_location", &self.ca_location),
+            ("certificate_location", &self.certificate_location),
+            ("key_location", &self.key_location),
+        ] {
+            if let Some(path) = path {
+                ensure_readable(name, path)?;
+            }
+        }
+        ensure!(
+            self.certificate_location.is_some() == self.key_location.is_some(),
+            "ssl certificate_location and key_location must be set together"
+        );
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SaslMechanism {
+    #[default]
+    #[serde(rename = "PLAIN")]
+    Plain,
+    #[serde(rename = "SCRAM-SHA-256")]
+    ScramSha256,
+    #[serde(rename = "SCRAM-SHA-512")]
+    ScramSha512,
+}
+
+impl SaslMechanism {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SaslMechanism::Plain => "PLAIN",
+            SaslMechanism::ScramSha256 => "SCRAM-SHA-256",
+            SaslMechanism::ScramSha512 => "SCRAM-SHA-512",
+        }
+    }
+
+    pub fn parse(raw: &str) -> anyhow::Result<Self> {
+        match raw.to_ascii_uppercase().as_str() {
+            "PLAIN" => Ok(SaslMechanism::Plain),
+            "SCRAM-SHA-256" => Ok(SaslMechanism::ScramSha256),
+            "SCRAM-SHA-512" => Ok(SaslMechanism::ScramSha512),
+            other => bail!("unsupported SASL mechanism {:?}", other),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct SaslConfig {
+    pub mechanism: SaslMechanism,
+    pub username: String,
+    pub password: String,
+}
+
+impl fmt::Debug for SaslConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SaslConfig")
+            .field("mechanism", &self.mechanism)
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+impl SaslConfig {
+    fn apply(&self, client: &mut ClientConfig) {
+        client
+            .set("sasl.mechanism", self.mechanism.as_str())
+            .set("sasl.username", &self.username)
+            .set("sasl.password", &self.password);
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        ensure!(!self.username.is_empty(), "sasl username must be set");
+        ensure!(!self.password.is_empty(), "sasl password must be set");
+        Ok(())
+    }
+}
+
+/// Applies SSL and SASL settings along with the matching `security.protocol`.
+pub fn apply_security(
+    client: &mut ClientConfig,
+    ssl: Option<&SslConfig>,
+    sasl: Option<&SaslConfig>,
+) {
+    let protocol = match (ssl.is_some(), sasl.is_some()) {
+        (false, false) => return,
+        (true, false) => "ssl",
+        (false, true) => "sasl_plaintext",
+        (true, true) => "sasl_ssl",
+    };
+    client.set("security.protocol", protocol);
+    if let Some(ssl) = ssl {
+        ssl.apply(client);
+    }
+    if let Some(sasl) = sasl {
+        sasl.apply(client);
+    }
+}
+
+pub fn validate_security(ssl: Option<&SslConfig>, sasl: Option<&SaslConfig>) -> anyhow::Result<()> {
+    if let Some(ssl) = ssl {
+        ssl.validate()?;
+    }
+    if let Some(sasl) = sasl {
+        sasl.validate()?;
+    }
+    Ok(())
+}
+
+fn ensure_readable(name: &str, path: &Path) -> anyhow::Result<()> {
+    if let Err(e) = std::fs::File::open(path) {
+        bail!("ssl {} {} is not readable: {}", name, path.display(), e);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn applied(settings: &ClientSettings) -> ClientConfig {
+        let mut client = ClientConfig::new();
+        settings.apply(&mut client);
+        client
+    }
+
+    #[test]
+    fn debug_redacts_secret_properties() {
+        let settings = ClientSettings {
+            properties: BTreeMap::from([
+                ("sasl.password".into(), "hunter2".into()),
+                ("sasl.oauthbearer.client.secret".into(), "s3cret".into()),
+                ("SSL.KEY.PEM".into(), "-----BEGIN".into()),
+                ("sasl.jaas.config".into(), "password=\"jaas-pass\"".into()),
+                ("client.id".into(), "shipper-1".into()),
+            ]),
+            ..ClientSettings::default()
+        };
+        let debug = format!("{:?}", settings);
+        for secret in ["hunter2", "s3cret", "-----BEGIN", "jaas-pass"] {
+            assert!(!debug.contains(secret), "{debug}");
+        }
+        assert!(
+            debug.contains("\"sasl.password\": \"<redacted>\""),
+            "{debug}"
+        );
+        assert!(debug.contains("shipper-1"), "{debug}");
+    }
+
+    #[test]
+    fn apply_sets_the_settings_and_lets_properties_override_them() {
+        let settings = ClientSettings {
+            acks: "all".into(),
+            compression: "zstd".into(),
+            properties: BTreeMap::from([("linger.ms".into(), "5".into())]),
+            ..ClientSettings::default()
+        };
+        let client = applied(&settings);
+        assert_eq!(client.get("acks"), Some("all"));
+        assert_eq!(client.get("compression.codec"), Some("zstd"));
+        assert_eq!(client.get("batch.size"), Some("131072"));
+        assert_eq!(client.get("linger.ms"), Some("5"));
+    }
+
+    #[test]
+    fn guarantees_override_acks_and_retries() {
+        let at_most_once = applied(&ClientSettings {
+            guarantee: Some(DeliveryGuarantee::AtMostOnce),
+            ..ClientSettings::default()
+        });
+        assert_eq!(at_most_once.get("enable.idempotence"), Some("false"));
+        assert_eq!(at_most_once.get("message.send.max.retries"), Some("0"));
+
+        let at_least_once = applied(&ClientSettings {
+            guarantee: Some(DeliveryGuarantee::AtLeastOnce),
+            ..ClientSettings::default()
+        });
+        assert_eq!(at_least_once.get("acks"), Some("all"));
+        assert_eq!(at_least_once.get("enable.idempotence"), Some("true"));
+        assert_eq!(at_least_once.get("transactional.id"), None);
+
+        let exactly_once = applied(&ClientSettings {
+            guarantee: Some(DeliveryGuarantee::ExactlyOnce),
+            transactional_id: Some("shipper-1".into()),
+            ..ClientSettings::default()
+        });
+        assert_eq!(exactly_once.get("acks"), Some("all"));
+        assert_eq!(exactly_once.get("enable.idempotence"), Some("true"));
+        assert_eq!(exactly_once.get("transactional.id"), Some("shipper-1"));
+    }
+
+    #[test]
+    fn exactly_once_needs_a_transactional_id() {
+        let mut settings = ClientSettings {
+            guarantee: Some(DeliveryGuarantee::ExactlyOnce),
+            ..ClientSettings::default()
+        };
+        assert!(settings.validate().is_err());
+        settings.transactional_id = Some(String::new());
+        assert!(settings.validate().is_err());
+        settings.transactional_id = Some("shipper-1".into());
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_acks_and_codecs_are_rejected() {
+        assert!(ClientSettings::default().validate().is_ok());
+        for acks in ["2", "ALL", ""] {
+            let settings = ClientSettings {
+                acks: acks.into(),
+                ..ClientSettings::default()
+            };
+            assert!(settings.validate().is_err(), "{acks:?}");
+        }
+        for compression in ["brotli", "Snappy", ""] {
+            let settings = ClientSettings {
+                compression: compression.into(),
+                ..ClientSettings::default()
+            };
+            assert!(settings.validate().is_err(), "{compression:?}");
+        }
+    }
+
+    #[test]
+    fn the_guarantee_is_parsed_in_any_case_and_separator() {
+        assert_eq!(
+            DeliveryGuarantee::parse("Exactly_Once").unwrap(),
+            DeliveryGuarantee::ExactlyOnce
+        );
+        assert!(DeliveryGuarantee::parse("twice").is_err());
+    }
+
+    #[test]
+    fn the_security_protocol_follows_ssl_and_sasl() {
+        let ssl = SslConfig::default();
+        let sasl = SaslConfig {
+            username: "user".into(),
+            password: "pass".into(),
+            ..SaslConfig::default()
+        };
+        for (ssl, sasl, protocol) in [
+            (None, None, None),
+            (Some(&ssl), None, Some("ssl")),
+            (None, Some(&sasl), Some("sasl_plaintext")),
+            (Some(&ssl), Some(&sasl), Some("sasl_ssl")),
+        ] {
+            let mut client = ClientConfig::new();
+            apply_security(&mut client, ssl, sasl);
+            assert_eq!(client.get("security.protocol"), protocol);
+        }
+
+        let mut client = ClientConfig::new();
+        apply_security(&mut client, None, Some(&sasl));
+        assert_eq!(client.get("sasl.mechanism"), Some("PLAIN"));
+        assert_eq!(client.get("sasl.username"), Some("user"));
+        assert_eq!(client.get("sasl.password"), Some("pass"));
+    }
+
+    #[test]
+    fn ssl_and_sasl_settings_are_validated() {
+        let sasl = SaslConfig::default();
+        assert!(validate_security(None, Some(&sasl)).is_err());
+
+        let ssl = SslConfig {
+            certificate_location: Some("/dev/null".into()),
+            ..SslConfig::default()
+        };
+        assert!(validate_security(Some(&ssl), None).is_err());
+        let ssl = SslConfig {
+            ca_location: Some("/nonexistent/ca.pem".into()),
+            ..SslConfig::default()
+        };
+        assert!(validate_security(Some(&ssl), None).is_err());
+    }
+}
@@ -1,30 +1,737 @@
-use std::{env, time::Duration};
+use std::{env, fs, path::Path, str::FromStr, time::Duration};
 
+use anyhow::Context;
 use dotenvy::dotenv;
+use rdkafka::ClientConfig;
+use serde::Deserialize;
 
-use crate::constant::{BROKER, TIME_OUT};
+use crate::backpressure::{BackpressureConfig, BackpressurePolicy};
+use crate::client::{
+    ClientSettings, DeliveryGuarantee, SaslConfig, SaslMechanism, SslConfig, apply_security,
+    validate_security,
+};
+#[cfg(feature = "avro")]
+use crate::constant::SCHEMA_REGISTRY_URL;
+use crate::constant::{
+    BACKPRESSURE_BLOCK_MS, BACKPRESSURE_POLICY, BROKER, CIRCUIT_BREAKER_OPEN_MS,
+    CIRCUIT_BREAKER_THRESHOLD, CONFIG_FILE, CREATE_TOPICS, DEAD_LETTER_TOPIC, DEDUP_WINDOW_MS,
+    DEFAULT_BROKER, DEFAULT_MAX_IN_FLIGHT, DEFAULT_SHUTDOWN_TIMEOUT_SECS, DEFAULT_TIME_OUT_SECS,
+    DEFAULT_TOPIC, DELIVERY_GUARANTEE, ENVIRONMENT, KAFKA_PROPERTY_PREFIX, LINE_FORMAT,
+    MAX_IN_FLIGHT, MAX_RECORD_BYTES, METRICS_ADDR, MULTILINE_CONTINUATION_PATTERN,
+    MULTILINE_START_PATTERN, OVERSIZE_POLICY, PARTITION_STRATEGY, PAYLOAD_FORMAT, RATE_LIMIT_BURST,
+    RATE_LIMIT_PER_SECOND, REDACTION_ACTION, REDACTION_DETECTORS, REDACTION_HASH_SALT,
+    SAMPLE_RATES, SASL_MECHANISM, SASL_PASSWORD, SASL_USERNAME, SHUTDOWN_TIMEOUT, SPOOL_DIR,
+    SSL_CA_LOCATION, SSL_CERTIFICATE_LOCATION, SSL_KEY_LOCATION, SSL_KEY_PASSWORD, SYSLOG_TCP_ADDR,
+    SYSLOG_UDP_ADDR, TAIL_PATHS, TAIL_STATE_FILE, TIME_OUT, TIMESTAMP_FORMAT, TOPIC_CLEANUP_POLICY,
+    TOPIC_PARTITIONS, TOPIC_REPLICATION_FACTOR, TOPIC_RETENTION_MS, TRANSACTIONAL_ID,
+};
+#[cfg(feature = "otlp")]
+use crate::constant::{OTLP_GRPC_ADDR, OTLP_HTTP_ADDR};
+use crate::encoding::{EncodingConfig, PayloadFormat, TimestampFormat};
+use crate::lines::LineFormat;
+use crate::metrics::MetricsConfig;
+#[cfg(feature = "otlp")]
+use crate::otlp::OtlpConfig;
+use crate::oversize::{OversizeConfig, OversizePolicy};
+use crate::partition::{PartitionStrategy, PartitioningConfig};
+use crate::provision::ProvisioningConfig;
+use crate::redact::{Detector, RedactAction, RedactionConfig};
+use crate::routing::RoutingConfig;
+use crate::spool::SpoolConfig;
+use crate::syslog::SyslogConfig;
+use crate::tail::TailConfig;
+use crate::throttle::ThrottleConfig;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Config {
     pub broker: String,
     pub send_timeout: Duration,
+    /// Upper bound on deliveries outstanding at once for `send_many` and `enqueue`.
+    pub max_in_flight: usize,
+    /// How long [`crate::producer::KafkaProducer::shutdown`] waits for queued logs to be
+    /// delivered.
+    pub shutdown_timeout: Duration,
+    pub client: ClientSettings,
+    pub ssl: Option<SslConfig>,
+    pub sasl: Option<SaslConfig>,
+    pub encoding: EncodingConfig,
+    pub routing: RoutingConfig,
+    pub partitioning: PartitioningConfig,
+    /// What happens to logs too large for the broker.
+    pub oversize: OversizeConfig,
+    /// PII redaction applied to every log before it is routed. Disabled when `None`.
+    pub redaction: Option<RedactionConfig>,
+    /// Sampling, rate limiting and duplicate suppression. Disabled when `None`.
+    pub throttle: Option<ThrottleConfig>,
+    /// What sends do while librdkafka's queue is full, and the circuit breaker.
+    pub backpressure: BackpressureConfig,
+    /// Topic checks, and creation, at startup. Disabled when `None`.
+    pub provisioning: Option<ProvisioningConfig>,
+    /// Disk spool for records the broker could not take. Disabled when `None`.
+    pub spool: Option<SpoolConfig>,
+    /// Files followed by the `tail` subcommand.
+    pub tail: Option<TailConfig>,
+    /// Sockets the `syslog` subcommand listens on.
+    pub syslog: Option<SyslogConfig>,
+    /// Endpoints the `otlp` subcommand serves.
+    #[cfg(feature = "otlp")]
+    pub otlp: Option<OtlpConfig>,
+    /// The health, readiness and Prometheus metrics endpoint. Not served when `None`.
+    pub metrics: Option<MetricsConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            broker: DEFAULT_BROKER.into(),
+            send_timeout: Duration::from_secs(DEFAULT_TIME_OUT_SECS),
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            shutdown_timeout: Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT_SECS),
+            client: ClientSettings::default(),
+            ssl: None,
+            sasl: None,
+            encoding: EncodingConfig::default(),
+            routing: RoutingConfig::default(),
+            partitioning: PartitioningConfig::default(),
+            oversize: OversizeConfig::default(),
+            redaction: None,
+            throttle: None,
+            backpressure: BackpressureConfig::default(),
+            provisioning: None,
+            spool: None,
+            tail: None,
+            syslog: None,
+            #[cfg(feature = "otlp")]
+            otlp: None,
+            metrics: None,
+        }
+    }
 }
 
 impl Config {
-    pub fn from_env_or_default() -> Self {
-        dotenv().ok();
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
 
-        let broker = env::var(BROKER).unwrap_or_else(|_| "localhost:9092".into());
-        let send_timeout = Duration::from_secs(
-            env::var(TIME_OUT)
-                .ok() 
-                .and_then(|s| s.parse::<u64>().ok()) 
-                .unwrap_or(5),
+    /// Checks settings librdkafka would only reject (or silently misuse) once connected.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(!self.broker.is_empty(), "broker must be set");
+        self.client.validate()?;
+        anyhow::ensure!(
+            !(self.client.guarantee == Some(DeliveryGuarantee::ExactlyOnce)
+                && self.spool.is_some()),
+            "the spool replays outside of transactions and cannot be used with exactly-once delivery"
+        );
+        anyhow::ensure!(
+            self.spool.is_some() || !self.backpressure.spills(),
+            "the spill backpressure policy needs a spool"
         );
+        validate_security(self.ssl.as_ref(), self.sasl.as_ref())
+    }
 
-        Self {
-            broker,
-            send_timeout,
+    pub fn client_config(&self) -> ClientConfig {
+        let mut client = ClientConfig::new();
+        client.set("bootstrap.servers", &self.broker).set(
+            "message.timeout.ms",
+            self.send_timeout.as_millis().to_string(),
+        );
+        if let Some(metrics) = &self.metrics {
+            client.set(
+                "statistics.interval.ms",
+                metrics.statistics_interval_ms.to_string(),
+            );
+        }
+        apply_security(&mut client, self.ssl.as_ref(), self.sasl.as_ref());
+        self.client.apply(&mut client);
+        client
+    }
+
+    /// Client config for the admin client: only the broker and its security settings.
+    pub fn admin_config(&self) -> ClientConfig {
+        let mut client = ClientConfig::new();
+        client.set("bootstrap.servers", &self.broker);
+        apply_security(&mut client, self.ssl.as_ref(), self.sasl.as_ref());
+        client
+    }
+}
+
+/// Shape of the TOML config file. Every key is optional.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    broker: Option<String>,
+    send_timeout_secs: Option<u64>,
+    max_in_flight: Option<usize>,
+    shutdown_timeout_secs: Option<u64>,
+    client: Option<ClientSettings>,
+    ssl: Option<SslConfig>,
+    sasl: Option<SaslConfig>,
+    encoding: Option<EncodingConfig>,
+    routing: Option<RoutingConfig>,
+    partitioning: Option<PartitioningConfig>,
+    oversize: Option<OversizeConfig>,
+    redaction: Option<RedactionConfig>,
+    throttle: Option<ThrottleConfig>,
+    backpressure: Option<BackpressureConfig>,
+    provisioning: Option<ProvisioningConfig>,
+    spool: Option<SpoolConfig>,
+    tail: Option<TailConfig>,
+    syslog: Option<SyslogConfig>,
+    #[cfg(feature = "otlp")]
+    otlp: Option<OtlpConfig>,
+    metrics: Option<MetricsConfig>,
+}
+
+/// Builds a [`Config`]. Values that are never set fall back to [`Config::default`].
+#[derive(Default, Debug)]
+pub struct ConfigBuilder {
+    broker: Option<String>,
+    send_timeout: Option<Duration>,
+    max_in_flight: Option<usize>,
+    shutdown_timeout: Option<Duration>,
+    client: Option<ClientSettings>,
+    ssl: Option<SslConfig>,
+    sasl: Option<SaslConfig>,
+    encoding: Option<EncodingConfig>,
+    routing: Option<RoutingConfig>,
+    partitioning: Option<PartitioningConfig>,
+    oversize: Option<OversizeConfig>,
+    redaction: Option<RedactionConfig>,
+    throttle: Option<ThrottleConfig>,
+    backpressure: Option<BackpressureConfig>,
+    provisioning: Option<ProvisioningConfig>,
+    spool: Option<SpoolConfig>,
+    tail: Option<TailConfig>,
+    syslog: Option<SyslogConfig>,
+    #[cfg(feature = "otlp")]
+    otlp: Option<OtlpConfig>,
+    metrics: Option<MetricsConfig>,
+}
+
+impl ConfigBuilder {
+    pub fn broker(mut self, broker: impl Into<String>) -> Self {
+        self.broker = Some(broker.into());
+        self
+    }
+
+    pub fn send_timeout(mut self, send_timeout: Duration) -> Self {
+        self.send_timeout = Some(send_timeout);
+        self
+    }
+
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = Some(max_in_flight);
+        self
+    }
+
+    pub fn shutdown_timeout(mut self, shutdown_timeout: Duration) -> Self {
+        self.shutdown_timeout = Some(shutdown_timeout);
+        self
+    }
+
+    pub fn client(mut self, client: ClientSettings) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Sets a single librdkafka property, overriding the typed client settings.
+    pub fn property(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.client
+            .get_or_insert_with(Default::default)
+            .properties
+            .insert(name.into(), value.into());
+        self
+    }
+
+    pub fn ssl(mut self, ssl: SslConfig) -> Self {
+        self.ssl = Some(ssl);
+        self
+    }
+
+    pub fn sasl(mut self, sasl: SaslConfig) -> Self {
+        self.sasl = Some(sasl);
+        self
+    }
+
+    pub fn encoding(mut self, encoding: EncodingConfig) -> Self {
+        self.encoding = Some(encoding);
+        self
+    }
+
+    pub fn routing(mut self, routing: RoutingConfig) -> Self {
+        self.routing = Some(routing);
+        self
+    }
+
+    pub fn partitioning(mut self, partitioning: PartitioningConfig) -> Self {
+        self.partitioning = Some(partitioning);
+        self
+    }
+
+    pub fn oversize(mut self, oversize: OversizeConfig) -> Self {
+        self.oversize = Some(oversize);
+        self
+    }
+
+    pub fn redaction(mut self, redaction: RedactionConfig) -> Self {
+        self.redaction = Some(redaction);
+        self
+    }
+
+    pub fn throttle(mut self, throttle: ThrottleConfig) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    pub fn backpressure(mut self, backpressure: BackpressureConfig) -> Self {
+        self.backpressure = Some(backpressure);
+        self
+    }
+
+    pub fn provisioning(mut self, provisioning: ProvisioningConfig) -> Self {
+        self.provisioning = Some(provisioning);
+        self
+    }
+
+    pub fn spool(mut self, spool: SpoolConfig) -> Self {
+        self.spool = Some(spool);
+        self
+    }
+
+    pub fn tail(mut self, tail: TailConfig) -> Self {
+        self.tail = Some(tail);
+        self
+    }
+
+    pub fn syslog(mut self, syslog: SyslogConfig) -> Self {
+        self.syslog = Some(syslog);
+        self
+    }
+
+    #[cfg(feature = "otlp")]
+    pub fn otlp(mut self, otlp: OtlpConfig) -> Self {
+        self.otlp = Some(otlp);
+        self
+    }
+
+    pub fn metrics(mut self, metrics: MetricsConfig) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Applies every value set in a TOML config file.
+    pub fn with_file(mut self, path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let raw = fs::read_to_string(path)
+            .with_context(|| format!("reading config file {}", path.display()))?;
+        let file: FileConfig = toml::from_str(&raw)
+            .with_context(|| format!("parsing config file {}", path.display()))?;
+
+        if let Some(broker) = file.broker {
+            self.broker = Some(broker);
+        }
+        if let Some(secs) = file.send_timeout_secs {
+            self.send_timeout = Some(Duration::from_secs(secs));
+        }
+        if let Some(max_in_flight) = file.max_in_flight {
+            self.max_in_flight = Some(max_in_flight);
+        }
+        if let Some(secs) = file.shutdown_timeout_secs {
+            self.shutdown_timeout = Some(Duration::from_secs(secs));
+        }
+        if let Some(client) = file.client {
+            self.client = Some(client);
+        }
+        if let Some(ssl) = file.ssl {
+            self.ssl = Some(ssl);
+        }
+        if let Some(sasl) = file.sasl {
+            self.sasl = Some(sasl);
+        }
+        if let Some(encoding) = file.encoding {
+            self.encoding = Some(encoding);
+        }
+        if let Some(routing) = file.routing {
+            self.routing = Some(routing);
+        }
+        if let Some(partitioning) = file.partitioning {
+            self.partitioning = Some(partitioning);
+        }
+        if let Some(oversize) = file.oversize {
+            self.oversize = Some(oversize);
+        }
+        if let Some(redaction) = file.redaction {
+            self.redaction = Some(redaction);
+        }
+        if let Some(throttle) = file.throttle {
+            self.throttle = Some(throttle);
+        }
+        if let Some(backpressure) = file.backpressure {
+            self.backpressure = Some(backpressure);
+        }
+        if let Some(provisioning) = file.provisioning {
+            self.provisioning = Some(provisioning);
+        }
+        if let Some(spool) = file.spool {
+            self.spool = Some(spool);
         }
+        if let Some(tail) = file.tail {
+            self.tail = Some(tail);
+        }
+        if let Some(syslog) = file.syslog {
+            self.syslog = Some(syslog);
+        }
+        #[cfg(feature = "otlp")]
+        if let Some(otlp) = file.otlp {
+            self.otlp = Some(otlp);
+        }
+        if let Some(metrics) = file.metrics {
+            self.metrics = Some(metrics);
+        }
+        Ok(self)
+    }
+
+    /// Loads the file named by `CONFIG_FILE`, if any, then overrides values with the variables in
+    /// [`crate::constant`] that are present in the environment (or `.env`).
+    pub fn with_env(mut self) -> anyhow::Result<Self> {
+        dotenv().ok();
+
+        if let Ok(path) = env::var(CONFIG_FILE) {
+            self = self.with_file(path)?;
+        }
+        if let Ok(broker) = env::var(BROKER) {
+            self.broker = Some(broker);
+        }
+        if let Some(secs) = env_parsed::<u64>(TIME_OUT)? {
+            self.send_timeout = Some(Duration::from_secs(secs));
+        }
+        if let Some(max_in_flight) = env_parsed::<usize>(MAX_IN_FLIGHT)? {
+            self.max_in_flight = Some(max_in_flight);
+        }
+        if let Some(secs) = env_parsed::<u64>(SHUTDOWN_TIMEOUT)? {
+            self.shutdown_timeout = Some(Duration::from_secs(secs));
+        }
+        if let Ok(environment) = env::var(ENVIRONMENT) {
+            self.routing
+                .get_or_insert_with(Default::default)
+                .environment = Some(environment);
+        }
+        if let Ok(topic) = env::var(DEFAULT_TOPIC) {
+            self.routing
+                .get_or_insert_with(Default::default)
+                .default_topic = topic;
+        }
+        if let Ok(guarantee) = env::var(DELIVERY_GUARANTEE) {
+            self.client.get_or_insert_with(Default::default).guarantee =
+                Some(DeliveryGuarantee::parse(&guarantee)?);
+        }
+        if let Ok(id) = env::var(TRANSACTIONAL_ID) {
+            self.client
+                .get_or_insert_with(Default::default)
+                .transactional_id = Some(id);
+        }
+        if let Ok(strategy) = env::var(PARTITION_STRATEGY) {
+            self.partitioning
+                .get_or_insert_with(Default::default)
+                .default = PartitionStrategy::parse(&strategy)?;
+        }
+        if let Ok(policy) = env::var(OVERSIZE_POLICY) {
+            self.oversize.get_or_insert_with(Default::default).default =
+                OversizePolicy::parse(&policy)?;
+        }
+        if let Some(max_bytes) = env_parsed::<usize>(MAX_RECORD_BYTES)? {
+            self.oversize.get_or_insert_with(Default::default).max_bytes = max_bytes;
+        }
+        if let Ok(topic) = env::var(DEAD_LETTER_TOPIC) {
+            self.oversize
+                .get_or_insert_with(Default::default)
+                .dead_letter_topic = topic;
+        }
+        if let Ok(detectors) = env::var(REDACTION_DETECTORS) {
+            self.redaction
+                .get_or_insert_with(Default::default)
+                .detectors = detectors
+                .split(',')
+                .map(|name| name.trim())
+                .filter(|name| !name.is_empty())
+                .map(Detector::parse)
+                .collect::<anyhow::Result<_>>()?;
+        }
+        if let Ok(action) = env::var(REDACTION_ACTION) {
+            self.redaction.get_or_insert_with(Default::default).action =
+                RedactAction::parse(&action)?;
+        }
+        if let Ok(salt) = env::var(REDACTION_HASH_SALT) {
+            self.redaction
+                .get_or_insert_with(Default::default)
+                .hash_salt = salt;
+        }
+        if let Ok(rates) = env::var(SAMPLE_RATES) {
+            let sample = &mut self.throttle.get_or_insert_with(Default::default).sample;
+            for pair in rates.split(',').filter(|pair| !pair.trim().is_empty()) {
+                let (level, rate) = pair.split_once('=').with_context(|| {
+                    format!("{} entry {:?} is not LEVEL=RATE", SAMPLE_RATES, pair)
+                })?;
+                let rate = rate
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("invalid sample rate for {}", level.trim()))?;
+                sample.insert(level.trim().to_string(), rate);
+            }
+        }
+        if let Some(per_second) = env_parsed::<f64>(RATE_LIMIT_PER_SECOND)? {
+            let throttle = self.throttle.get_or_insert_with(Default::default);
+            throttle
+                .rate_limit
+                .get_or_insert_with(Default::default)
+                .per_second = per_second;
+        }
+        if let Some(burst) = env_parsed::<u32>(RATE_LIMIT_BURST)? {
+            let throttle = self.throttle.get_or_insert_with(Default::default);
+            throttle
+                .rate_limit
+                .get_or_insert_with(Default::default)
+                .burst = burst;
+        }
+        if let Some(window) = env_parsed::<u64>(DEDUP_WINDOW_MS)? {
+            self.throttle
+                .get_or_insert_with(Default::default)
+                .dedup_window_ms = Some(window);
+        }
+        if let Ok(policy) = env::var(BACKPRESSURE_POLICY) {
+            self.backpressure
+                .get_or_insert_with(Default::default)
+                .default = BackpressurePolicy::parse(&policy)?;
+        }
+        if let Some(block_ms) = env_parsed::<u64>(BACKPRESSURE_BLOCK_MS)? {
+            self.backpressure
+                .get_or_insert_with(Default::default)
+                .block_ms = Some(block_ms);
+        }
+        if let Some(threshold) = env_parsed::<u32>(CIRCUIT_BREAKER_THRESHOLD)? {
+            let backpressure = self.backpressure.get_or_insert_with(Default::default);
+            backpressure
+                .circuit_breaker
+                .get_or_insert_with(Default::default)
+                .failure_threshold = threshold;
+        }
+        if let Some(open_ms) = env_parsed::<u64>(CIRCUIT_BREAKER_OPEN_MS)? {
+            let backpressure = self.backpressure.get_or_insert_with(Default::default);
+            backpressure
+                .circuit_breaker
+                .get_or_insert_with(Default::default)
+                .open_ms = open_ms;
+        }
+        if let Some(create) = env_flag(CREATE_TOPICS)? {
+            self.provisioning
+                .get_or_insert_with(Default::default)
+                .create_missing = create;
+        }
+        if let Some(partitions) = env_parsed::<i32>(TOPIC_PARTITIONS)? {
+            let provisioning = self.provisioning.get_or_insert_with(Default::default);
+            provisioning.defaults.partitions = Some(partitions);
+        }
+        if let Some(replication_factor) = env_parsed::<i32>(TOPIC_REPLICATION_FACTOR)? {
+            let provisioning = self.provisioning.get_or_insert_with(Default::default);
+            provisioning.defaults.replication_factor = Some(replication_factor);
+        }
+        if let Some(retention_ms) = env_parsed::<i64>(TOPIC_RETENTION_MS)? {
+            let provisioning = self.provisioning.get_or_insert_with(Default::default);
+            provisioning.defaults.retention_ms = Some(retention_ms);
+        }
+        if let Ok(policy) = env::var(TOPIC_CLEANUP_POLICY) {
+            let provisioning = self.provisioning.get_or_insert_with(Default::default);
+            provisioning.defaults.cleanup_policy = Some(policy);
+        }
+        if let Ok(dir) = env::var(SPOOL_DIR) {
+            self.spool.get_or_insert_with(Default::default).dir = dir.into();
+        }
+        if let Ok(paths) = env::var(TAIL_PATHS) {
+            self.tail.get_or_insert_with(Default::default).paths = paths
+                .split(',')
+                .map(|path| path.trim().to_string())
+                .collect();
+        }
+        if let Ok(path) = env::var(TAIL_STATE_FILE) {
+            self.tail.get_or_insert_with(Default::default).state_file = path.into();
+        }
+        if let Ok(format) = env::var(LINE_FORMAT) {
+            self.tail.get_or_insert_with(Default::default).format = LineFormat::parse(&format)?;
+        }
+        if let Ok(pattern) = env::var(MULTILINE_START_PATTERN) {
+            let tail = self.tail.get_or_insert_with(Default::default);
+            tail.multiline
+                .get_or_insert_with(Default::default)
+                .start_pattern = Some(pattern);
+        }
+        if let Ok(pattern) = env::var(MULTILINE_CONTINUATION_PATTERN) {
+            let tail = self.tail.get_or_insert_with(Default::default);
+            tail.multiline
+                .get_or_insert_with(Default::default)
+                .continuation_pattern = Some(pattern);
+        }
+        if let Ok(addr) = env::var(SYSLOG_UDP_ADDR) {
+            self.syslog.get_or_insert_with(Default::default).udp_addr = Some(addr);
+        }
+        if let Ok(addr) = env::var(SYSLOG_TCP_ADDR) {
+            self.syslog.get_or_insert_with(Default::default).tcp_addr = Some(addr);
+        }
+        #[cfg(feature = "otlp")]
+        {
+            if let Ok(addr) = env::var(OTLP_GRPC_ADDR) {
+                self.otlp.get_or_insert_with(Default::default).grpc_addr = Some(addr);
+            }
+            if let Ok(addr) = env::var(OTLP_HTTP_ADDR) {
+                self.otlp.get_or_insert_with(Default::default).http_addr = Some(addr);
+            }
+        }
+        if let Ok(addr) = env::var(METRICS_ADDR) {
+            self.metrics.get_or_insert_with(Default::default).addr = addr;
+        }
+
+        if let Ok(path) = env::var(SSL_CA_LOCATION) {
+            self.ssl.get_or_insert_with(Default::default).ca_location = Some(path.into());
+        }
+        if let Ok(path) = env::var(SSL_CERTIFICATE_LOCATION) {
+            self.ssl
+                .get_or_insert_with(Default::default)
+                .certificate_location = Some(path.into());
+        }
+        if let Ok(path) = env::var(SSL_KEY_LOCATION) {
+            self.ssl.get_or_insert_with(Default::default).key_location = Some(path.into());
+        }
+        if let Ok(password) = env::var(SSL_KEY_PASSWORD) {
+            self.ssl.get_or_insert_with(Default::default).key_password = Some(password);
+        }
+        if let Ok(mechanism) = env::var(SASL_MECHANISM) {
+            self.sasl.get_or_insert_with(Default::default).mechanism =
+                SaslMechanism::parse(&mechanism)?;
+        }
+        if let Ok(username) = env::var(SASL_USERNAME) {
+            self.sasl.get_or_insert_with(Default::default).username = username;
+        }
+        if let Ok(password) = env::var(SASL_PASSWORD) {
+            self.sasl.get_or_insert_with(Default::default).password = password;
+        }
+        if let Ok(format) = env::var(PAYLOAD_FORMAT) {
+            self.encoding.get_or_insert_with(Default::default).format =
+                PayloadFormat::parse(&format)?;
+        }
+        if let Ok(format) = env::var(TIMESTAMP_FORMAT) {
+            self.encoding
+                .get_or_insert_with(Default::default)
+                .timestamp_format = TimestampFormat::parse(&format)?;
+        }
+        #[cfg(feature = "avro")]
+        if let Ok(url) = env::var(SCHEMA_REGISTRY_URL) {
+            self.encoding
+                .get_or_insert_with(Default::default)
+                .schema_registry
+                .get_or_insert_with(Default::default)
+                .url = url;
+        }
+
+        // KAFKA_PROP_QUEUE_BUFFERING_MAX_MESSAGES=1000 sets `queue.buffering.max.messages`.
+        for (var, value) in env::vars() {
+            if let Some(name) = var.strip_prefix(KAFKA_PROPERTY_PREFIX) {
+                self = self.property(name.to_ascii_lowercase().replace('_', "."), value);
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn build(self) -> Config {
+        let default = Config::default();
+        Config {
+            broker: self.broker.unwrap_or(default.broker),
+            send_timeout: self.send_timeout.unwrap_or(default.send_timeout),
+            max_in_flight: self.max_in_flight.unwrap_or(default.max_in_flight),
+            shutdown_timeout: self.shutdown_timeout.unwrap_or(default.shutdown_timeout),
+            client: self.client.unwrap_or(default.client),
+            ssl: self.ssl,
+            sasl: self.sasl,
+            encoding: self.encoding.unwrap_or_default(),
+            routing: self.routing.unwrap_or(default.routing),
+            partitioning: self.partitioning.unwrap_or_default(),
+            oversize: self.oversize.unwrap_or_default(),
+            redaction: self.redaction,
+            throttle: self.throttle,
+            backpressure: self.backpressure.unwrap_or_default(),
+            provisioning: self.provisioning,
+            spool: self.spool,
+            tail: self.tail,
+            syslog: self.syslog,
+            #[cfg(feature = "otlp")]
+            otlp: self.otlp,
+            metrics: self.metrics,
+        }
+    }
+}
+
+/// The environment variable `var` parsed as a `T`, or `None` when it is unset. A value that does
+/// not parse is an error, so a typo fails startup instead of leaving the default in place.
+fn env_parsed<T>(var: &str) -> anyhow::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    env::var(var)
+        .ok()
+        .map(|raw| {
+            raw.trim()
+                .parse()
+                .with_context(|| format!("invalid {} {:?}", var, raw))
+        })
+        .transpose()
+}
+
+/// Like [`env_parsed`], for a flag: `1`, `true` or `yes`, or `0`, `false`, `no` or empty.
+fn env_flag(var: &str) -> anyhow::Result<Option<bool>> {
+    env::var(var)
+        .ok()
+        .map(|raw| match raw.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" => Ok(true),
+            "" | "0" | "false" | "no" => Ok(false),
+            _ => anyhow::bail!("invalid {} {:?}, expected true or false", var, raw),
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each test sets its own variables, so tests running in parallel do not see each other's.
+    fn set(var: &str, value: &str) {
+        unsafe { env::set_var(var, value) };
+    }
+
+    #[test]
+    fn parses_numeric_variables() {
+        set("KAFKA_APP_TEST_NUMBER", " 42 ");
+        assert_eq!(
+            env_parsed::<u64>("KAFKA_APP_TEST_NUMBER").unwrap(),
+            Some(42)
+        );
+        assert_eq!(env_parsed::<u64>("KAFKA_APP_TEST_UNSET").unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_numeric_variables_that_do_not_parse() {
+        set("KAFKA_APP_TEST_BAD_NUMBER", "5s");
+        let error = env_parsed::<u64>("KAFKA_APP_TEST_BAD_NUMBER").unwrap_err();
+        assert!(error.to_string().contains("KAFKA_APP_TEST_BAD_NUMBER"));
+        set("KAFKA_APP_TEST_NEGATIVE", "-1");
+        assert!(env_parsed::<usize>("KAFKA_APP_TEST_NEGATIVE").is_err());
+    }
+
+    #[test]
+    fn parses_flags() {
+        set("KAFKA_APP_TEST_FLAG_ON", "Yes");
+        set("KAFKA_APP_TEST_FLAG_OFF", "0");
+        set("KAFKA_APP_TEST_FLAG_BAD", "maybe");
+        assert_eq!(env_flag("KAFKA_APP_TEST_FLAG_ON").unwrap(), Some(true));
+        assert_eq!(env_flag("KAFKA_APP_TEST_FLAG_OFF").unwrap(), Some(false));
+        assert!(env_flag("KAFKA_APP_TEST_FLAG_BAD").is_err());
     }
 }
@@ -1,5 +1,83 @@
 pub const BROKER: &str = "BROKER";
 pub const TIME_OUT: &str = "TIME_OUT";
+pub const SHUTDOWN_TIMEOUT: &str = "SHUTDOWN_TIMEOUT";
+pub const CONFIG_FILE: &str = "CONFIG_FILE";
+pub const ENVIRONMENT: &str = "ENVIRONMENT";
+pub const DEFAULT_TOPIC: &str = "DEFAULT_TOPIC";
+pub const SPOOL_DIR: &str = "SPOOL_DIR";
+pub const MAX_IN_FLIGHT: &str = "MAX_IN_FLIGHT";
+pub const SSL_CA_LOCATION: &str = "SSL_CA_LOCATION";
+pub const SSL_CERTIFICATE_LOCATION: &str = "SSL_CERTIFICATE_LOCATION";
+pub const SSL_KEY_LOCATION: &str = "SSL_KEY_LOCATION";
+pub const SSL_KEY_PASSWORD: &str = "SSL_KEY_PASSWORD";
+pub const SASL_MECHANISM: &str = "SASL_MECHANISM";
+pub const SASL_USERNAME: &str = "SASL_USERNAME";
+pub const SASL_PASSWORD: &str = "SASL_PASSWORD";
+pub const PAYLOAD_FORMAT: &str = "PAYLOAD_FORMAT";
+pub const SCHEMA_REGISTRY_URL: &str = "SCHEMA_REGISTRY_URL";
+pub const TIMESTAMP_FORMAT: &str = "TIMESTAMP_FORMAT";
+pub const DELIVERY_GUARANTEE: &str = "DELIVERY_GUARANTEE";
+pub const TRANSACTIONAL_ID: &str = "TRANSACTIONAL_ID";
+pub const PARTITION_STRATEGY: &str = "PARTITION_STRATEGY";
+pub const TAIL_PATHS: &str = "TAIL_PATHS";
+pub const TAIL_STATE_FILE: &str = "TAIL_STATE_FILE";
+pub const LINE_FORMAT: &str = "LINE_FORMAT";
+pub const MULTILINE_START_PATTERN: &str = "MULTILINE_START_PATTERN";
+pub const MULTILINE_CONTINUATION_PATTERN: &str = "MULTILINE_CONTINUATION_PATTERN";
+pub const SYSLOG_UDP_ADDR: &str = "SYSLOG_UDP_ADDR";
+pub const SYSLOG_TCP_ADDR: &str = "SYSLOG_TCP_ADDR";
+pub const OTLP_GRPC_ADDR: &str = "OTLP_GRPC_ADDR";
+pub const OTLP_HTTP_ADDR: &str = "OTLP_HTTP_ADDR";
+pub const METRICS_ADDR: &str = "METRICS_ADDR";
+pub const OVERSIZE_POLICY: &str = "OVERSIZE_POLICY";
+pub const MAX_RECORD_BYTES: &str = "MAX_RECORD_BYTES";
+pub const DEAD_LETTER_TOPIC: &str = "DEAD_LETTER_TOPIC";
+pub const REDACTION_DETECTORS: &str = "REDACTION_DETECTORS";
+pub const REDACTION_ACTION: &str = "REDACTION_ACTION";
+pub const REDACTION_HASH_SALT: &str = "REDACTION_HASH_SALT";
+pub const SAMPLE_RATES: &str = "SAMPLE_RATES";
+pub const RATE_LIMIT_PER_SECOND: &str = "RATE_LIMIT_PER_SECOND";
+pub const RATE_LIMIT_BURST: &str = "RATE_LIMIT_BURST";
+pub const DEDUP_WINDOW_MS: &str = "DEDUP_WINDOW_MS";
+pub const BACKPRESSURE_POLICY: &str = "BACKPRESSURE_POLICY";
+pub const BACKPRESSURE_BLOCK_MS: &str = "BACKPRESSURE_BLOCK_MS";
+pub const CIRCUIT_BREAKER_THRESHOLD: &str = "CIRCUIT_BREAKER_THRESHOLD";
+pub const CIRCUIT_BREAKER_OPEN_MS: &str = "CIRCUIT_BREAKER_OPEN_MS";
+pub const CREATE_TOPICS: &str = "CREATE_TOPICS";
+pub const TOPIC_PARTITIONS: &str = "TOPIC_PARTITIONS";
+pub const TOPIC_REPLICATION_FACTOR: &str = "TOPIC_REPLICATION_FACTOR";
+pub const TOPIC_RETENTION_MS: &str = "TOPIC_RETENTION_MS";
+pub const TOPIC_CLEANUP_POLICY: &str = "TOPIC_CLEANUP_POLICY";
+pub const KAFKA_PROPERTY_PREFIX: &str = "KAFKA_PROP_";
+pub const DEFAULT_BROKER: &str = "localhost:9092";
+pub const DEFAULT_TIME_OUT_SECS: u64 = 5;
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 1000;
+pub const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 10;
+/// librdkafka's default `message.max.bytes`.
+pub const DEFAULT_MAX_RECORD_BYTES: usize = 1_000_000;
+/// How long, and how many bytes of chunks, a [`crate::oversize::Reassembler`] waits for the
+/// rest of a split record.
+pub const DEFAULT_REASSEMBLY_TTL_SECS: u64 = 60;
+pub const DEFAULT_REASSEMBLY_MAX_BYTES: usize = 64_000_000;
+/// How long a Schema Registry request may take to connect, and to finish.
+pub const REGISTRY_CONNECT_TIMEOUT_SECS: u64 = 5;
+pub const REGISTRY_REQUEST_TIMEOUT_SECS: u64 = 10;
 pub const ERROR_TOPIC: &str = "error_logs_test";
 pub const INFO_TOPIC: &str = "common_logs_test";
-pub const WARN_TOPIC: &str = "warn_logs_test";
\ No newline at end of file
+pub const WARN_TOPIC: &str = "warn_logs_test";
+pub const DEFAULT_DEAD_LETTER_TOPIC: &str = "dead_letter_logs";
+pub const CONTENT_TYPE_HEADER: &str = "content-type";
+pub const LOG_TYPE_HEADER: &str = "log-type";
+pub const SCHEMA_VERSION_HEADER: &str = "schema-version";
+pub const HOSTNAME_HEADER: &str = "hostname";
+pub const TRACE_ID_HEADER: &str = "trace-id";
+pub const SPAN_ID_HEADER: &str = "span-id";
+pub const CHUNK_ID_HEADER: &str = "chunk-id";
+pub const CHUNK_INDEX_HEADER: &str = "chunk-index";
+pub const CHUNK_COUNT_HEADER: &str = "chunk-count";
+pub const ORIGINAL_TOPIC_HEADER: &str = "original-topic";
+pub const ORIGINAL_SIZE_HEADER: &str = "original-size";
+pub const DEAD_LETTER_REASON_HEADER: &str = "dead-letter-reason";
+pub const REPEAT_COUNT_HEADER: &str = "repeat-count";
+/// Version of the log models' shape, shared by the JSON, Avro and Protobuf encodings.
+pub const LOG_SCHEMA_VERSION: &str = "1";
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[cfg(feature = "avro")]
+use crate::avro::{AvroDecoder, AvroEncoder, SchemaRegistryConfig};
+use crate::helper::legacy_timestamp;
+use crate::models::LogType;
+use crate::proto;
+
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+pub const AVRO_CONTENT_TYPE: &str = "application/vnd.confluent.avro";
+pub const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadFormat {
+    #[default]
+    Json,
+    /// Avro in the Schema Registry wire format. Needs `schema_registry` and the `avro` feature.
+    Avro,
+    /// The messages in `proto/logs.proto`.
+    Protobuf,
+}
+
+impl PayloadFormat {
+    pub fn parse(raw: &str) -> anyhow::Result<Self> {
+        match raw.to_ascii_lowercase().as_str() {
+            "json" => Ok(PayloadFormat::Json),
+            "avro" => Ok(PayloadFormat::Avro),
+            "protobuf" | "proto" => Ok(PayloadFormat::Protobuf),
+            other => anyhow::bail!("unsupported payload format {:?}", other),
+        }
+    }
+}
+
+/// How the `timestamp` field of a log is written.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TimestampFormat {
+    /// RFC 3339 in UTC, e.g. `2025-01-31T09:15:02.123456789Z`.
+    #[default]
+    Rfc3339,
+    /// The format sent before timestamps were typed, for consumers that parse it: local time with
+    /// millisecond precision and a literal `Z`.
+    Legacy,
+}
+
+impl TimestampFormat {
+    pub fn parse(raw: &str) -> anyhow::Result<Self> {
+        match raw.to_ascii_lowercase().replace('-', "").as_str() {
+            "rfc3339" => Ok(TimestampFormat::Rfc3339),
+            "legacy" => Ok(TimestampFormat::Legacy),
+            other => anyhow::bail!("unsupported timestamp format {:?}", other),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct EncodingConfig {
+    pub format: PayloadFormat,
+    pub timestamp_format: TimestampFormat,
+    /// Per-topic overrides of `format`, keyed by the routed topic name (prefix included).
+    pub topics: HashMap<String, PayloadFormat>,
+    #[cfg(feature = "avro")]
+    pub schema_registry: Option<SchemaRegistryConfig>,
+}
+
+impl EncodingConfig {
+    pub fn format_for(&self, topic: &str) -> PayloadFormat {
+        self.topics.get(topic).copied().unwrap_or(self.format)
+    }
+}
+
+/// A payload ready to produce, with the `content-type` header value that describes it.
+pub struct EncodedPayload {
+    pub bytes: Vec<u8>,
+    pub content_type: String,
+}
+
+/// Turns the JSON form of a log into the wire format configured for its topic.
+pub struct PayloadEncoder {
+    cfg: EncodingConfig,
+    #[cfg(feature = "avro")]
+    avro: Option<AvroEncoder>,
+}
+
+impl PayloadEncoder {
+    pub fn new(cfg: &EncodingConfig) -> anyhow::Result<Self> {
+        let uses_avro = std::iter::once(&cfg.format)
+            .chain(cfg.topics.values())
+            .any(|format| *format == PayloadFormat::Avro);
+        #[cfg(feature = "avro")]
+        let avro = match (uses_avro, &cfg.schema_registry) {
+            (true, Some(registry)) => Some(AvroEncoder::new(registry.clone())?),
+            (true, None) => {
+                anyhow::bail!("the avro payload format needs a schema_registry section")
+            }
+            (false, _) => None,
+        };
+        #[cfg(not(feature = "avro"))]
+        anyhow::ensure!(
+            !uses_avro,
+            "the avro payload format needs kafka_app's avro feature"
+        );
+        Ok(Self {
+            cfg: cfg.clone(),
+            #[cfg(feature = "avro")]
+            avro,
+        })
+    }
+
+    /// Serializes a log to JSON, the form used for routing and as the input of [`Self::encode`].
+    pub fn serialize<T: Serialize>(&self, entry: &T) -> anyhow::Result<String> {
+        if self.cfg.timestamp_format == TimestampFormat::Rfc3339 {
+            return Ok(serde_json::to_string(entry)?);
+        }
+        let mut doc = serde_json::to_value(entry)?;
+        if let Some(timestamp) = doc.get_mut("timestamp")
+            && let Some(at) = timestamp
+                .as_str()
+                .and_then(|raw| raw.parse::<DateTime<Utc>>().ok())
+        {
+            *timestamp = Value::String(legacy_timestamp(at));
+        }
+        Ok(serde_json::to_string(&doc)?)
+    }
+
+    /// `json` is the serialized log, as produced for routing.
+    pub fn format_for(&self, topic: &str) -> PayloadFormat {
+        self.cfg.format_for(topic)
+    }
+
+    pub async fn encode(
+        &self,
+        topic: &str,
+        log_type: LogType,
+        json: String,
+    ) -> anyhow::Result<EncodedPayload> {
+        match self.cfg.format_for(topic) {
+            PayloadFormat::Json => Ok(EncodedPayload {
+                bytes: json.into_bytes(),
+                content_type: JSON_CONTENT_TYPE.into(),
+            }),
+            #[cfg(feature = "avro")]
+            PayloadFormat::Avro => {
+                let doc: Value = serde_json::from_str(&json)?;
+                let avro = self
+                    .avro
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("avro encoder is not configured"))?;
+                Ok(EncodedPayload {
+                    bytes: avro.encode(topic, log_type, &doc).await?,
+                    content_type: AVRO_CONTENT_TYPE.into(),
+                })
+            }
+            #[cfg(not(feature = "avro"))]
+            PayloadFormat::Avro => anyhow::bail!("avro encoding needs kafka_app's avro feature"),
+            PayloadFormat::Protobuf => {
+                let doc: Value = serde_json::from_str(&json)?;
+                Ok(EncodedPayload {
+                    bytes: proto::encode(log_type, &doc),
+                    content_type: format!(
+                        "{}; messagetype={}",
+                        PROTOBUF_CONTENT_TYPE,
+                        proto::message_type(log_type)
+                    ),
+                })
+            }
+        }
+    }
+}
+
+/// Decodes payloads of every format, Avro included, into the JSON form of the log.
+#[cfg(feature = "avro")]
+pub struct PayloadDecoder {
+    avro: Option<AvroDecoder>,
+}
+
+#[cfg(feature = "avro")]
+impl PayloadDecoder {
+    /// Avro payloads can only be decoded with a `schema_registry` to fetch their schemas from.
+    pub fn new(schema_registry: Option<SchemaRegistryConfig>) -> anyhow::Result<Self> {
+        Ok(Self {
+            avro: schema_registry.map(AvroDecoder::new).transpose()?,
+        })
+    }
+
+    /// Like [`decode`], but Avro payloads are decoded too.
+    pub async fn decode(
+        &self,
+        content_type: Option<&str>,
+        payload: &[u8],
+    ) -> anyhow::Result<Value> {
+        let media_type = content_type
+            .and_then(|content_type| content_type.split(';').next())
+            .map(str::trim);
+        if media_type != Some(AVRO_CONTENT_TYPE) {
+            return decode(content_type, payload);
+        }
+        match &self.avro {
+            Some(avro) => avro.decode(payload).await,
+            None => anyhow::bail!("avro payloads need a schema registry to be decoded"),
+        }
+    }
+}
+
+/// Decodes a payload by its `content-type` header into the JSON form of the log. Records
+/// without the header are treated as JSON, which is what older producers sent. Avro payloads
+/// need their schema from the registry, which [`PayloadDecoder`] fetches.
+pub fn decode(content_type: Option<&str>, payload: &[u8]) -> anyhow::Result<Value> {
+    let content_type = content_type.unwrap_or(JSON_CONTENT_TYPE);
+    let mut parts = content_type.split(';').map(str::trim);
+    match parts.next().unwrap_or_default() {
+        JSON_CONTENT_TYPE => Ok(serde_json::from_slice(payload)?),
+        PROTOBUF_CONTENT_TYPE => {
+            let message_type = parts
+                .find_map(|param| param.strip_prefix("messagetype="))
+                .ok_or_else(|| anyhow::anyhow!("protobuf content-type without messagetype"))?;
+            proto::decode(message_type, payload)
+        }
+        AVRO_CONTENT_TYPE => anyhow::bail!("avro payloads are decoded by a PayloadDecoder"),
+        other => anyhow::bail!("cannot decode content-type {:?}", other),
+    }
+}
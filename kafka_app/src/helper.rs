@@ -1,4 +1,25 @@
+use chrono::{DateTime, Local, Utc};
 use hostname::get;
+use rdkafka::message::{Header, OwnedHeaders};
+use serde_json::Value;
+
+/// Resolves on the first SIGINT (Ctrl-C) or SIGTERM.
+pub async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await
+    }
+}
 
 pub fn get_hostname() -> String {
     get()
@@ -7,8 +28,104 @@ pub fn get_hostname() -> String {
         .unwrap_or_else(|_| "unknown".into())
 }
 
-pub fn formatted_timestamp() -> String {
-    chrono::Local::now()
+/// The timestamp format the models used before they carried a typed timestamp: local time with
+/// millisecond precision and a literal `Z`, whatever the local offset is.
+pub fn legacy_timestamp(at: DateTime<Utc>) -> String {
+    at.with_timezone(&Local)
         .format("%Y-%m-%dT%H:%M:%S%.3fZ")
         .to_string()
 }
+
+/// Matches `value` against a pattern where `*` stands for any run of characters.
+pub fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// The text of a JSON value: strings as-is, `null` as empty, anything else as JSON.
+pub fn json_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// Looks a field up at the top level of a serialized log, then in its `fields` map.
+pub fn log_field<'a>(doc: &'a Value, name: &str) -> Option<&'a Value> {
+    doc.get(name)
+        .or_else(|| doc.get("fields").and_then(|fields| fields.get(name)))
+}
+
+pub fn owned_headers(headers: &[(String, Vec<u8>)]) -> OwnedHeaders {
+    headers
+        .iter()
+        .fold(OwnedHeaders::new(), |owned, (name, value)| {
+            owned.insert(Header {
+                key: name,
+                value: Some(value),
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_match_without_a_star_is_equality() {
+        assert!(wildcard_match("web-1", "web-1"));
+        assert!(!wildcard_match("web-1", "web-10"));
+        assert!(!wildcard_match("web-1", "web-"));
+        assert!(wildcard_match("", ""));
+        assert!(!wildcard_match("", "web"));
+    }
+
+    #[test]
+    fn wildcard_match_stars() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("*", "anything"));
+        assert!(wildcard_match("**", "anything"));
+        assert!(wildcard_match("web-*", "web-"));
+        assert!(wildcard_match("web-*", "web-1"));
+        assert!(!wildcard_match("web-*", "db-1"));
+        assert!(wildcard_match("*-prod", "eu-prod"));
+        assert!(!wildcard_match("*-prod", "eu-prod-2"));
+        assert!(wildcard_match("web*prod", "web-eu-prod"));
+        assert!(wildcard_match("a*b*c", "abc"));
+        assert!(wildcard_match("a*b*c", "a-b-b-c"));
+        assert!(!wildcard_match("a*b*c", "acb"));
+    }
+
+    #[test]
+    fn wildcard_match_does_not_reuse_characters() {
+        // The prefix and suffix cannot overlap.
+        assert!(!wildcard_match("ab*ba", "aba"));
+        assert!(wildcard_match("ab*ba", "abba"));
+        assert!(!wildcard_match("a*a", "a"));
+        assert!(!wildcard_match("a*b*b", "ab"));
+    }
+
+    #[test]
+    fn log_field_falls_back_to_the_fields_map() {
+        let doc = serde_json::json!({"level": "INFO", "fields": {"service": "shop", "level": "x"}});
+        assert_eq!(log_field(&doc, "level"), Some(&Value::from("INFO")));
+        assert_eq!(log_field(&doc, "service"), Some(&Value::from("shop")));
+        assert_eq!(log_field(&doc, "missing"), None);
+    }
+}
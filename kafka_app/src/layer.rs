@@ -0,0 +1,351 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use chrono::Utc;
+use serde_json::{Map, Value};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::Layer;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::helper::get_hostname;
+use crate::models::{ErrorLog, InfoLog, LogContext, LogRecord, WarnLog};
+use crate::producer::KafkaProducer;
+
+/// Events from these targets, and their submodules, are never forwarded. They come from the
+/// clients the producer itself uses (librdkafka, and HTTP/gRPC for the schema registry and the
+/// receivers), which would otherwise log about their own traffic in a loop.
+const IGNORED_TARGETS: [&str; 8] = [
+    "rdkafka",
+    "hyper",
+    "hyper_util",
+    "h2",
+    "reqwest",
+    "tonic",
+    "tower",
+    "kafka_app::layer",
+];
+
+/// A `tracing` layer that turns events into log models and produces them to Kafka.
+///
+/// `ERROR` events become [`ErrorLog`], `WARN` events [`WarnLog`] and everything else [`InfoLog`].
+/// `trace_id` and `span_id` fields, on the event or an enclosing span, are also sent as headers.
+/// Events are handed to a background task over a bounded channel, so the caller never waits on
+/// the broker; when the channel is full the event is dropped and counted in the
+/// [`DroppedEvents`] that [`KafkaLayer::dropped_events`] returns. The task passes each event to
+/// [`KafkaProducer::enqueue`], so up to the producer's `max_in_flight` are delivered at once, and
+/// failures show in its metrics and [`KafkaProducer::take_report`].
+pub struct KafkaLayer {
+    sender: Sender<LogRecord>,
+    dropped: DroppedEvents,
+}
+
+/// Number of events a [`KafkaLayer`] dropped because its delivery task fell behind. Clones share
+/// the count, so it stays readable once the layer is moved into a subscriber.
+#[derive(Debug, Clone, Default)]
+pub struct DroppedEvents(Arc<AtomicU64>);
+
+impl DroppedEvents {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl KafkaLayer {
+    /// Spawns the delivery task on the current Tokio runtime.
+    pub fn spawn(producer: Arc<KafkaProducer>, capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity);
+        tokio::spawn(deliver(producer, receiver));
+
+        Self {
+            sender,
+            dropped: DroppedEvents::default(),
+        }
+    }
+
+    /// The layer's dropped-event count. Take it before handing the layer to a subscriber.
+    pub fn dropped_events(&self) -> DroppedEvents {
+        self.dropped.clone()
+    }
+}
+
+async fn deliver(producer: Arc<KafkaProducer>, mut receiver: Receiver<LogRecord>) {
+    while let Some(record) = receiver.recv().await {
+        producer.enqueue(record).await;
+    }
+}
+
+fn is_ignored(target: &str) -> bool {
+    IGNORED_TARGETS.iter().any(|ignored| {
+        target
+            .strip_prefix(ignored)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+    })
+}
+
+impl<S> Layer<S> for KafkaLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        span.extensions_mut().insert(SpanFields(visitor.fields));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(SpanFields(fields)) = extensions.get_mut::<SpanFields>() {
+            let mut visitor = FieldVisitor {
+                fields: std::mem::take(fields),
+                message: None,
+            };
+            values.record(&mut visitor);
+            *fields = visitor.fields;
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        if is_ignored(metadata.target()) {
+            return;
+        }
+
+        let mut context = LogContext {
+            target: Some(metadata.target().to_string()),
+            ..LogContext::default()
+        };
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                context.spans.push(span.name().to_string());
+                if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                    context
+                        .fields
+                        .extend(fields.iter().map(|(k, v)| (k.clone(), v.clone())));
+                }
+            }
+        }
+
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        context.fields.extend(visitor.fields);
+
+        let record = to_record(
+            *metadata.level(),
+            visitor.message.unwrap_or_default(),
+            context,
+        );
+        if self.sender.try_send(record).is_err() {
+            self.dropped.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn to_record(level: Level, message: String, mut context: LogContext) -> LogRecord {
+    let level_name = level.as_str().to_string();
+    match level {
+        Level::ERROR => {
+            let error_code = context
+                .fields
+                .remove("error_code")
+                .and_then(|v| v.as_u64())
+                .unwrap_or_default();
+            ErrorLog::new(level_name, message, get_hostname(), Utc::now(), error_code)
+                .with_context(context)
+                .into()
+        }
+        Level::WARN => {
+            let reason = match context.fields.remove("reason") {
+                Some(Value::String(reason)) => reason,
+                Some(other) => other.to_string(),
+                None => String::new(),
+            };
+            WarnLog::new(level_name, message, get_hostname(), Utc::now(), reason)
+                .with_context(context)
+                .into()
+        }
+        _ => InfoLog::new(level_name, message, get_hostname(), Utc::now())
+            .with_context(context)
+            .into(),
+    }
+}
+
+struct SpanFields(Map<String, Value>);
+
+#[derive(Default)]
+struct FieldVisitor {
+    fields: Map<String, Value>,
+    message: Option<String>,
+}
+
+impl FieldVisitor {
+    fn insert(&mut self, field: &Field, value: Value) {
+        if field.name() == "message" {
+            self.message = Some(match value {
+                Value::String(s) => s,
+                other => other.to_string(),
+            });
+        } else {
+            self.fields.insert(field.name().to_string(), value);
+        }
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, Value::from(format!("{:?}", value)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::models::{LogType, Loggable};
+
+    /// Runs `emit` under a subscriber with the layer and returns the records it forwarded.
+    fn records(emit: impl FnOnce()) -> Vec<LogRecord> {
+        let (sender, mut receiver) = mpsc::channel(16);
+        let layer = KafkaLayer {
+            sender,
+            dropped: DroppedEvents::default(),
+        };
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), emit);
+        let mut records = Vec::new();
+        while let Ok(record) = receiver.try_recv() {
+            records.push(record);
+        }
+        records
+    }
+
+    fn json(record: &LogRecord) -> Value {
+        serde_json::to_value(record).unwrap()
+    }
+
+    #[test]
+    fn counts_dropped_events_after_the_layer_is_moved() {
+        // Nothing drains the channel, so only the first event fits. Events from this module are
+        // ignored, hence the target.
+        let (sender, _receiver) = mpsc::channel(1);
+        let layer = KafkaLayer {
+            sender,
+            dropped: DroppedEvents::default(),
+        };
+        let dropped = layer.dropped_events();
+
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            for i in 0..3 {
+                tracing::info!(target: "app", i, "event");
+            }
+        });
+        assert_eq!(dropped.get(), 2);
+    }
+
+    #[test]
+    fn maps_levels_to_log_types() {
+        let records = records(|| {
+            tracing::trace!(target: "app", "tracing");
+            tracing::debug!(target: "app", "debugging");
+            tracing::info!(target: "app", "informing");
+            tracing::warn!(target: "app", reason = "disk", "warning");
+            tracing::error!(target: "app", error_code = 28u64, "failing");
+        });
+        let types: Vec<_> = records.iter().map(Loggable::log_type).collect();
+        assert_eq!(
+            types,
+            [
+                LogType::Info,
+                LogType::Info,
+                LogType::Info,
+                LogType::Warn,
+                LogType::Error
+            ]
+        );
+        let levels: Vec<_> = records.iter().map(|r| json(r)["level"].clone()).collect();
+        assert_eq!(levels, ["TRACE", "DEBUG", "INFO", "WARN", "ERROR"]);
+
+        let warn = json(&records[3]);
+        assert_eq!(warn["message"], "warning");
+        assert_eq!(warn["reason"], "disk");
+        assert!(warn.get("fields").is_none());
+        let error = json(&records[4]);
+        assert_eq!(error["error_code"], 28);
+        assert!(error.get("fields").is_none());
+    }
+
+    #[test]
+    fn carries_target_spans_and_fields() {
+        let records = records(|| {
+            let request = tracing::info_span!(target: "app", "request", request_id = "r-1");
+            let _request = request.enter();
+            let query =
+                tracing::info_span!(target: "app", "query", trace_id = tracing::field::Empty);
+            query.record("trace_id", "abc");
+            let _query = query.enter();
+            tracing::info!(target: "app::db", rows = 3, cached = false, "query done");
+        });
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].trace_id(), Some("abc"));
+
+        let log = json(&records[0]);
+        assert_eq!(log["message"], "query done");
+        assert_eq!(log["target"], "app::db");
+        assert_eq!(log["spans"], serde_json::json!(["request", "query"]));
+        assert_eq!(
+            log["fields"],
+            serde_json::json!({
+                "request_id": "r-1",
+                "trace_id": "abc",
+                "rows": 3,
+                "cached": false,
+            })
+        );
+    }
+
+    #[test]
+    fn ignores_the_producers_own_clients() {
+        let records = records(|| {
+            tracing::info!(target: "rdkafka::client", "ignored");
+            tracing::info!(target: "hyper::proto::h1", "ignored");
+            tracing::info!(target: "hyper_util::client", "ignored");
+            tracing::info!(target: "h2", "ignored");
+            tracing::info!(target: "tonic::transport", "ignored");
+            tracing::info!(target: "reqwest::connect", "ignored");
+            tracing::info!(target: "hyperion", "kept");
+        });
+        assert_eq!(records.len(), 1);
+        assert_eq!(json(&records[0])["target"], "hyperion");
+    }
+}
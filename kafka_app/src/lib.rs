@@ -0,0 +1,70 @@
+#[cfg(feature = "avro")]
+pub mod avro;
+pub mod backpressure;
+pub mod client;
+pub mod config;
+pub mod constant;
+pub mod encoding;
+pub mod helper;
+pub mod layer;
+pub mod lines;
+pub mod metrics;
+pub mod models;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod oversize;
+pub mod partition;
+pub mod producer;
+pub mod proto;
+pub mod provision;
+pub mod redact;
+pub mod routing;
+pub mod spool;
+pub mod state;
+pub mod syslog;
+pub mod tail;
+pub mod throttle;
+pub mod transaction;
+
+pub use log_multiline as multiline;
+
+pub use backpressure::{
+    BackpressureConfig, BackpressureMetrics, BackpressurePolicy, CircuitBreakerConfig, CircuitState,
+};
+pub use client::{ClientSettings, DeliveryGuarantee, SaslConfig, SaslMechanism, SslConfig};
+pub use config::{Config, ConfigBuilder};
+#[cfg(feature = "avro")]
+pub use encoding::PayloadDecoder;
+pub use encoding::{EncodingConfig, PayloadFormat, TimestampFormat};
+pub use kafka_app_derive::Loggable;
+pub use layer::{DroppedEvents, KafkaLayer};
+pub use lines::LineFormat;
+#[cfg(feature = "metrics-server")]
+pub use metrics::MetricsServer;
+pub use metrics::{DeliveryMetrics, LatencyHistogram, MetricsConfig, TopicMetrics};
+pub use models::{
+    ErrorLog, InfoLog, LogContext, LogRecord, LogType, Loggable, MissingField, WarnLog,
+};
+pub use multiline::{MultilineAggregator, MultilineConfig};
+#[cfg(feature = "otlp")]
+pub use otlp::{OtlpConfig, OtlpReceiver};
+pub use oversize::{OversizeConfig, OversizePolicy, Reassembler};
+pub use partition::{PartitionStrategy, PartitioningConfig};
+pub use producer::{
+    DeliveryReport, InnerProducer, KafkaProducer, Sent, ShutdownReport, TopicDeliveries,
+};
+pub use provision::{ProvisionReport, ProvisioningConfig, TopicDrift, TopicSpec, provision_topics};
+pub use redact::{Detector, RedactAction, RedactionConfig, RedactionMetrics, Redactor};
+pub use routing::{RoutingConfig, RoutingRule};
+pub use spool::{SpoolConfig, SpoolMetrics};
+pub use state::AppState;
+pub use syslog::{SyslogConfig, SyslogReceiver};
+pub use tail::{TailConfig, Tailer};
+pub use throttle::{RateLimitConfig, ThrottleConfig, ThrottleMetrics};
+pub use transaction::Transaction;
+
+/// Used by the code `#[derive(Loggable)]` generates.
+#[doc(hidden)]
+pub mod __private {
+    pub use chrono::{DateTime, Utc};
+}
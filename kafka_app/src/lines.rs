@@ -0,0 +1,168 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::helper::get_hostname;
+use crate::models::{ErrorLog, InfoLog, LogContext, LogRecord, LogType, WarnLog};
+
+pub use log_multiline::text_level;
+
+const LEVEL_KEYS: [&str; 3] = ["level", "severity", "lvl"];
+const MESSAGE_KEYS: [&str; 3] = ["message", "msg", "log"];
+const TIMESTAMP_KEYS: [&str; 4] = ["timestamp", "@timestamp", "time", "ts"];
+const HOSTNAME_KEYS: [&str; 2] = ["hostname", "host"];
+
+/// How a line of text written by another program is read.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LineFormat {
+    /// JSON when the line is a JSON object, plain text otherwise.
+    #[default]
+    Auto,
+    /// JSON objects. Lines that are not are still shipped, as plain text.
+    Json,
+    Text,
+}
+
+impl LineFormat {
+    pub fn parse(raw: &str) -> anyhow::Result<Self> {
+        match raw.to_ascii_lowercase().as_str() {
+            "auto" => Ok(LineFormat::Auto),
+            "json" => Ok(LineFormat::Json),
+            "text" => Ok(LineFormat::Text),
+            other => anyhow::bail!("unsupported line format {:?}", other),
+        }
+    }
+}
+
+/// Maps a level name from another logger onto a log type. Anything unknown is informational.
+pub fn level_type(level: &str) -> LogType {
+    let level = level.to_ascii_lowercase();
+    if ["err", "fatal", "crit", "alert", "emerg", "panic"]
+        .iter()
+        .any(|name| level.starts_with(name))
+    {
+        LogType::Error
+    } else if level.starts_with("warn") {
+        LogType::Warn
+    } else {
+        LogType::Info
+    }
+}
+
+/// Turns one line into a log model. `context` describes where the line came from; the fields
+/// of a JSON line that are not part of the model are added to it.
+///
+/// A multiline event (see [`crate::multiline`]) is parsed by its first line; the lines after a
+/// JSON first line, e.g. a stack trace, are appended to its message.
+pub fn parse_line(line: &str, format: LineFormat, context: LogContext) -> LogRecord {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let (first, rest) = match line.split_once('\n') {
+        Some((first, rest)) => (first.trim_end_matches('\r'), Some(rest)),
+        None => (line, None),
+    };
+    if format != LineFormat::Text
+        && first.trim_start().starts_with('{')
+        && let Ok(Value::Object(mut object)) = serde_json::from_str(first)
+    {
+        if let Some(rest) = rest {
+            let key = MESSAGE_KEYS
+                .iter()
+                .find(|key| object.contains_key(**key))
+                .unwrap_or(&MESSAGE_KEYS[0]);
+            let message = take_text(&mut object, &[key]).unwrap_or_default();
+            object.insert(
+                (*key).into(),
+                Value::String(format!("{}\n{}", message, rest)),
+            );
+        }
+        return from_json(object, context);
+    }
+    from_text(line, context)
+}
+
+fn from_json(mut object: Map<String, Value>, mut context: LogContext) -> LogRecord {
+    let level = take_text(&mut object, &LEVEL_KEYS).unwrap_or_else(|| "INFO".into());
+    let message = take_text(&mut object, &MESSAGE_KEYS).unwrap_or_default();
+    let hostname = take_text(&mut object, &HOSTNAME_KEYS).unwrap_or_else(get_hostname);
+    let timestamp = TIMESTAMP_KEYS
+        .iter()
+        .find_map(|key| {
+            object
+                .remove(*key)
+                .and_then(|value| parse_timestamp(&value))
+        })
+        .unwrap_or_else(Utc::now);
+
+    match level_type(&level) {
+        LogType::Error => {
+            let error_code = object
+                .remove("error_code")
+                .and_then(|value| value.as_u64())
+                .unwrap_or_default();
+            context.fields.extend(object);
+            ErrorLog::new(level, message, hostname, timestamp, error_code)
+                .with_context(context)
+                .into()
+        }
+        LogType::Warn => {
+            let reason = take_text(&mut object, &["reason"]).unwrap_or_default();
+            context.fields.extend(object);
+            WarnLog::new(level, message, hostname, timestamp, reason)
+                .with_context(context)
+                .into()
+        }
+        LogType::Info => {
+            context.fields.extend(object);
+            InfoLog::new(level, message, hostname, timestamp)
+                .with_context(context)
+                .into()
+        }
+    }
+}
+
+fn from_text(line: &str, context: LogContext) -> LogRecord {
+    // An unlabelled multiline event is almost always a stack trace.
+    let level = text_level(line)
+        .unwrap_or_else(|| if line.contains('\n') { "ERROR" } else { "INFO" }.into());
+    let message = line.to_string();
+    let hostname = get_hostname();
+    let timestamp = Utc::now();
+
+    match level_type(&level) {
+        LogType::Error => ErrorLog::new(level, message, hostname, timestamp, 0)
+            .with_context(context)
+            .into(),
+        LogType::Warn => WarnLog::new(level, message, hostname, timestamp, String::new())
+            .with_context(context)
+            .into(),
+        LogType::Info => InfoLog::new(level, message, hostname, timestamp)
+            .with_context(context)
+            .into(),
+    }
+}
+
+fn take_text(object: &mut Map<String, Value>, keys: &[&str]) -> Option<String> {
+    keys.iter().find_map(|key| match object.remove(*key)? {
+        Value::String(text) => Some(text),
+        Value::Null => None,
+        other => Some(other.to_string()),
+    })
+}
+
+/// RFC 3339 strings, or numbers of seconds (or milliseconds, when too large for seconds) since
+/// the epoch.
+fn parse_timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::String(raw) => raw.parse().ok(),
+        Value::Number(number) => {
+            let secs = number.as_f64()?;
+            if secs > 1e11 {
+                DateTime::from_timestamp_millis(secs as i64)
+            } else {
+                DateTime::from_timestamp_millis((secs * 1000.0) as i64)
+            }
+        }
+        _ => None,
+    }
+}
@@ -1,83 +1,201 @@
-mod config;
-mod constant;
-mod helper;
-mod models;
-mod producer;
-mod state;
+mod bench;
+
 use std::time::Instant;
 
-use crate::config::Config;
-use crate::models::{ErrorLog, InfoLog, WarnLog};
+use chrono::Utc;
+use clap::{Parser, Subcommand};
+#[cfg(feature = "metrics-server")]
+use kafka_app::MetricsServer;
+#[cfg(feature = "otlp")]
+use kafka_app::OtlpReceiver;
+use kafka_app::helper::{get_hostname, shutdown_signal};
+use kafka_app::{AppState, Config, SyslogReceiver, Tailer, WarnLog, provision_topics};
+
+use crate::bench::BenchArgs;
+
+/// Produces logs to Kafka. Settings come from `CONFIG_FILE` and the environment.
+#[derive(Parser)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Sends a small batch of sample logs (the default).
+    Demo,
+    /// Generates load and reports throughput and delivery latency.
+    Bench(BenchArgs),
+    /// Follows the files in the `[tail]` config section (or `TAIL_PATHS`) and produces each line.
+    Tail,
+    /// Receives syslog on the sockets in the `[syslog]` config section (or `SYSLOG_UDP_ADDR` /
+    /// `SYSLOG_TCP_ADDR`) and produces each message.
+    Syslog,
+    /// Serves OTLP/gRPC and OTLP/HTTP log endpoints from the `[otlp]` config section (or
+    /// `OTLP_GRPC_ADDR` / `OTLP_HTTP_ADDR`) and produces each log record.
+    #[cfg(feature = "otlp")]
+    Otlp,
+}
 
-use crate::helper::{formatted_timestamp, get_hostname};
-use crate::state::AppState;
-use tokio::time::{Duration, sleep};
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
 #[tokio::main]
-async fn main() -> anyhow::Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let cfg = Config::from_env_or_default();
+async fn main() -> anyhow::Result<(), BoxError> {
+    let cli = Cli::parse();
+    let mut cfg = Config::builder().with_env()?.build();
+    let command = cli.command.unwrap_or(Command::Demo);
+    if let Command::Bench(args) = &command
+        && let Some(compression) = &args.compression
+    {
+        cfg.client.compression = compression.clone();
+    }
+
+    if cfg.provisioning.is_some() {
+        let report = provision_topics(&cfg).await?;
+        for topic in &report.created {
+            println!("Created topic {}", topic);
+        }
+        for topic in &report.missing {
+            eprintln!("Topic {} does not exist", topic);
+        }
+        for drift in &report.drift {
+            eprintln!(
+                "Topic {} has {} = {}, configured {}",
+                drift.topic,
+                drift.setting,
+                drift.actual.as_deref().unwrap_or("(unset)"),
+                drift.declared
+            );
+        }
+    }
 
     let state = AppState::new(&cfg)?;
+    #[cfg(feature = "metrics-server")]
+    if let Some(metrics) = cfg.metrics.clone() {
+        let server = MetricsServer::new(state.producer.clone(), metrics);
+        tokio::spawn(async move {
+            if let Err(e) = server.run().await {
+                eprintln!("Metrics endpoint stopped: {:#}", e);
+            }
+        });
+    }
     println!("Application started.");
+    let work = async {
+        match command {
+            Command::Demo => demo(&state, &cfg).await,
+            Command::Bench(args) => bench::run(state.producer.clone(), args, cfg.send_timeout)
+                .await
+                .map_err(Into::into),
+            Command::Tail => {
+                let tail = cfg
+                    .tail
+                    .clone()
+                    .ok_or("tail needs a [tail] config section or TAIL_PATHS")?;
+                Tailer::new(state.producer.clone(), tail)?
+                    .run()
+                    .await
+                    .map_err(Into::into)
+            }
+            Command::Syslog => {
+                let syslog = cfg
+                    .syslog
+                    .clone()
+                    .ok_or("syslog needs a [syslog] config section or SYSLOG_UDP_ADDR")?;
+                SyslogReceiver::new(state.producer.clone(), syslog)?
+                    .run()
+                    .await
+                    .map_err(Into::into)
+            }
+            #[cfg(feature = "otlp")]
+            Command::Otlp => {
+                let otlp = cfg
+                    .otlp
+                    .clone()
+                    .ok_or("otlp needs an [otlp] config section or OTLP_GRPC_ADDR")?;
+                OtlpReceiver::new(state.producer.clone(), otlp)?
+                    .run()
+                    .await
+                    .map_err(Into::into)
+            }
+        }
+    };
+    // The producer is shut down however the work ended, so an error still flushes what was
+    // already accepted.
+    let result = tokio::select! {
+        result = work => result,
+        signal = shutdown_signal() => match signal {
+            Ok(()) => {
+                println!("Shutdown signal received, no longer accepting logs.");
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        },
+    };
+
+    let report = state.producer.shutdown(cfg.shutdown_timeout).await;
+    println!(
+        "Application finished. Undelivered: {}, still enqueued: {}, left in spool: {} bytes",
+        report.undelivered, report.pending_enqueued, report.spooled_bytes
+    );
+    result
+}
+
+async fn demo(state: &AppState, cfg: &Config) -> anyhow::Result<(), BoxError> {
     let num_messages_per_type = 10; // Send a large number of messages
-    let total_messages = num_messages_per_type * 3; // Info, Error, Warn
 
     let start_time = Instant::now();
-    let mut sent_count = 0;
 
+    let mut logs = Vec::with_capacity(num_messages_per_type * 3);
     for i in 0..num_messages_per_type {
-        let warn_log_1 = WarnLog::new(
-            "WARN".to_string(),
-            format!("Cache not found, using default. Warn #{}", i),
-            get_hostname(),
-            formatted_timestamp(),
-            "System Failure".to_string(),
-        );
-        
-        state.producer.send(warn_log_1, &cfg.send_timeout).await?;
-        sent_count += 1;
-        let warn_log_2 = WarnLog::new(
-            "WARN".to_string(),
-            format!("Cache not found, using default. Warn #{}", i),
-            get_hostname(),
-            formatted_timestamp(),
-            "System Failure".to_string(),
-        );
-
-        state.producer.send(warn_log_2, &cfg.send_timeout).await?;
-        sent_count += 1;
-
-        let warn_log = WarnLog::new(
-            "WARN".to_string(),
-            format!("Cache not found, using default. Warn #{}", i),
-            get_hostname(),
-            formatted_timestamp(),
-            "System Failure".to_string(),
-        );
-        state.producer.send(warn_log, &cfg.send_timeout).await?;
-        sent_count += 1;
-
-        if sent_count % 1000 == 0 {
-            println!("Sent {} messages...", sent_count);
+        for _ in 0..3 {
+            logs.push(WarnLog::new(
+                "WARN".to_string(),
+                format!("Cache not found, using default. Warn #{}", i),
+                get_hostname(),
+                Utc::now(),
+                "System Failure".to_string(),
+            ));
         }
     }
+    let sent_count = logs.len();
 
-    // Allow some time for final batches to be sent and delivered reports to arrive
-    // This is important because `linger.ms` means messages aren't sent immediately.
-    sleep(Duration::from_secs(5)).await;
+    // send_many keeps up to `max_in_flight` deliveries outstanding, so `linger.ms` can actually
+    // fill batches, and only returns once every delivery report has arrived.
+    let report = state.producer.send_many(logs, &cfg.send_timeout).await;
 
     let duration = start_time.elapsed();
     println!(
-        "\nFinished sending {} messages in {:?}. Average throughput: {:.2} msg/s",
+        "\nFinished sending {} messages in {:?}. Delivered: {}, spooled: {}, failed: {}, suppressed: {}, dropped: {}. Average throughput: {:.2} msg/s",
         sent_count,
         duration,
-        sent_count as f64 / duration.as_secs_f64()
+        report.delivered,
+        report.spooled,
+        report.failed,
+        report.suppressed,
+        report.dropped,
+        report.delivered as f64 / duration.as_secs_f64()
     );
+    for (topic, deliveries) in &report.topics {
+        println!(
+            "  {}: delivered {}, spooled {}, dropped {}, failed {}{}",
+            topic,
+            deliveries.delivered,
+            deliveries.spooled,
+            deliveries.dropped,
+            deliveries.failed,
+            deliveries
+                .last_error
+                .as_ref()
+                .map(|e| format!(" (last error: {})", e))
+                .unwrap_or_default()
+        );
+    }
+    if let Some(metrics) = state.producer.throttle_metrics() {
+        println!(
+            "  throttled: sampled out {}, rate limited {}, collapsed {}",
+            metrics.sampled_out, metrics.rate_limited, metrics.collapsed
+        );
+    }
 
-    // Give Kafka Connect some time to process if you're observing it simultaneously
-    println!("Producer finished. Waiting for Kafka Connect to process messages...");
-    sleep(Duration::from_secs(10)).await;
-
-    println!("Application finished.");
     Ok(())
 }
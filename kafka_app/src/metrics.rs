@@ -0,0 +1,733 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[cfg(feature = "metrics-server")]
+use anyhow::Context;
+#[cfg(feature = "metrics-server")]
+use axum::{
+    Router,
+    extract::State,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use rdkafka::ClientContext;
+use rdkafka::producer::Producer;
+use rdkafka::statistics::{Broker, Statistics};
+use serde::Deserialize;
+#[cfg(feature = "metrics-server")]
+use tokio::net::TcpListener;
+
+use crate::backpressure::CircuitState;
+use crate::producer::{KafkaProducer, Outcome};
+
+#[cfg(feature = "metrics-server")]
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+/// Upper bounds, in seconds, of the delivery latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The `[metrics]` section of the config file.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// Where to serve `/healthz`, `/readyz` and `/metrics`.
+    pub addr: String,
+    /// How often librdkafka reports its statistics, which `/metrics` shows the latest of.
+    pub statistics_interval_ms: u64,
+    /// How long `/readyz` waits for the broker metadata.
+    pub readiness_timeout_ms: u64,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            addr: "0.0.0.0:9464".into(),
+            statistics_interval_ms: 5000,
+            readiness_timeout_ms: 2000,
+        }
+    }
+}
+
+/// Delivery latency distribution, from handing a log to the producer to its acknowledgement.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyHistogram {
+    /// Deliveries per bucket of [`LATENCY_BUCKETS`], not cumulative. The last one counts those
+    /// slower than every bound.
+    pub buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    pub sum_secs: f64,
+    pub count: u64,
+}
+
+impl LatencyHistogram {
+    fn observe(&mut self, latency: Duration) {
+        let secs = latency.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum_secs += secs;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TopicMetrics {
+    pub delivered: u64,
+    pub spooled: u64,
+    pub dropped: u64,
+    pub failed: u64,
+    pub latency: LatencyHistogram,
+}
+
+/// Outcomes of every log a [`KafkaProducer`] was given since it was created.
+#[derive(Debug, Clone, Default)]
+pub struct DeliveryMetrics {
+    pub topics: BTreeMap<String, TopicMetrics>,
+    pub suppressed: u64,
+    /// Logs that failed before a topic was chosen, e.g. because they could not be serialized.
+    pub unrouted_failures: u64,
+}
+
+impl DeliveryMetrics {
+    pub(crate) fn observe(&mut self, outcome: &Outcome, latency: Duration) {
+        match outcome {
+            Outcome::Delivered { topic } => {
+                let topic = self.topic(topic);
+                topic.delivered += 1;
+                topic.latency.observe(latency);
+            }
+            Outcome::Spooled { topic } => self.topic(topic).spooled += 1,
+            Outcome::Suppressed => self.suppressed += 1,
+            Outcome::Dropped { topic } => self.topic(topic).dropped += 1,
+            Outcome::Failed {
+                topic: Some(topic), ..
+            } => self.topic(topic).failed += 1,
+            Outcome::Failed { topic: None, .. } => self.unrouted_failures += 1,
+        }
+    }
+
+    fn topic(&mut self, topic: &str) -> &mut TopicMetrics {
+        self.topics.entry(topic.to_string()).or_default()
+    }
+}
+
+/// Keeps the latest statistics librdkafka reports every `statistics.interval.ms`.
+#[derive(Default)]
+pub struct StatsContext {
+    latest: Arc<Mutex<Option<Statistics>>>,
+}
+
+impl StatsContext {
+    pub(crate) fn latest(&self) -> Arc<Mutex<Option<Statistics>>> {
+        self.latest.clone()
+    }
+}
+
+impl ClientContext for StatsContext {
+    fn stats(&self, statistics: Statistics) {
+        *self.latest.lock().unwrap_or_else(|e| e.into_inner()) = Some(statistics);
+    }
+}
+
+/// Serves `/healthz`, `/readyz` and `/metrics` for a [`KafkaProducer`].
+#[cfg(feature = "metrics-server")]
+#[derive(Clone)]
+pub struct MetricsServer {
+    cfg: MetricsConfig,
+    producer: Arc<KafkaProducer>,
+}
+
+#[cfg(feature = "metrics-server")]
+impl MetricsServer {
+    pub fn new(producer: Arc<KafkaProducer>, cfg: MetricsConfig) -> Self {
+        Self { cfg, producer }
+    }
+
+    /// Serves until the future is dropped or the server fails.
+    pub async fn run(self) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(&self.cfg.addr)
+            .await
+            .with_context(|| format!("binding metrics endpoint {}", self.cfg.addr))?;
+        let app = Router::new()
+            .route("/healthz", get(healthz))
+            .route("/readyz", get(readyz))
+            .route("/metrics", get(metrics))
+            .with_state(self);
+        axum::serve(listener, app)
+            .await
+            .context("serving metrics endpoint")
+    }
+}
+
+/// `GET /healthz`: the process is up and serving.
+#[cfg(feature = "metrics-server")]
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// `GET /readyz`: the producer accepts logs and the broker answers a metadata request.
+#[cfg(feature = "metrics-server")]
+async fn readyz(State(server): State<MetricsServer>) -> Response {
+    if server.producer.is_closed() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "shutting down").into_response();
+    }
+    let inner = server.producer.inner.clone();
+    let timeout = Duration::from_millis(server.cfg.readiness_timeout_ms);
+    let metadata =
+        tokio::task::spawn_blocking(move || inner.client().fetch_metadata(None, timeout).map(drop))
+            .await;
+    match metadata {
+        Ok(Ok(())) => "ready".into_response(),
+        Ok(Err(e)) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("broker unreachable: {}", e),
+        )
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// `GET /metrics` in the Prometheus text format.
+#[cfg(feature = "metrics-server")]
+async fn metrics(State(server): State<MetricsServer>) -> Response {
+    (
+        [(header::CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)],
+        render(&server.producer),
+    )
+        .into_response()
+}
+
+/// Prometheus exposition of the producer's delivery, queue, spool, redaction, throttle and
+/// backpressure metrics and of the latest librdkafka statistics.
+pub fn render(producer: &KafkaProducer) -> String {
+    let mut out = Exposition::default();
+    render_delivery(&mut out, &producer.delivery_metrics());
+
+    out.single(
+        "kafka_app_queued_messages",
+        "gauge",
+        "Messages in librdkafka's queue waiting to be delivered.",
+        producer.inner.in_flight_count(),
+    );
+    out.single(
+        "kafka_app_enqueued_pending",
+        "gauge",
+        "Enqueued logs whose delivery has not finished.",
+        producer.pending_enqueued(),
+    );
+
+    if let Some(spool) = producer.spool_metrics() {
+        out.single(
+            "kafka_app_spool_bytes",
+            "gauge",
+            "Spooled bytes waiting to be replayed.",
+            spool.spooled_bytes,
+        );
+        out.single(
+            "kafka_app_spooled_records_total",
+            "counter",
+            "Records written to the spool.",
+            spool.spooled_records,
+        );
+        out.single(
+            "kafka_app_spool_replayed_records_total",
+            "counter",
+            "Spooled records replayed to the broker.",
+            spool.replayed_records,
+        );
+        out.single(
+            "kafka_app_spool_dropped_records_total",
+            "counter",
+            "Records dropped because the spool was full.",
+            spool.dropped_records,
+        );
+        out.single(
+            "kafka_app_spool_discarded_records_total",
+            "counter",
+            "Spooled records discarded on replay as undecodable or rejected by the broker.",
+            spool.discarded_records,
+        );
+    }
+    if let Some(redaction) = producer.redaction_metrics() {
+        out.single(
+            "kafka_app_redacted_logs_total",
+            "counter",
+            "Logs with at least one redaction.",
+            redaction.redacted_logs,
+        );
+        out.family(
+            "kafka_app_redactions_total",
+            "counter",
+            "Redactions by rule.",
+        );
+        for (rule, count) in &redaction.redactions {
+            out.sample("kafka_app_redactions_total", &[("rule", rule)], count);
+        }
+    }
+    if let Some(throttle) = producer.throttle_metrics() {
+        out.family(
+            "kafka_app_throttled_total",
+            "counter",
+            "Logs held back by the throttle, by reason.",
+        );
+        for (reason, count) in [
+            ("sampled_out", throttle.sampled_out),
+            ("rate_limited", throttle.rate_limited),
+            ("collapsed", throttle.collapsed),
+        ] {
+            out.sample("kafka_app_throttled_total", &[("reason", reason)], count);
+        }
+    }
+    let backpressure = producer.backpressure_metrics();
+    out.family(
+        "kafka_app_backpressure_total",
+        "counter",
+        "Logs handled by a backpressure policy while librdkafka's queue was full, by action.",
+    );
+    for (action, count) in [
+        ("dropped_oldest", backpressure.dropped_oldest),
+        ("dropped_newest", backpressure.dropped_newest),
+        ("spilled", backpressure.spilled),
+        ("block_timeout", backpressure.block_timeouts),
+    ] {
+        out.sample("kafka_app_backpressure_total", &[("action", action)], count);
+    }
+    if let Some(circuit) = backpressure.circuit {
+        out.family(
+            "kafka_app_circuit_breaker_state",
+            "gauge",
+            "1 for the state the circuit breaker is in.",
+        );
+        for (state, name) in [
+            (CircuitState::Closed, "closed"),
+            (CircuitState::Open, "open"),
+            (CircuitState::HalfOpen, "half_open"),
+        ] {
+            out.sample(
+                "kafka_app_circuit_breaker_state",
+                &[("state", name)],
+                u8::from(circuit == state),
+            );
+        }
+        out.single(
+            "kafka_app_circuit_breaker_opened_total",
+            "counter",
+            "Times the circuit breaker opened.",
+            backpressure.circuit_opened,
+        );
+        out.single(
+            "kafka_app_circuit_breaker_fast_failed_total",
+            "counter",
+            "Sends refused while the circuit breaker was open.",
+            backpressure.fast_failed,
+        );
+    }
+    if let Some(statistics) = producer.statistics() {
+        render_statistics(&mut out, &statistics);
+    }
+    out.text
+}
+
+/// Outcome counters and the delivery latency histogram.
+fn render_delivery(out: &mut Exposition, delivery: &DeliveryMetrics) {
+    out.family(
+        "kafka_app_records_total",
+        "counter",
+        "Logs by topic and outcome.",
+    );
+    for (topic, metrics) in &delivery.topics {
+        for (outcome, value) in [
+            ("delivered", metrics.delivered),
+            ("spooled", metrics.spooled),
+            ("dropped", metrics.dropped),
+            ("failed", metrics.failed),
+        ] {
+            out.sample(
+                "kafka_app_records_total",
+                &[("topic", topic), ("outcome", outcome)],
+                value,
+            );
+        }
+    }
+    out.single(
+        "kafka_app_unrouted_failures_total",
+        "counter",
+        "Logs that failed before a topic was chosen.",
+        delivery.unrouted_failures,
+    );
+    out.single(
+        "kafka_app_suppressed_total",
+        "counter",
+        "Logs dropped by sampling or rate limiting, or collapsed into an identical log.",
+        delivery.suppressed,
+    );
+
+    out.family(
+        "kafka_app_delivery_latency_seconds",
+        "histogram",
+        "Time from handing a log to the producer to its acknowledgement.",
+    );
+    for (topic, metrics) in &delivery.topics {
+        let histogram = &metrics.latency;
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+            cumulative += count;
+            out.sample(
+                "kafka_app_delivery_latency_seconds_bucket",
+                &[("topic", topic), ("le", &bound.to_string())],
+                cumulative,
+            );
+        }
+        out.sample(
+            "kafka_app_delivery_latency_seconds_bucket",
+            &[("topic", topic), ("le", "+Inf")],
+            histogram.count,
+        );
+        out.sample(
+            "kafka_app_delivery_latency_seconds_sum",
+            &[("topic", topic)],
+            histogram.sum_secs,
+        );
+        out.sample(
+            "kafka_app_delivery_latency_seconds_count",
+            &[("topic", topic)],
+            histogram.count,
+        );
+    }
+}
+
+/// Name, type, help and value of a per-broker metric.
+type BrokerFamily = (&'static str, &'static str, &'static str, fn(&Broker) -> f64);
+
+/// The librdkafka statistics fields worth alerting on. Its latency windows are in microseconds.
+fn render_statistics(out: &mut Exposition, statistics: &Statistics) {
+    out.single(
+        "kafka_app_rdkafka_queue_bytes",
+        "gauge",
+        "Bytes in librdkafka's producer queue.",
+        statistics.msg_size,
+    );
+    out.single(
+        "kafka_app_rdkafka_queue_max_messages",
+        "gauge",
+        "Capacity of librdkafka's producer queue in messages.",
+        statistics.msg_max,
+    );
+    out.single(
+        "kafka_app_rdkafka_reply_queue",
+        "gauge",
+        "Events waiting to be served by librdkafka.",
+        statistics.replyq,
+    );
+    out.single(
+        "kafka_app_rdkafka_tx_messages_total",
+        "counter",
+        "Messages transmitted to brokers.",
+        statistics.txmsgs,
+    );
+    out.single(
+        "kafka_app_rdkafka_tx_message_bytes_total",
+        "counter",
+        "Message bytes transmitted to brokers.",
+        statistics.txmsg_bytes,
+    );
+
+    let brokers: BTreeMap<&String, _> = statistics.brokers.iter().collect();
+    let gauges: [BrokerFamily; 6] = [
+        (
+            "kafka_app_rdkafka_broker_up",
+            "gauge",
+            "1 when the connection to the broker is up.",
+            |broker| f64::from(u8::from(broker.state == "UP")),
+        ),
+        (
+            "kafka_app_rdkafka_broker_outbuf_messages",
+            "gauge",
+            "Messages waiting to be sent to the broker.",
+            |broker| broker.outbuf_msg_cnt as f64,
+        ),
+        (
+            "kafka_app_rdkafka_broker_rtt_seconds",
+            "gauge",
+            "Average round-trip time to the broker.",
+            |broker| broker.rtt.as_ref().map_or(0.0, |rtt| rtt.avg as f64 / 1e6),
+        ),
+        (
+            "kafka_app_rdkafka_broker_tx_errors_total",
+            "counter",
+            "Transmission errors.",
+            |broker| broker.txerrs as f64,
+        ),
+        (
+            "kafka_app_rdkafka_broker_tx_retries_total",
+            "counter",
+            "Request retries.",
+            |broker| broker.txretries as f64,
+        ),
+        (
+            "kafka_app_rdkafka_broker_request_timeouts_total",
+            "counter",
+            "Requests timed out.",
+            |broker| broker.req_timeouts as f64,
+        ),
+    ];
+    for (name, kind, help, value) in gauges {
+        out.family(name, kind, help);
+        for (broker_name, broker) in &brokers {
+            out.sample(name, &[("broker", broker_name)], value(broker));
+        }
+    }
+
+    let topics: BTreeMap<&String, _> = statistics.topics.iter().collect();
+    out.family(
+        "kafka_app_rdkafka_topic_batch_size_bytes",
+        "gauge",
+        "Average size of the batches sent for the topic.",
+    );
+    for (topic, stats) in &topics {
+        out.sample(
+            "kafka_app_rdkafka_topic_batch_size_bytes",
+            &[("topic", topic)],
+            stats.batchsize.avg,
+        );
+    }
+    out.family(
+        "kafka_app_rdkafka_topic_batch_messages",
+        "gauge",
+        "Average number of messages in the batches sent for the topic.",
+    );
+    for (topic, stats) in &topics {
+        out.sample(
+            "kafka_app_rdkafka_topic_batch_messages",
+            &[("topic", topic)],
+            stats.batchcnt.avg,
+        );
+    }
+}
+
+/// Builds a Prometheus text exposition.
+#[derive(Default)]
+struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+                .collect();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {}", value);
+    }
+
+    /// A family with one unlabelled sample.
+    fn single(&mut self, name: &str, kind: &str, help: &str, value: impl std::fmt::Display) {
+        self.family(name, kind, help);
+        self.sample(name, &[], value);
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backpressure::{BackpressureConfig, CircuitBreakerConfig};
+    use crate::config::Config;
+    use crate::redact::RedactionConfig;
+    use crate::spool::SpoolConfig;
+    use crate::throttle::ThrottleConfig;
+
+    /// The value of the sample with exactly this name and label set.
+    fn value(text: &str, series: &str) -> f64 {
+        text.lines()
+            .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("no {} in\n{}", series, text))
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn latency_buckets_are_cumulative() {
+        let mut delivery = DeliveryMetrics::default();
+        for latency in [
+            Duration::from_micros(500),
+            Duration::from_millis(20),
+            Duration::from_secs(20),
+        ] {
+            let topic = "logs".to_string();
+            delivery.observe(&Outcome::Delivered { topic }, latency);
+        }
+        let mut out = Exposition::default();
+        render_delivery(&mut out, &delivery);
+        let text = out.text;
+
+        let bucket = |le: &str| {
+            value(
+                &text,
+                &format!(
+                    "kafka_app_delivery_latency_seconds_bucket{{topic=\"logs\",le=\"{}\"}}",
+                    le
+                ),
+            )
+        };
+        assert_eq!(bucket("0.001"), 1.0);
+        assert_eq!(bucket("0.01"), 1.0);
+        assert_eq!(bucket("0.025"), 2.0);
+        assert_eq!(bucket("10"), 2.0);
+        assert_eq!(bucket("+Inf"), 3.0);
+        let sum = value(
+            &text,
+            "kafka_app_delivery_latency_seconds_sum{topic=\"logs\"}",
+        );
+        assert!((sum - 20.0205).abs() < 1e-9, "{}", sum);
+        assert_eq!(
+            value(
+                &text,
+                "kafka_app_delivery_latency_seconds_count{topic=\"logs\"}"
+            ),
+            3.0
+        );
+        assert!(text.contains("# TYPE kafka_app_delivery_latency_seconds histogram\n"));
+        // One bucket per bound and one for +Inf.
+        let buckets = text
+            .lines()
+            .filter(|line| line.starts_with("kafka_app_delivery_latency_seconds_bucket"))
+            .count();
+        assert_eq!(buckets, LATENCY_BUCKETS.len() + 1);
+    }
+
+    #[test]
+    fn outcomes_are_counted_by_topic() {
+        let mut delivery = DeliveryMetrics::default();
+        let latency = Duration::from_millis(1);
+        delivery.observe(&Outcome::Spooled { topic: "a".into() }, latency);
+        delivery.observe(&Outcome::Dropped { topic: "a".into() }, latency);
+        delivery.observe(
+            &Outcome::Failed {
+                topic: Some("b".into()),
+                error: "boom".into(),
+            },
+            latency,
+        );
+        delivery.observe(
+            &Outcome::Failed {
+                topic: None,
+                error: "unserializable".into(),
+            },
+            latency,
+        );
+        delivery.observe(&Outcome::Suppressed, latency);
+        let mut out = Exposition::default();
+        render_delivery(&mut out, &delivery);
+        let text = out.text;
+
+        let records = |topic: &str, outcome: &str| {
+            value(
+                &text,
+                &format!(
+                    "kafka_app_records_total{{topic=\"{}\",outcome=\"{}\"}}",
+                    topic, outcome
+                ),
+            )
+        };
+        assert_eq!(records("a", "spooled"), 1.0);
+        assert_eq!(records("a", "dropped"), 1.0);
+        assert_eq!(records("a", "delivered"), 0.0);
+        assert_eq!(records("b", "failed"), 1.0);
+        assert_eq!(value(&text, "kafka_app_unrouted_failures_total"), 1.0);
+        assert_eq!(value(&text, "kafka_app_suppressed_total"), 1.0);
+        // Nothing was delivered, so the histograms are empty but present.
+        assert_eq!(
+            value(
+                &text,
+                "kafka_app_delivery_latency_seconds_count{topic=\"b\"}"
+            ),
+            0.0
+        );
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        let mut out = Exposition::default();
+        out.sample("m", &[("topic", "a\"b\\c\nd")], 1);
+        assert_eq!(out.text, "m{topic=\"a\\\"b\\\\c\\nd\"} 1\n");
+    }
+
+    fn rendered(cfg: Config) -> String {
+        render(&KafkaProducer::new(&cfg).unwrap())
+    }
+
+    #[tokio::test]
+    async fn optional_families_follow_the_config() {
+        let families = [
+            "kafka_app_spool_bytes",
+            "kafka_app_redacted_logs_total",
+            "kafka_app_throttled_total",
+            "kafka_app_circuit_breaker_state",
+        ];
+        let text = rendered(Config::builder().broker("127.0.0.1:1").build());
+        for family in families {
+            assert!(!text.contains(family), "{} in\n{}", family, text);
+        }
+        // Always present. librdkafka's queue may already hold a metadata request.
+        value(&text, "kafka_app_queued_messages");
+        assert_eq!(
+            value(&text, "kafka_app_backpressure_total{action=\"spilled\"}"),
+            0.0
+        );
+        assert!(!text.contains("kafka_app_rdkafka_"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let text = rendered(
+            Config::builder()
+                .broker("127.0.0.1:1")
+                .spool(SpoolConfig {
+                    dir: dir.path().to_path_buf(),
+                    ..SpoolConfig::default()
+                })
+                .redaction(RedactionConfig::default())
+                .throttle(ThrottleConfig::default())
+                .backpressure(BackpressureConfig {
+                    circuit_breaker: Some(CircuitBreakerConfig::default()),
+                    ..BackpressureConfig::default()
+                })
+                .build(),
+        );
+        for family in families {
+            assert!(
+                text.contains(&format!("# TYPE {} ", family)),
+                "{} missing from\n{}",
+                family,
+                text
+            );
+        }
+        assert_eq!(
+            value(&text, "kafka_app_circuit_breaker_state{state=\"closed\"}"),
+            1.0
+        );
+        assert_eq!(
+            value(&text, "kafka_app_circuit_breaker_state{state=\"open\"}"),
+            0.0
+        );
+    }
+}
@@ -1,41 +1,181 @@
-use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
 
-use crate::constant::{ERROR_TOPIC, INFO_TOPIC, WARN_TOPIC};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
+/// A log record that can be shipped through [`crate::producer::KafkaProducer`].
+///
+/// The topic is not chosen by the record itself: the producer routes on [`Loggable::log_type`]
+/// using [`crate::routing::RoutingConfig`].
 pub trait Loggable: Serialize + Send + Sync + 'static {
-    fn topic(&self) -> &'static str;
+    fn log_type(&self) -> LogType;
+
+    /// When the event happened. Used as the Kafka record timestamp; without it the producer's
+    /// clock is used.
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        None
+    }
+
+    /// Id of the trace the log belongs to, sent as the `trace-id` header.
+    fn trace_id(&self) -> Option<&str> {
+        None
+    }
+
+    /// Id of the span that emitted the log, sent as the `span-id` header.
+    fn span_id(&self) -> Option<&str> {
+        None
+    }
+
+    /// Extra record headers, sent after the ones the producer sets.
+    fn headers(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    /// Topic the log always goes to, before the routing prefix is added. Routing rules are
+    /// skipped when set.
+    fn topic(&self) -> Option<&str> {
+        None
+    }
+
+    /// Partition key, used instead of the topic's [`crate::partition::PartitionStrategy`].
+    fn key(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Whether the log has the fields of [`InfoLog`], [`WarnLog`] or [`ErrorLog`], which the
+    /// Avro and Protobuf schemas are written for. Other logs can only be produced as JSON.
+    fn fits_log_schema(&self) -> bool {
+        true
+    }
+}
+
+/// A field the builder of a `#[derive(Loggable)]` struct needs was never set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissingField(pub &'static str);
+
+impl fmt::Display for MissingField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "missing field {}", self.0)
+    }
+}
+
+impl std::error::Error for MissingField {}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum LogType {
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogType::Info => "info",
+            LogType::Warn => "warn",
+            LogType::Error => "error",
+        }
+    }
 }
+
+impl fmt::Display for LogType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Where a log came from: the emitting target, the enclosing spans and any extra fields.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LogContext {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spans: Vec<String>,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub fields: Map<String, Value>,
+}
+
+impl LogContext {
+    fn field_str(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).and_then(Value::as_str)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct InfoLog {
     level: String,
     message: String,
     hostname: String,
-    timestamp: String,
+    timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    context: LogContext,
+    #[serde(skip)]
+    headers: BTreeMap<String, String>,
 }
 
 impl InfoLog {
-    pub fn new(level: String, message: String, hostname: String, timestamp: String) -> Self {
+    pub fn new(level: String, message: String, hostname: String, timestamp: DateTime<Utc>) -> Self {
         Self {
             level,
             message,
             hostname,
             timestamp,
+            context: LogContext::default(),
+            headers: BTreeMap::new(),
         }
     }
+
+    pub fn with_context(mut self, context: LogContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Adds a record header. It is not part of the payload.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
 }
 
 impl Loggable for InfoLog {
-    fn topic(&self) -> &'static str {
-        INFO_TOPIC
+    fn log_type(&self) -> LogType {
+        LogType::Info
+    }
+
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        Some(self.timestamp)
+    }
+
+    fn trace_id(&self) -> Option<&str> {
+        self.context.field_str("trace_id")
+    }
+
+    fn span_id(&self) -> Option<&str> {
+        self.context.field_str("span_id")
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        self.headers
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
     }
 }
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ErrorLog {
     level: String,
     message: String,
     hostname: String,
-    timestamp: String,
+    timestamp: DateTime<Utc>,
     error_code: u64,
+    #[serde(flatten)]
+    context: LogContext,
+    #[serde(skip)]
+    headers: BTreeMap<String, String>,
 }
 
 impl ErrorLog {
@@ -43,7 +183,7 @@ impl ErrorLog {
         level: String,
         message: String,
         hostname: String,
-        timestamp: String,
+        timestamp: DateTime<Utc>,
         error_code: u64,
     ) -> Self {
         Self {
@@ -52,24 +192,59 @@ impl ErrorLog {
             hostname,
             timestamp,
             error_code,
+            context: LogContext::default(),
+            headers: BTreeMap::new(),
         }
     }
+
+    pub fn with_context(mut self, context: LogContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Adds a record header. It is not part of the payload.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
 }
 
 impl Loggable for ErrorLog {
-    fn topic(&self) -> &'static str {
-        ERROR_TOPIC
+    fn log_type(&self) -> LogType {
+        LogType::Error
+    }
+
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        Some(self.timestamp)
+    }
+
+    fn trace_id(&self) -> Option<&str> {
+        self.context.field_str("trace_id")
+    }
+
+    fn span_id(&self) -> Option<&str> {
+        self.context.field_str("span_id")
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        self.headers
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-
 pub struct WarnLog {
     level: String,
     message: String,
     hostname: String,
-    timestamp: String,
+    timestamp: DateTime<Utc>,
     reason: String,
+    #[serde(flatten)]
+    context: LogContext,
+    #[serde(skip)]
+    headers: BTreeMap<String, String>,
 }
 
 impl WarnLog {
@@ -77,7 +252,7 @@ impl WarnLog {
         level: String,
         message: String,
         hostname: String,
-        timestamp: String,
+        timestamp: DateTime<Utc>,
         reason: String,
     ) -> Self {
         Self {
@@ -86,12 +261,129 @@ impl WarnLog {
             hostname,
             timestamp,
             reason,
+            context: LogContext::default(),
+            headers: BTreeMap::new(),
         }
     }
+
+    pub fn with_context(mut self, context: LogContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Adds a record header. It is not part of the payload.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
 }
 
 impl Loggable for WarnLog {
-    fn topic(&self) -> &'static str {
-        WARN_TOPIC
+    fn log_type(&self) -> LogType {
+        LogType::Warn
+    }
+
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        Some(self.timestamp)
+    }
+
+    fn trace_id(&self) -> Option<&str> {
+        self.context.field_str("trace_id")
+    }
+
+    fn span_id(&self) -> Option<&str> {
+        self.context.field_str("span_id")
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        self.headers
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+}
+
+/// Any of the three models, for inputs that only know the log type at runtime.
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum LogRecord {
+    Info(InfoLog),
+    Warn(WarnLog),
+    Error(ErrorLog),
+}
+
+impl Loggable for LogRecord {
+    fn log_type(&self) -> LogType {
+        match self {
+            LogRecord::Info(log) => log.log_type(),
+            LogRecord::Warn(log) => log.log_type(),
+            LogRecord::Error(log) => log.log_type(),
+        }
+    }
+
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        match self {
+            LogRecord::Info(log) => log.timestamp(),
+            LogRecord::Warn(log) => log.timestamp(),
+            LogRecord::Error(log) => log.timestamp(),
+        }
+    }
+
+    fn trace_id(&self) -> Option<&str> {
+        match self {
+            LogRecord::Info(log) => log.trace_id(),
+            LogRecord::Warn(log) => log.trace_id(),
+            LogRecord::Error(log) => log.trace_id(),
+        }
+    }
+
+    fn span_id(&self) -> Option<&str> {
+        match self {
+            LogRecord::Info(log) => log.span_id(),
+            LogRecord::Warn(log) => log.span_id(),
+            LogRecord::Error(log) => log.span_id(),
+        }
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        match self {
+            LogRecord::Info(log) => log.headers(),
+            LogRecord::Warn(log) => log.headers(),
+            LogRecord::Error(log) => log.headers(),
+        }
+    }
+
+    fn topic(&self) -> Option<&str> {
+        match self {
+            LogRecord::Info(log) => log.topic(),
+            LogRecord::Warn(log) => log.topic(),
+            LogRecord::Error(log) => log.topic(),
+        }
+    }
+
+    fn key(&self) -> Option<Vec<u8>> {
+        match self {
+            LogRecord::Info(log) => log.key(),
+            LogRecord::Warn(log) => log.key(),
+            LogRecord::Error(log) => log.key(),
+        }
+    }
+}
+
+impl From<InfoLog> for LogRecord {
+    fn from(log: InfoLog) -> Self {
+        LogRecord::Info(log)
+    }
+}
+
+impl From<WarnLog> for LogRecord {
+    fn from(log: WarnLog) -> Self {
+        LogRecord::Warn(log)
+    }
+}
+
+impl From<ErrorLog> for LogRecord {
+    fn from(log: ErrorLog) -> Self {
+        LogRecord::Error(log)
     }
 }
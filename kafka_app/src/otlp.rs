@@ -0,0 +1,774 @@
+use std::io::Read;
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use opentelemetry_proto::tonic::collector::logs::v1::logs_service_server::{
+    LogsService, LogsServiceServer,
+};
+use opentelemetry_proto::tonic::collector::logs::v1::{
+    ExportLogsPartialSuccess, ExportLogsServiceRequest, ExportLogsServiceResponse,
+};
+use opentelemetry_proto::tonic::common::v1::any_value::Value as OtlpValue;
+use opentelemetry_proto::tonic::common::v1::{AnyValue, KeyValue};
+use opentelemetry_proto::tonic::logs::v1::LogRecord as OtlpRecord;
+use prost::Message;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio::net::TcpListener;
+use tonic::transport::Server;
+use tonic::transport::server::TcpIncoming;
+
+use crate::helper::get_hostname;
+use crate::lines::level_type;
+use crate::models::{ErrorLog, InfoLog, LogContext, LogRecord, LogType, WarnLog};
+use crate::producer::{DeliveryReport, KafkaProducer};
+
+const LOGS_PATH: &str = "/v1/logs";
+const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+const JSON_CONTENT_TYPE: &str = "application/json";
+/// The most a gzip request body may inflate to, the same as axum's default body limit.
+const MAX_DECODED_BYTES: u64 = 2 * 1024 * 1024;
+/// The resource attribute OpenTelemetry SDKs put the machine name in.
+const HOST_NAME_ATTRIBUTE: &str = "host.name";
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct OtlpConfig {
+    /// Where to serve OTLP/gRPC, usually `0.0.0.0:4317`. Not served when unset.
+    pub grpc_addr: Option<String>,
+    /// Where to serve OTLP/HTTP (`POST /v1/logs`, protobuf or JSON), usually `0.0.0.0:4318`.
+    /// Not served when unset.
+    pub http_addr: Option<String>,
+}
+
+/// Converts the log records of an export request into log models.
+///
+/// The severity number picks the log type, and the severity text, when set, is kept as the
+/// level. Attributes become context fields, resource attributes go under `resource`, the scope
+/// name becomes the target, and trace and span ids are kept as hex in `trace_id` / `span_id`.
+pub fn convert_request(request: ExportLogsServiceRequest) -> Vec<LogRecord> {
+    let mut records = Vec::new();
+    for resource_logs in request.resource_logs {
+        let resource = resource_logs
+            .resource
+            .map(|resource| attributes(resource.attributes))
+            .unwrap_or_default();
+        let hostname = resource
+            .get(HOST_NAME_ATTRIBUTE)
+            .and_then(Value::as_str)
+            .map(String::from)
+            .unwrap_or_else(get_hostname);
+        for scope_logs in resource_logs.scope_logs {
+            let target = scope_logs
+                .scope
+                .map(|scope| scope.name)
+                .filter(|name| !name.is_empty());
+            for record in scope_logs.log_records {
+                let context = LogContext {
+                    target: target.clone(),
+                    ..LogContext::default()
+                };
+                records.push(convert_record(record, &resource, &hostname, context));
+            }
+        }
+    }
+    records
+}
+
+fn convert_record(
+    record: OtlpRecord,
+    resource: &Map<String, Value>,
+    hostname: &str,
+    mut context: LogContext,
+) -> LogRecord {
+    let severity = severity_level(record.severity_number);
+    let log_type = match severity {
+        Some(level) => level_type(level),
+        None => level_type(&record.severity_text),
+    };
+    let level = if record.severity_text.is_empty() {
+        severity.unwrap_or("INFO").to_string()
+    } else {
+        record.severity_text
+    };
+    let message = match record.body.and_then(|body| body.value) {
+        Some(OtlpValue::StringValue(text)) => text,
+        Some(other) => any_value(other).to_string(),
+        None => String::new(),
+    };
+    let timestamp = [record.time_unix_nano, record.observed_time_unix_nano]
+        .into_iter()
+        .find(|nanos| *nanos > 0)
+        .map(|nanos| DateTime::from_timestamp_nanos(nanos as i64))
+        .unwrap_or_else(Utc::now);
+
+    let mut fields = attributes(record.attributes);
+    if !resource.is_empty() {
+        fields.insert("resource".into(), Value::Object(resource.clone()));
+    }
+    if !record.event_name.is_empty() {
+        fields.insert("event_name".into(), Value::String(record.event_name));
+    }
+    for (name, id) in [("trace_id", record.trace_id), ("span_id", record.span_id)] {
+        if id.iter().any(|byte| *byte != 0) {
+            fields.insert(name.into(), Value::String(hex(&id)));
+        }
+    }
+    let hostname = hostname.to_string();
+
+    match log_type {
+        LogType::Error => {
+            let error_code = fields
+                .remove("error_code")
+                .and_then(|value| value.as_u64())
+                .unwrap_or_default();
+            context.fields = fields;
+            ErrorLog::new(level, message, hostname, timestamp, error_code)
+                .with_context(context)
+                .into()
+        }
+        LogType::Warn => {
+            let reason = match fields.remove("reason") {
+                Some(Value::String(reason)) => reason,
+                _ => String::new(),
+            };
+            context.fields = fields;
+            WarnLog::new(level, message, hostname, timestamp, reason)
+                .with_context(context)
+                .into()
+        }
+        LogType::Info => {
+            context.fields = fields;
+            InfoLog::new(level, message, hostname, timestamp)
+                .with_context(context)
+                .into()
+        }
+    }
+}
+
+/// The level name of an OTLP severity number, or `None` when it is unspecified.
+fn severity_level(number: i32) -> Option<&'static str> {
+    match number {
+        1..=4 => Some("TRACE"),
+        5..=8 => Some("DEBUG"),
+        9..=12 => Some("INFO"),
+        13..=16 => Some("WARN"),
+        17..=20 => Some("ERROR"),
+        21..=24 => Some("FATAL"),
+        _ => None,
+    }
+}
+
+fn attributes(attributes: Vec<KeyValue>) -> Map<String, Value> {
+    attributes
+        .into_iter()
+        .map(|attribute| {
+            let value = attribute
+                .value
+                .and_then(|value| value.value)
+                .map_or(Value::Null, any_value);
+            (attribute.key, value)
+        })
+        .collect()
+}
+
+fn any_value(value: OtlpValue) -> Value {
+    match value {
+        OtlpValue::StringValue(text) => Value::String(text),
+        OtlpValue::BoolValue(flag) => Value::Bool(flag),
+        OtlpValue::IntValue(number) => Value::from(number),
+        OtlpValue::DoubleValue(number) => Value::from(number),
+        OtlpValue::ArrayValue(array) => Value::Array(
+            array
+                .values
+                .into_iter()
+                .map(|AnyValue { value }| value.map_or(Value::Null, any_value))
+                .collect(),
+        ),
+        OtlpValue::KvlistValue(list) => Value::Object(attributes(list.values)),
+        OtlpValue::BytesValue(bytes) => Value::String(hex(&bytes)),
+        // Only meaningful in profiles, which are not received here.
+        OtlpValue::StringValueStrindex(_) => Value::Null,
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Serves the endpoints in an [`OtlpConfig`] and produces every log record received.
+#[derive(Clone)]
+pub struct OtlpReceiver {
+    cfg: OtlpConfig,
+    producer: Arc<KafkaProducer>,
+}
+
+impl OtlpReceiver {
+    pub fn new(producer: Arc<KafkaProducer>, cfg: OtlpConfig) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            cfg.grpc_addr.is_some() || cfg.http_addr.is_some(),
+            "otlp needs a gRPC or HTTP address"
+        );
+        Ok(Self { cfg, producer })
+    }
+
+    /// Serves until the future is dropped or a server fails.
+    pub async fn run(self) -> anyhow::Result<()> {
+        let grpc = match &self.cfg.grpc_addr {
+            Some(addr) => Some(
+                TcpListener::bind(addr)
+                    .await
+                    .with_context(|| format!("binding OTLP gRPC {}", addr))?,
+            ),
+            None => None,
+        };
+        let http = match &self.cfg.http_addr {
+            Some(addr) => Some(
+                TcpListener::bind(addr)
+                    .await
+                    .with_context(|| format!("binding OTLP HTTP {}", addr))?,
+            ),
+            None => None,
+        };
+
+        let grpc = async {
+            match grpc {
+                Some(listener) => Server::builder()
+                    .add_service(LogsServiceServer::new(self.clone()))
+                    .serve_with_incoming(TcpIncoming::from(listener))
+                    .await
+                    .context("serving OTLP gRPC"),
+                None => Ok(()),
+            }
+        };
+        let http = async {
+            match http {
+                Some(listener) => {
+                    let app = Router::new()
+                        .route(LOGS_PATH, post(export_http))
+                        .with_state(self.clone());
+                    axum::serve(listener, app)
+                        .await
+                        .context("serving OTLP HTTP")
+                }
+                None => Ok(()),
+            }
+        };
+        tokio::try_join!(grpc, http)?;
+        Ok(())
+    }
+
+    /// Produces the records and waits for their delivery, so that records the producer could
+    /// neither deliver nor spool are reported back as rejected. When none of them could be
+    /// accepted the error is returned instead, so the client retries the whole request.
+    async fn export(
+        &self,
+        request: ExportLogsServiceRequest,
+    ) -> Result<ExportLogsServiceResponse, String> {
+        let records = convert_request(request);
+        let report = self
+            .producer
+            .send_many(records, &self.producer.send_timeout())
+            .await;
+        if report.failed > 0
+            && report.delivered + report.spooled + report.suppressed + report.dropped == 0
+        {
+            return Err(last_error(&report));
+        }
+        Ok(ExportLogsServiceResponse {
+            partial_success: partial_success(&report),
+        })
+    }
+}
+
+fn last_error(report: &DeliveryReport) -> String {
+    report
+        .topics
+        .values()
+        .find_map(|deliveries| deliveries.last_error.clone())
+        .unwrap_or_else(|| "delivery to Kafka failed".into())
+}
+
+fn partial_success(report: &DeliveryReport) -> Option<ExportLogsPartialSuccess> {
+    if report.failed == 0 {
+        return None;
+    }
+    Some(ExportLogsPartialSuccess {
+        rejected_log_records: report.failed as i64,
+        error_message: last_error(report),
+    })
+}
+
+#[tonic::async_trait]
+impl LogsService for OtlpReceiver {
+    async fn export(
+        &self,
+        request: tonic::Request<ExportLogsServiceRequest>,
+    ) -> Result<tonic::Response<ExportLogsServiceResponse>, tonic::Status> {
+        OtlpReceiver::export(self, request.into_inner())
+            .await
+            .map(tonic::Response::new)
+            .map_err(tonic::Status::unavailable)
+    }
+}
+
+/// `POST /v1/logs`. The body may be gzip-compressed, and the response uses the encoding of the
+/// request.
+async fn export_http(
+    State(receiver): State<OtlpReceiver>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or(PROTOBUF_CONTENT_TYPE);
+    let json = if content_type.starts_with(JSON_CONTENT_TYPE) {
+        true
+    } else if content_type.starts_with(PROTOBUF_CONTENT_TYPE) {
+        false
+    } else {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("unsupported content type {}", content_type),
+        )
+            .into_response();
+    };
+    let body = match decode_body(&headers, body) {
+        Ok(body) => body,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    let request = if json {
+        serde_json::from_slice(&body).map_err(|e| e.to_string())
+    } else {
+        ExportLogsServiceRequest::decode(body).map_err(|e| e.to_string())
+    };
+    let request = match request {
+        Ok(request) => request,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let response = match receiver.export(request).await {
+        Ok(response) => response,
+        Err(e) => return (StatusCode::SERVICE_UNAVAILABLE, e).into_response(),
+    };
+    if json {
+        match serde_json::to_vec(&response) {
+            Ok(json) => ([(header::CONTENT_TYPE, JSON_CONTENT_TYPE)], json).into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }
+    } else {
+        (
+            [(header::CONTENT_TYPE, PROTOBUF_CONTENT_TYPE)],
+            response.encode_to_vec(),
+        )
+            .into_response()
+    }
+}
+
+/// Undoes the `Content-Encoding` of a request body; only gzip is supported.
+fn decode_body(headers: &HeaderMap, body: Bytes) -> Result<Bytes, (StatusCode, String)> {
+    let encoding = headers
+        .get(header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .unwrap_or_default();
+    if encoding.is_empty() || encoding.eq_ignore_ascii_case("identity") {
+        return Ok(body);
+    }
+    if !encoding.eq_ignore_ascii_case("gzip") {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("unsupported content encoding {}", encoding),
+        ));
+    }
+    let mut decoded = Vec::new();
+    GzDecoder::new(&body[..])
+        .take(MAX_DECODED_BYTES + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid gzip body: {}", e)))?;
+    if decoded.len() as u64 > MAX_DECODED_BYTES {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("body inflates past {} bytes", MAX_DECODED_BYTES),
+        ));
+    }
+    Ok(decoded.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use opentelemetry_proto::tonic::common::v1::{ArrayValue, InstrumentationScope};
+    use opentelemetry_proto::tonic::logs::v1::{ResourceLogs, ScopeLogs};
+    use opentelemetry_proto::tonic::resource::v1::Resource;
+    use rdkafka::mocking::MockCluster;
+    use rdkafka::producer::DefaultProducerContext;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::models::Loggable;
+    use crate::oversize::{OversizeConfig, OversizePolicy};
+
+    fn string(value: &str) -> Option<AnyValue> {
+        Some(AnyValue {
+            value: Some(OtlpValue::StringValue(value.into())),
+        })
+    }
+
+    fn attribute(key: &str, value: OtlpValue) -> KeyValue {
+        KeyValue {
+            key: key.into(),
+            value: Some(AnyValue { value: Some(value) }),
+            ..KeyValue::default()
+        }
+    }
+
+    fn log(severity_number: i32, severity_text: &str, body: &str) -> OtlpRecord {
+        OtlpRecord {
+            severity_number,
+            severity_text: severity_text.into(),
+            body: string(body),
+            ..OtlpRecord::default()
+        }
+    }
+
+    fn request(resource: Vec<KeyValue>, records: Vec<OtlpRecord>) -> ExportLogsServiceRequest {
+        ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: Some(Resource {
+                    attributes: resource,
+                    ..Resource::default()
+                }),
+                scope_logs: vec![ScopeLogs {
+                    scope: Some(InstrumentationScope {
+                        name: "checkout".into(),
+                        ..InstrumentationScope::default()
+                    }),
+                    log_records: records,
+                    ..ScopeLogs::default()
+                }],
+                ..ResourceLogs::default()
+            }],
+        }
+    }
+
+    fn json(records: Vec<LogRecord>) -> Vec<Value> {
+        records
+            .iter()
+            .map(|record| serde_json::to_value(record).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn severity_picks_the_log_type_and_level() {
+        let records = convert_request(request(
+            vec![],
+            vec![
+                log(17, "", "from the number"),
+                log(14, "Warning", "text kept as the level"),
+                log(0, "error", "from the text"),
+                log(0, "", "unspecified"),
+                log(22, "", "fatal"),
+            ],
+        ));
+        let types: Vec<_> = records.iter().map(Loggable::log_type).collect();
+        assert_eq!(
+            types,
+            [
+                LogType::Error,
+                LogType::Warn,
+                LogType::Error,
+                LogType::Info,
+                LogType::Error
+            ]
+        );
+        let levels: Vec<_> = json(records)
+            .into_iter()
+            .map(|record| record["level"].clone())
+            .collect();
+        assert_eq!(levels, ["ERROR", "Warning", "error", "INFO", "FATAL"]);
+    }
+
+    #[test]
+    fn attributes_become_fields() {
+        let mut record = log(9, "", "paid");
+        record.attributes = vec![
+            attribute("order", OtlpValue::IntValue(42)),
+            attribute("retried", OtlpValue::BoolValue(true)),
+            attribute("ratio", OtlpValue::DoubleValue(0.5)),
+            attribute("digest", OtlpValue::BytesValue(vec![0xde, 0xad])),
+            attribute(
+                "tags",
+                OtlpValue::ArrayValue(ArrayValue {
+                    values: vec![string("a").unwrap(), AnyValue { value: None }],
+                }),
+            ),
+        ];
+        record.trace_id = vec![0xab; 16];
+        record.span_id = vec![0; 8];
+        record.event_name = "payment".into();
+        record.time_unix_nano = 1_700_000_000_000_000_000;
+
+        let record = &json(convert_request(request(vec![], vec![record])))[0];
+        assert_eq!(record["message"], "paid");
+        assert_eq!(record["target"], "checkout");
+        assert_eq!(record["fields"]["order"], 42);
+        assert_eq!(record["fields"]["retried"], true);
+        assert_eq!(record["fields"]["ratio"], 0.5);
+        assert_eq!(record["fields"]["digest"], "dead");
+        assert_eq!(record["fields"]["tags"], serde_json::json!(["a", null]));
+        assert_eq!(record["fields"]["event_name"], "payment");
+        assert_eq!(record["fields"]["trace_id"], "ab".repeat(16));
+        // An all-zero id means the record is not in a span.
+        assert!(record["fields"].get("span_id").is_none());
+        assert_eq!(
+            record["timestamp"],
+            DateTime::from_timestamp_nanos(1_700_000_000_000_000_000)
+                .to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)
+        );
+    }
+
+    #[test]
+    fn error_code_and_reason_attributes_fill_the_model() {
+        let mut error = log(17, "", "failed");
+        error.attributes = vec![attribute("error_code", OtlpValue::IntValue(503))];
+        let mut warn = log(13, "", "slow");
+        warn.attributes = vec![attribute("reason", OtlpValue::StringValue("p99".into()))];
+
+        let records = json(convert_request(request(vec![], vec![error, warn])));
+        assert_eq!(records[0]["error_code"], 503);
+        assert_eq!(records[1]["reason"], "p99");
+    }
+
+    #[test]
+    fn resource_attributes_go_under_resource_and_name_the_host() {
+        let resource = vec![
+            attribute(HOST_NAME_ATTRIBUTE, OtlpValue::StringValue("web-1".into())),
+            attribute("service.name", OtlpValue::StringValue("shop".into())),
+        ];
+        let record = &json(convert_request(request(resource, vec![log(9, "", "up")])))[0];
+        assert_eq!(record["hostname"], "web-1");
+        assert_eq!(record["fields"]["resource"]["service.name"], "shop");
+
+        let record = &json(convert_request(request(vec![], vec![log(9, "", "up")])))[0];
+        assert_eq!(record["hostname"], get_hostname());
+        assert!(record.get("fields").is_none());
+    }
+
+    /// A receiver whose producer delivers to a mock cluster, and records that are not
+    /// oversize.
+    fn receiver(cluster: &MockCluster<'_, DefaultProducerContext>) -> OtlpReceiver {
+        let cfg = Config::builder()
+            .broker(cluster.bootstrap_servers())
+            .oversize(OversizeConfig {
+                max_bytes: 1000,
+                default: OversizePolicy::Reject,
+                ..OversizeConfig::default()
+            })
+            .build();
+        for log_type in [LogType::Info, LogType::Warn, LogType::Error] {
+            cluster
+                .create_topic(&cfg.routing.route(log_type, "{}"), 1, 1)
+                .unwrap();
+        }
+        let producer = Arc::new(KafkaProducer::new(&cfg).unwrap());
+        OtlpReceiver::new(
+            producer,
+            OtlpConfig {
+                http_addr: Some("127.0.0.1:0".into()),
+                ..OtlpConfig::default()
+            },
+        )
+        .unwrap()
+    }
+
+    /// A receiver whose producer cannot reach a broker.
+    fn unreachable_receiver() -> OtlpReceiver {
+        let cfg = Config::builder()
+            .broker("127.0.0.1:1")
+            .property("message.timeout.ms", "200")
+            .build();
+        let producer = Arc::new(KafkaProducer::new(&cfg).unwrap());
+        OtlpReceiver::new(
+            producer,
+            OtlpConfig {
+                grpc_addr: Some("127.0.0.1:0".into()),
+                ..OtlpConfig::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn grpc_reports_rejected_records() {
+        let cluster = MockCluster::new(1).unwrap();
+        let receiver = receiver(&cluster);
+
+        let response = LogsService::export(
+            &receiver,
+            tonic::Request::new(request(vec![], vec![log(9, "", "fits")])),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.into_inner().partial_success, None);
+
+        let oversize = "x".repeat(2000);
+        let response = LogsService::export(
+            &receiver,
+            tonic::Request::new(request(
+                vec![],
+                vec![log(9, "", "fits"), log(9, "", &oversize)],
+            )),
+        )
+        .await
+        .unwrap();
+        let partial = response.into_inner().partial_success.unwrap();
+        assert_eq!(partial.rejected_log_records, 1);
+        assert!(!partial.error_message.is_empty());
+    }
+
+    #[tokio::test]
+    async fn grpc_is_unavailable_when_every_record_fails() {
+        let status = LogsService::export(
+            &unreachable_receiver(),
+            tonic::Request::new(request(vec![], vec![log(9, "", "lost")])),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+    }
+
+    fn headers(content_type: &str, content_encoding: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+        if let Some(encoding) = content_encoding {
+            headers.insert(header::CONTENT_ENCODING, encoding.parse().unwrap());
+        }
+        headers
+    }
+
+    fn gzip(body: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    async fn body(response: Response) -> Bytes {
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn http_accepts_protobuf_and_json_plain_or_gzipped() {
+        let cluster = MockCluster::new(1).unwrap();
+        let receiver = receiver(&cluster);
+        let export = request(vec![], vec![log(9, "", "hello")]);
+        let protobuf = export.encode_to_vec();
+        let json = serde_json::to_vec(&export).unwrap();
+
+        for (content_type, encoding, payload) in [
+            (PROTOBUF_CONTENT_TYPE, None, protobuf.clone()),
+            (PROTOBUF_CONTENT_TYPE, Some("gzip"), gzip(&protobuf)),
+            (JSON_CONTENT_TYPE, None, json.clone()),
+            (JSON_CONTENT_TYPE, Some("GZIP"), gzip(&json)),
+        ] {
+            let response = export_http(
+                State(receiver.clone()),
+                headers(content_type, encoding),
+                payload.into(),
+            )
+            .await;
+            assert_eq!(
+                response.status(),
+                StatusCode::OK,
+                "{content_type} {encoding:?}"
+            );
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                content_type,
+                "{content_type} {encoding:?}"
+            );
+            let body = body(response).await;
+            let response = if content_type == JSON_CONTENT_TYPE {
+                serde_json::from_slice::<ExportLogsServiceResponse>(&body).unwrap()
+            } else {
+                ExportLogsServiceResponse::decode(body).unwrap()
+            };
+            assert_eq!(response.partial_success, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn http_rejects_bodies_it_cannot_read() {
+        let cluster = MockCluster::new(1).unwrap();
+        let receiver = receiver(&cluster);
+        let protobuf = request(vec![], vec![log(9, "", "hello")]).encode_to_vec();
+
+        for (content_type, encoding, payload, status) in [
+            (
+                "text/plain",
+                None,
+                protobuf.clone(),
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ),
+            (
+                PROTOBUF_CONTENT_TYPE,
+                Some("br"),
+                protobuf.clone(),
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ),
+            (
+                PROTOBUF_CONTENT_TYPE,
+                Some("gzip"),
+                protobuf.clone(),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                PROTOBUF_CONTENT_TYPE,
+                Some("gzip"),
+                gzip(&vec![0; MAX_DECODED_BYTES as usize + 1]),
+                StatusCode::PAYLOAD_TOO_LARGE,
+            ),
+            (
+                JSON_CONTENT_TYPE,
+                None,
+                b"{".to_vec(),
+                StatusCode::BAD_REQUEST,
+            ),
+        ] {
+            let response = export_http(
+                State(receiver.clone()),
+                headers(content_type, encoding),
+                payload.into(),
+            )
+            .await;
+            assert_eq!(response.status(), status, "{content_type} {encoding:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn http_is_unavailable_when_every_record_fails() {
+        let export = request(vec![], vec![log(9, "", "lost")]);
+        let response = export_http(
+            State(unreachable_receiver()),
+            headers(PROTOBUF_CONTENT_TYPE, None),
+            export.encode_to_vec().into(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!body(response).await.is_empty());
+    }
+}
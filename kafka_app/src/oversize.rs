@@ -0,0 +1,491 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::constant::{
+    CHUNK_COUNT_HEADER, CHUNK_ID_HEADER, CHUNK_INDEX_HEADER, CONTENT_TYPE_HEADER,
+    DEAD_LETTER_REASON_HEADER, DEFAULT_DEAD_LETTER_TOPIC, DEFAULT_MAX_RECORD_BYTES,
+    DEFAULT_REASSEMBLY_MAX_BYTES, DEFAULT_REASSEMBLY_TTL_SECS, ORIGINAL_SIZE_HEADER,
+    ORIGINAL_TOPIC_HEADER,
+};
+use crate::encoding::JSON_CONTENT_TYPE;
+use crate::producer::{BoxError, Prepared};
+
+/// Bytes a record takes on top of its key, payload and headers, allowed for in size checks.
+const RECORD_OVERHEAD: usize = 64;
+/// Room kept for the marker that replaces the end of a truncated message.
+const TRUNCATION_MARKER_RESERVE: usize = 48;
+/// Longest value of the chunk headers: a chunk id, or a 20-digit index or count.
+const CHUNK_HEADER_VALUE_RESERVE: usize = 40;
+
+static CHUNK_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// What happens to a log whose record would be larger than [`OversizeConfig::max_bytes`].
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizePolicy {
+    /// The send fails before anything reaches the broker.
+    #[default]
+    Reject,
+    /// The `message` field is cut to fit and ends with a marker saying how much was removed.
+    Truncate,
+    /// The encoded payload is split over several records with the same key, carrying
+    /// `chunk-id`, `chunk-index` and `chunk-count` headers. See [`Reassembler`].
+    Split,
+    /// A JSON summary goes to [`OversizeConfig::dead_letter_topic`] instead, with the record's
+    /// key, timestamp and headers plus `original-topic`, `original-size` and
+    /// `dead-letter-reason`. The summary names the original topic, size and content type, and
+    /// holds as much of the payload, as text, as fits.
+    DeadLetter,
+}
+
+impl OversizePolicy {
+    pub fn parse(raw: &str) -> anyhow::Result<Self> {
+        match raw.to_ascii_lowercase().replace('-', "_").as_str() {
+            "reject" => Ok(OversizePolicy::Reject),
+            "truncate" => Ok(OversizePolicy::Truncate),
+            "split" => Ok(OversizePolicy::Split),
+            "dead_letter" => Ok(OversizePolicy::DeadLetter),
+            other => anyhow::bail!("unsupported oversize policy {:?}", other),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct OversizeConfig {
+    /// Largest record produced, counting key, payload and headers. Keep it at or below the
+    /// broker's `message.max.bytes` (or the topic's `max.message.bytes`) and the client's
+    /// `message.max.bytes` property.
+    pub max_bytes: usize,
+    pub default: OversizePolicy,
+    /// Per-topic overrides of `default`, keyed by the routed topic name (prefix included).
+    pub topics: HashMap<String, OversizePolicy>,
+    /// Where [`OversizePolicy::DeadLetter`] sends records. The routing prefix is added to it.
+    pub dead_letter_topic: String,
+}
+
+impl Default for OversizeConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_RECORD_BYTES,
+            default: OversizePolicy::default(),
+            topics: HashMap::new(),
+            dead_letter_topic: DEFAULT_DEAD_LETTER_TOPIC.into(),
+        }
+    }
+}
+
+impl OversizeConfig {
+    pub fn policy_for(&self, topic: &str) -> OversizePolicy {
+        self.topics.get(topic).copied().unwrap_or(self.default)
+    }
+}
+
+/// Approximate size of the record as the broker counts it.
+pub(crate) fn record_size(prepared: &Prepared) -> usize {
+    RECORD_OVERHEAD
+        + prepared.key.as_ref().map_or(0, Vec::len)
+        + prepared.payload.len()
+        + headers_size(&prepared.headers)
+}
+
+fn headers_size(headers: &[(String, Vec<u8>)]) -> usize {
+    headers
+        .iter()
+        .map(|(name, value)| name.len() + value.len())
+        .sum()
+}
+
+/// Shortens the `message` field of a serialized log by at least `shrink` bytes and marks the
+/// cut. `None` when there is no message long enough to make up the difference.
+pub(crate) fn truncate_message(json: &str, shrink: usize) -> Option<String> {
+    let mut doc: Value = serde_json::from_str(json).ok()?;
+    let message = doc.get_mut("message")?;
+    let text = message.as_str()?;
+    let mut cut = text.len().checked_sub(shrink + TRUNCATION_MARKER_RESERVE)?;
+    while !text.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    let truncated = format!("{}... [truncated {} bytes]", &text[..cut], text.len() - cut);
+    *message = Value::String(truncated);
+    serde_json::to_string(&doc).ok()
+}
+
+/// Splits a record's payload over as many records of at most `max_bytes` as needed. Without a
+/// partition key the chunk id becomes the key, so every chunk lands on the same partition.
+pub(crate) fn split(prepared: Prepared, max_bytes: usize) -> Result<Vec<Prepared>, BoxError> {
+    let chunk_id = format!(
+        "{:x}-{:x}-{:x}",
+        std::process::id(),
+        Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+        CHUNK_SEQ.fetch_add(1, Ordering::Relaxed)
+    );
+    let key = prepared
+        .key
+        .clone()
+        .unwrap_or_else(|| chunk_id.clone().into_bytes());
+    let overhead = RECORD_OVERHEAD
+        + key.len()
+        + headers_size(&prepared.headers)
+        + CHUNK_ID_HEADER.len()
+        + CHUNK_INDEX_HEADER.len()
+        + CHUNK_COUNT_HEADER.len()
+        + 3 * CHUNK_HEADER_VALUE_RESERVE;
+    let chunk_size = max_bytes
+        .checked_sub(overhead)
+        .filter(|size| *size > 0)
+        .ok_or_else(|| {
+            format!(
+                "max_bytes {} leaves no room for a chunk of the payload",
+                max_bytes
+            )
+        })?;
+
+    let count = prepared.payload.len().div_ceil(chunk_size);
+    Ok(prepared
+        .payload
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut headers = prepared.headers.clone();
+            headers.push((CHUNK_ID_HEADER.into(), chunk_id.clone().into_bytes()));
+            headers.push((CHUNK_INDEX_HEADER.into(), index.to_string().into_bytes()));
+            headers.push((CHUNK_COUNT_HEADER.into(), count.to_string().into_bytes()));
+            Prepared {
+                topic: prepared.topic.clone(),
+                key: Some(key.clone()),
+                timestamp: prepared.timestamp,
+                headers,
+                payload: chunk.to_vec(),
+            }
+        })
+        .collect())
+}
+
+/// Redirects a record to `topic`, keeping its metadata and replacing the payload with a
+/// summary that fits.
+pub(crate) fn dead_letter(mut prepared: Prepared, topic: String, max_bytes: usize) -> Prepared {
+    let payload = std::mem::take(&mut prepared.payload);
+    let original_topic = std::mem::replace(&mut prepared.topic, topic);
+    let content_type = prepared
+        .headers
+        .iter()
+        .position(|(name, _)| name == CONTENT_TYPE_HEADER)
+        .map(|index| prepared.headers.remove(index).1);
+    prepared.headers.extend([
+        (CONTENT_TYPE_HEADER.into(), JSON_CONTENT_TYPE.into()),
+        (
+            ORIGINAL_TOPIC_HEADER.into(),
+            original_topic.clone().into_bytes(),
+        ),
+        (
+            ORIGINAL_SIZE_HEADER.into(),
+            payload.len().to_string().into_bytes(),
+        ),
+        (DEAD_LETTER_REASON_HEADER.into(), b"oversize".to_vec()),
+    ]);
+    let room = max_bytes.saturating_sub(record_size(&prepared));
+    prepared.payload = dead_letter_summary(
+        &original_topic,
+        content_type
+            .as_deref()
+            .map(String::from_utf8_lossy)
+            .as_deref(),
+        &payload,
+        room,
+    );
+    prepared
+}
+
+/// A JSON document describing an oversize payload, with as much of it as text as fits in `room`
+/// bytes. Only when even an empty excerpt does not fit is it larger.
+fn dead_letter_summary(
+    original_topic: &str,
+    content_type: Option<&str>,
+    payload: &[u8],
+    room: usize,
+) -> Vec<u8> {
+    let text = String::from_utf8_lossy(payload);
+    let summary = |cut: usize| {
+        let cut = text.floor_char_boundary(cut);
+        json!({
+            "reason": "oversize",
+            "original_topic": original_topic,
+            "original_size": payload.len(),
+            "content_type": content_type,
+            "excerpt": &text[..cut],
+            "truncated": cut < text.len(),
+        })
+        .to_string()
+        .into_bytes()
+    };
+    // Escaping makes the encoded length hard to predict, so the longest excerpt that fits is
+    // searched for: `low` fits (or is empty) and `high` does not.
+    let (mut low, mut high) = (0, text.len() + 1);
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if summary(mid).len() <= room {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    summary(low)
+}
+
+/// The chunks received so far of one split record.
+struct PendingChunks {
+    count: usize,
+    chunks: HashMap<usize, Vec<u8>>,
+    bytes: usize,
+    first_seen: Instant,
+}
+
+/// Puts split records back together on the consuming side.
+///
+/// Chunks are matched by their `chunk-id` header and may arrive in any order. A chunk id whose
+/// records never all arrive (e.g. the consumer restarted between them) is given up on once its
+/// first chunk is older than the TTL, and the oldest chunk ids are given up on whenever the
+/// chunks held would exceed the byte limit.
+pub struct Reassembler {
+    pending: HashMap<String, PendingChunks>,
+    pending_bytes: usize,
+    ttl: Duration,
+    max_bytes: usize,
+    evicted: u64,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new(
+            Duration::from_secs(DEFAULT_REASSEMBLY_TTL_SECS),
+            DEFAULT_REASSEMBLY_MAX_BYTES,
+        )
+    }
+}
+
+impl Reassembler {
+    pub fn new(ttl: Duration, max_bytes: usize) -> Self {
+        Self {
+            pending: HashMap::new(),
+            pending_bytes: 0,
+            ttl,
+            max_bytes,
+            evicted: 0,
+        }
+    }
+
+    /// Adds one record. Returns its payload untouched when it is not a chunk, the whole payload
+    /// once the last chunk of a record arrives, and `None` while chunks are missing.
+    /// `header` looks up a record header by name.
+    pub fn push<'a>(
+        &mut self,
+        header: impl Fn(&str) -> Option<&'a str>,
+        payload: &[u8],
+    ) -> Option<Vec<u8>> {
+        let (Some(id), Some(index), Some(count)) = (
+            header(CHUNK_ID_HEADER),
+            header(CHUNK_INDEX_HEADER).and_then(|raw| raw.parse::<usize>().ok()),
+            header(CHUNK_COUNT_HEADER).and_then(|raw| raw.parse::<usize>().ok()),
+        ) else {
+            return Some(payload.to_vec());
+        };
+        let now = Instant::now();
+        self.evict_expired(now);
+        if index >= count {
+            return None;
+        }
+
+        let pending = self
+            .pending
+            .entry(id.to_string())
+            .or_insert_with(|| PendingChunks {
+                count,
+                chunks: HashMap::new(),
+                bytes: 0,
+                first_seen: now,
+            });
+        if index >= pending.count {
+            return None;
+        }
+        if let Some(previous) = pending.chunks.insert(index, payload.to_vec()) {
+            pending.bytes -= previous.len();
+            self.pending_bytes -= previous.len();
+        }
+        pending.bytes += payload.len();
+        self.pending_bytes += payload.len();
+        if pending.chunks.len() == pending.count {
+            let mut pending = self.remove(id)?;
+            return Some(
+                (0..pending.count)
+                    .filter_map(|index| pending.chunks.remove(&index))
+                    .flatten()
+                    .collect(),
+            );
+        }
+
+        while self.pending_bytes > self.max_bytes {
+            let Some(oldest) = self
+                .pending
+                .iter()
+                .min_by_key(|(_, pending)| pending.first_seen)
+                .map(|(id, _)| id.clone())
+            else {
+                break;
+            };
+            self.remove(&oldest);
+            self.evicted += 1;
+        }
+        None
+    }
+
+    /// Chunk ids still waiting for records.
+    pub fn incomplete(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Chunk ids given up on, because they expired or to stay under the byte limit.
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    fn remove(&mut self, id: &str) -> Option<PendingChunks> {
+        let pending = self.pending.remove(id)?;
+        self.pending_bytes -= pending.bytes;
+        Some(pending)
+    }
+
+    fn evict_expired(&mut self, now: Instant) {
+        let expired: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| now.duration_since(pending.first_seen) >= self.ttl)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            self.remove(&id);
+            self.evicted += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prepared(payload: Vec<u8>) -> Prepared {
+        Prepared {
+            topic: "logs".into(),
+            key: None,
+            timestamp: Some(0),
+            headers: vec![(
+                CONTENT_TYPE_HEADER.into(),
+                b"application/vnd.confluent.avro".to_vec(),
+            )],
+            payload,
+        }
+    }
+
+    fn header<'a>(record: &'a Prepared) -> impl Fn(&str) -> Option<&'a str> {
+        |name| {
+            record
+                .headers
+                .iter()
+                .find(|(header, _)| header == name)
+                .and_then(|(_, value)| std::str::from_utf8(value).ok())
+        }
+    }
+
+    #[test]
+    fn reassembles_chunks_in_any_order() {
+        let payload: Vec<u8> = (0..2000u32).map(|i| i as u8).collect();
+        let mut chunks = split(prepared(payload.clone()), 600).unwrap();
+        assert!(chunks.len() > 2);
+        chunks.reverse();
+
+        let mut reassembler = Reassembler::default();
+        let (last, rest) = chunks.split_last().unwrap();
+        for chunk in rest {
+            assert_eq!(reassembler.push(header(chunk), &chunk.payload), None);
+        }
+        assert_eq!(reassembler.incomplete(), 1);
+        assert_eq!(reassembler.push(header(last), &last.payload), Some(payload));
+        assert_eq!(reassembler.incomplete(), 0);
+    }
+
+    #[test]
+    fn gives_up_on_expired_chunk_ids() {
+        let chunks = split(prepared(vec![1; 2000]), 600).unwrap();
+        let mut reassembler = Reassembler::new(Duration::ZERO, usize::MAX);
+        reassembler.push(header(&chunks[0]), &chunks[0].payload);
+        assert_eq!(reassembler.incomplete(), 1);
+
+        // The next push finds the first chunk id expired and starts over.
+        assert_eq!(
+            reassembler.push(header(&chunks[1]), &chunks[1].payload),
+            None
+        );
+        assert_eq!(reassembler.evicted(), 1);
+        assert_eq!(reassembler.incomplete(), 1);
+    }
+
+    #[test]
+    fn gives_up_on_the_oldest_chunk_ids_above_the_byte_limit() {
+        let first = split(prepared(vec![1; 2000]), 600).unwrap();
+        let second = split(prepared(vec![2; 2000]), 600).unwrap();
+        // Room for every chunk of one record but one, not for a chunk of the other as well.
+        let mut reassembler = Reassembler::new(Duration::from_secs(60), 1999);
+
+        reassembler.push(header(&first[0]), &first[0].payload);
+        std::thread::sleep(Duration::from_millis(1));
+        let reassembled: Vec<_> = second
+            .iter()
+            .filter_map(|chunk| reassembler.push(header(chunk), &chunk.payload))
+            .collect();
+        assert_eq!(reassembled, vec![vec![2; 2000]]);
+        assert_eq!(reassembler.evicted(), 1);
+        assert_eq!(reassembler.incomplete(), 0);
+    }
+
+    #[test]
+    fn passes_records_that_are_not_chunks_through() {
+        let record = prepared(b"whole".to_vec());
+        let mut reassembler = Reassembler::default();
+        assert_eq!(
+            reassembler.push(header(&record), &record.payload),
+            Some(b"whole".to_vec())
+        );
+    }
+
+    #[test]
+    fn dead_letters_a_json_summary_that_fits() {
+        let payload = "\"quoted\" \u{e9}".repeat(500).into_bytes();
+        let original_size = payload.len();
+        let record = dead_letter(prepared(payload), "dead_letters".into(), 1000);
+
+        assert_eq!(record.topic, "dead_letters");
+        assert!(record_size(&record) <= 1000);
+        let lookup = header(&record);
+        assert_eq!(lookup(CONTENT_TYPE_HEADER), Some(JSON_CONTENT_TYPE));
+        assert_eq!(lookup(ORIGINAL_TOPIC_HEADER), Some("logs"));
+        assert_eq!(
+            lookup(ORIGINAL_SIZE_HEADER),
+            Some(original_size.to_string().as_str())
+        );
+
+        let summary: Value = serde_json::from_slice(&record.payload).unwrap();
+        assert_eq!(summary["original_topic"], "logs");
+        assert_eq!(summary["original_size"], original_size);
+        assert_eq!(summary["content_type"], "application/vnd.confluent.avro");
+        assert_eq!(summary["truncated"], true);
+        let excerpt = summary["excerpt"].as_str().unwrap();
+        assert!(excerpt.starts_with("\"quoted\" \u{e9}"));
+        // Most of the room goes to the excerpt.
+        assert!(excerpt.len() > 500, "{}", excerpt.len());
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::bail;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::helper::{json_text, log_field};
+use crate::models::LogType;
+
+/// Computes the record key of a log from its type and its serialized form. `None` sends the
+/// record without a key.
+pub type KeyFn = dyn Fn(LogType, &Value) -> Option<Vec<u8>> + Send + Sync;
+
+/// How the record key, and so the partition, is chosen for a log.
+#[derive(Deserialize, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PartitionStrategy {
+    /// Keyed by the producer hostname: logs from one host stay in order on one partition.
+    #[default]
+    Hostname,
+    /// No key. librdkafka's sticky partitioner fills a batch for one partition, then moves on
+    /// to the next, which spreads load evenly without per-record round trips.
+    #[serde(alias = "sticky")]
+    RoundRobin,
+    /// Keyed by a field of the log, looked up like a routing rule field. `request_id` keeps
+    /// the logs of one request together. Logs without the field are sent without a key.
+    Field(String),
+    /// Keyed by a closure. Can only be set in code.
+    #[serde(skip)]
+    Custom(Arc<KeyFn>),
+}
+
+impl fmt::Debug for PartitionStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionStrategy::Hostname => f.write_str("Hostname"),
+            PartitionStrategy::RoundRobin => f.write_str("RoundRobin"),
+            PartitionStrategy::Field(name) => f.debug_tuple("Field").field(name).finish(),
+            PartitionStrategy::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+impl PartitionStrategy {
+    pub fn custom(
+        key: impl Fn(LogType, &Value) -> Option<Vec<u8>> + Send + Sync + 'static,
+    ) -> Self {
+        PartitionStrategy::Custom(Arc::new(key))
+    }
+
+    /// Parses `hostname`, `round_robin` (or `sticky`) and `field:<name>`.
+    pub fn parse(raw: &str) -> anyhow::Result<Self> {
+        if let Some(name) = raw.strip_prefix("field:") {
+            return Ok(PartitionStrategy::Field(name.to_string()));
+        }
+        match raw.to_ascii_lowercase().replace('-', "_").as_str() {
+            "hostname" => Ok(PartitionStrategy::Hostname),
+            "round_robin" | "sticky" => Ok(PartitionStrategy::RoundRobin),
+            other => bail!("unsupported partition strategy {:?}", other),
+        }
+    }
+
+    /// `payload` is the serialized log and is only parsed when the strategy looks inside it.
+    pub fn key(&self, log_type: LogType, hostname: &str, payload: &str) -> Option<Vec<u8>> {
+        match self {
+            PartitionStrategy::Hostname => Some(hostname.as_bytes().to_vec()),
+            PartitionStrategy::RoundRobin => None,
+            PartitionStrategy::Field(name) => {
+                let doc: Value = serde_json::from_str(payload).ok()?;
+                log_field(&doc, name).map(|value| json_text(value).into_bytes())
+            }
+            PartitionStrategy::Custom(key) => {
+                let doc: Value = serde_json::from_str(payload).ok()?;
+                key(log_type, &doc)
+            }
+        }
+    }
+}
+
+/// The `[partitioning]` section of the config file.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PartitioningConfig {
+    pub default: PartitionStrategy,
+    /// Per-topic overrides of `default`, keyed by the routed topic name (prefix included).
+    pub topics: HashMap<String, PartitionStrategy>,
+}
+
+impl PartitioningConfig {
+    pub fn strategy_for(&self, topic: &str) -> &PartitionStrategy {
+        self.topics.get(topic).unwrap_or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOAD: &str =
+        r#"{"level":"INFO","message":"hi","fields":{"request_id":"req-1","attempt":3}}"#;
+
+    #[test]
+    fn parses_strategies() {
+        assert!(matches!(
+            PartitionStrategy::parse("hostname").unwrap(),
+            PartitionStrategy::Hostname
+        ));
+        for raw in ["round_robin", "Round-Robin", "sticky"] {
+            assert!(matches!(
+                PartitionStrategy::parse(raw).unwrap(),
+                PartitionStrategy::RoundRobin
+            ));
+        }
+        assert!(matches!(
+            PartitionStrategy::parse("field:request_id").unwrap(),
+            PartitionStrategy::Field(name) if name == "request_id"
+        ));
+        assert!(PartitionStrategy::parse("random").is_err());
+    }
+
+    #[test]
+    fn hostname_keys_by_the_producer_host() {
+        let key = PartitionStrategy::Hostname.key(LogType::Info, "host-a", PAYLOAD);
+        assert_eq!(key.as_deref(), Some(&b"host-a"[..]));
+    }
+
+    #[test]
+    fn round_robin_sends_no_key() {
+        assert_eq!(
+            PartitionStrategy::RoundRobin.key(LogType::Info, "host-a", PAYLOAD),
+            None
+        );
+    }
+
+    #[test]
+    fn field_keys_by_top_level_and_context_fields() {
+        let key = |name: &str| {
+            PartitionStrategy::Field(name.into()).key(LogType::Info, "host-a", PAYLOAD)
+        };
+        assert_eq!(key("level").as_deref(), Some(&b"INFO"[..]));
+        assert_eq!(key("request_id").as_deref(), Some(&b"req-1"[..]));
+        assert_eq!(key("attempt").as_deref(), Some(&b"3"[..]));
+        assert_eq!(key("missing"), None);
+        assert_eq!(
+            PartitionStrategy::Field("level".into()).key(LogType::Info, "host-a", "not json"),
+            None
+        );
+    }
+
+    #[test]
+    fn custom_gets_the_log_type_and_document() {
+        let strategy = PartitionStrategy::custom(|log_type, doc| {
+            Some(format!("{}/{}", log_type.as_str(), doc["message"].as_str()?).into_bytes())
+        });
+        assert_eq!(
+            strategy.key(LogType::Warn, "host-a", PAYLOAD).as_deref(),
+            Some(&b"warn/hi"[..])
+        );
+    }
+
+    #[test]
+    fn topics_override_the_default() {
+        let cfg = PartitioningConfig {
+            default: PartitionStrategy::Hostname,
+            topics: HashMap::from([("logs.audit".to_string(), PartitionStrategy::RoundRobin)]),
+        };
+        assert!(matches!(
+            cfg.strategy_for("logs.audit"),
+            PartitionStrategy::RoundRobin
+        ));
+        assert!(matches!(
+            cfg.strategy_for("logs.info"),
+            PartitionStrategy::Hostname
+        ));
+    }
+}
@@ -1,50 +1,904 @@
+use crate::backpressure::{Backpressure, BackpressureMetrics, Refused};
+use crate::client::DeliveryGuarantee;
 use crate::config::Config;
-use crate::helper::get_hostname;
-use crate::models::Loggable;
-use rdkafka::ClientConfig;
-use rdkafka::producer::{FutureProducer, FutureRecord};
-use std::time::Duration;
+use crate::constant::{
+    CONTENT_TYPE_HEADER, HOSTNAME_HEADER, LOG_SCHEMA_VERSION, LOG_TYPE_HEADER,
+    ORIGINAL_SIZE_HEADER, SCHEMA_VERSION_HEADER, SPAN_ID_HEADER, TRACE_ID_HEADER,
+};
+use crate::encoding::{PayloadEncoder, PayloadFormat};
+use crate::helper::{get_hostname, owned_headers};
+use crate::metrics::{DeliveryMetrics, StatsContext};
+use crate::models::{LogType, Loggable};
+use crate::oversize::{self, OversizeConfig, OversizePolicy};
+use crate::partition::PartitioningConfig;
+use crate::redact::{RedactionMetrics, Redactor};
+use crate::routing::RoutingConfig;
+use crate::spool::{Spool, SpoolMetrics, SpooledRecord};
+use crate::throttle::{Admission, Collapsed, DedupKey, Throttle, ThrottleMetrics};
+use crate::transaction::{Transaction, TransactionState};
+use anyhow::Context;
+use futures::StreamExt;
+use futures::future::join_all;
+use futures::stream;
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use rdkafka::statistics::Statistics;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
 
+pub(crate) type BoxError = Box<dyn std::error::Error + Send + Sync>;
+/// The librdkafka producer, keeping its latest statistics for [`crate::metrics`].
+pub type InnerProducer = FutureProducer<StatsContext>;
+
+const SHUT_DOWN: &str = "the producer is shut down";
+const CIRCUIT_OPEN: &str = "the circuit breaker is open after repeated broker errors";
+const SPOOL_FULL: &str = "spool is full, log dropped";
+const QUEUE_AND_SPOOL_FULL: &str = "queue and spool are full, log dropped";
+/// Rounds of cutting a message shorter before a truncated log is given up on. More than one is
+/// only needed when the encoding does not shrink byte for byte with the JSON.
+const TRUNCATE_ATTEMPTS: usize = 3;
+
+/// Serializes [`Loggable`] records to JSON and produces them to the topic chosen by the
+/// configured [`RoutingConfig`].
+///
+/// With a spool configured, records the broker does not accept are written to disk instead of
+/// being returned as errors, and replayed in the background.
+///
+/// Records larger than the configured limit are rejected, truncated, split or dead-lettered
+/// before they reach the broker, as set by [`OversizeConfig`].
+///
+/// With a [`crate::redact::RedactionConfig`], personal data is redacted from every log before
+/// anything else, so routing, partition keys, the spool and the broker only see the result.
+///
+/// With a [`crate::throttle::ThrottleConfig`], logs are sampled, rate limited and collapsed
+/// with their duplicates before that. Dropped and collapsed logs count as suppressed; they are
+/// not errors. `send_many` batches in a transaction are never throttled.
+///
+/// When librdkafka's queue is full, each log type's
+/// [`crate::backpressure::BackpressurePolicy`] decides whether the send waits, drops a log or
+/// spills to the spool. Dropped logs are not errors either. An optional circuit breaker fails
+/// sends fast, or spools them, while the broker keeps failing.
+///
+/// With the exactly-once guarantee every send runs inside a transaction: `send` and `enqueue`
+/// commit one log at a time, `send_many` commits the whole batch, and [`KafkaProducer::begin`]
+/// lets the caller decide what goes into a transaction.
+///
+/// Every outcome is counted in [`DeliveryMetrics`], which [`crate::metrics::MetricsServer`]
+/// exposes along with librdkafka's statistics.
 #[derive(Clone)]
 pub struct KafkaProducer {
-    pub inner: FutureProducer,
+    pub inner: InnerProducer,
+    routing: Arc<RoutingConfig>,
+    partitioning: Arc<PartitioningConfig>,
+    oversize: Arc<OversizeConfig>,
+    redactor: Option<Arc<Redactor>>,
+    throttle: Option<Arc<Throttle>>,
+    backpressure: Arc<Backpressure>,
+    encoder: Arc<PayloadEncoder>,
+    spool: Option<Arc<Spool>>,
+    send_timeout: Duration,
+    max_in_flight: usize,
+    in_flight: Arc<Semaphore>,
+    enqueued: Arc<Mutex<DeliveryReport>>,
+    metrics: Arc<Mutex<DeliveryMetrics>>,
+    statistics: Arc<Mutex<Option<Statistics>>>,
+    transactions: Option<Arc<TransactionState>>,
+    closed: Arc<AtomicBool>,
+}
+
+/// What was left behind when [`KafkaProducer::shutdown`] returned.
+#[derive(Debug, Default, Clone)]
+pub struct ShutdownReport {
+    /// Messages librdkafka had not delivered when the deadline passed. They are lost.
+    pub undelivered: u64,
+    /// Enqueued logs whose delivery task had not finished.
+    pub pending_enqueued: u64,
+    /// Spooled bytes still on disk. They are replayed on the next start.
+    pub spooled_bytes: u64,
+}
+
+/// Outcome of a batch of sends, broken down by topic.
+#[derive(Debug, Default, Clone)]
+pub struct DeliveryReport {
+    pub delivered: u64,
+    /// Accepted by the disk spool instead of the broker; they are delivered later.
+    pub spooled: u64,
+    pub failed: u64,
+    /// The part of `failed` that sending again later may fix, e.g. while the broker is
+    /// unreachable. The rest would fail the same way, e.g. an oversize log with the reject policy.
+    pub retryable: u64,
+    /// Dropped by sampling or rate limiting, or collapsed into an identical log.
+    pub suppressed: u64,
+    /// Dropped by a backpressure policy while librdkafka's queue was full.
+    pub dropped: u64,
+    pub topics: HashMap<String, TopicDeliveries>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct TopicDeliveries {
+    pub delivered: u64,
+    pub spooled: u64,
+    pub dropped: u64,
+    pub failed: u64,
+    pub last_error: Option<String>,
+}
+
+impl DeliveryReport {
+    pub(crate) fn record(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Delivered { topic, .. } => {
+                self.delivered += 1;
+                self.topics.entry(topic).or_default().delivered += 1;
+            }
+            Outcome::Spooled { topic } => {
+                self.spooled += 1;
+                self.topics.entry(topic).or_default().spooled += 1;
+            }
+            Outcome::Suppressed => self.suppressed += 1,
+            Outcome::Dropped { topic } => {
+                self.dropped += 1;
+                self.topics.entry(topic).or_default().dropped += 1;
+            }
+            Outcome::Failed { topic, error } => {
+                self.failed += 1;
+                if is_retryable(&error) {
+                    self.retryable += 1;
+                }
+                if let Some(topic) = topic {
+                    let deliveries = self.topics.entry(topic).or_default();
+                    deliveries.failed += 1;
+                    deliveries.last_error = Some(error.to_string());
+                }
+            }
+        }
+    }
+}
+
+/// What became of a log [`KafkaProducer::send`] did not fail on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sent {
+    Delivered,
+    /// Accepted by the disk spool instead of the broker; it is delivered later.
+    Spooled,
+    /// Dropped by sampling or rate limiting, or collapsed into an identical log.
+    Suppressed,
+    /// Dropped by a backpressure policy while librdkafka's queue was full.
+    Dropped,
+}
+
+pub(crate) enum Outcome {
+    Delivered {
+        topic: String,
+    },
+    Spooled {
+        topic: String,
+    },
+    /// Held back by the [`Throttle`].
+    Suppressed,
+    /// Dropped by the [`Backpressure`] policy.
+    Dropped {
+        topic: String,
+    },
+    /// `topic` is `None` when the log failed before it could be routed.
+    Failed {
+        topic: Option<String>,
+        error: BoxError,
+    },
+}
+
+/// A log serialized, routed and encoded, ready to produce.
+pub(crate) struct Prepared {
+    pub topic: String,
+    /// Chosen by the topic's [`crate::partition::PartitionStrategy`].
+    pub key: Option<Vec<u8>>,
+    /// Event time in milliseconds since the epoch.
+    pub timestamp: Option<i64>,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub payload: Vec<u8>,
+}
+
+impl Prepared {
+    pub fn record(&self) -> FutureRecord<'_, Vec<u8>, Vec<u8>> {
+        let record = FutureRecord::to(&self.topic)
+            .payload(&self.payload)
+            .headers(owned_headers(&self.headers));
+        let record = match self.timestamp {
+            Some(timestamp) => record.timestamp(timestamp),
+            None => record,
+        };
+        match &self.key {
+            Some(key) => record.key(key),
+            None => record,
+        }
+    }
+
+    fn into_spooled(self) -> SpooledRecord {
+        SpooledRecord {
+            topic: self.topic,
+            key: self.key,
+            timestamp: self.timestamp,
+            headers: self.headers,
+            payload: self.payload,
+        }
+    }
 }
 
 impl KafkaProducer {
+    /// In exactly-once mode this blocks for up to `send_timeout` while the transactional id is
+    /// registered with the broker.
     pub fn new(cfg: &Config) -> anyhow::Result<Self> {
-        let producer: FutureProducer = ClientConfig::new()
-            .set("bootstrap.servers", &cfg.broker)
-            .set(
-                "message.timeout.ms",
-                cfg.send_timeout.as_millis().to_string(),
-            )
-            .set("linger.ms", "100") // Wait up to 100ms to accumulate messages
-            .set("batch.size", "131072") // Max batch size 128KB
-            .set("compression.codec", "snappy") // Use snappy compression
-            // --- END BATCHING CONFIGURATIONS ---
-            .set("acks", "1")
-            .create()?;
+        cfg.validate()?;
+        let context = StatsContext::default();
+        let statistics = context.latest();
+        let producer: InnerProducer = cfg.client_config().create_with_context(context)?;
 
-        Ok(Self { inner: producer })
+        let transactions = match cfg.client.guarantee {
+            Some(DeliveryGuarantee::ExactlyOnce) => {
+                producer
+                    .init_transactions(cfg.send_timeout)
+                    .context("initializing transactions")?;
+                Some(Arc::new(TransactionState::default()))
+            }
+            _ => None,
+        };
+
+        let spool = match &cfg.spool {
+            Some(spool_cfg) => {
+                let spool = Arc::new(
+                    Spool::open(spool_cfg.clone())
+                        .with_context(|| format!("opening spool {}", spool_cfg.dir.display()))?,
+                );
+                tokio::runtime::Handle::try_current()
+                    .context("the spool replay task needs a Tokio runtime")?
+                    .spawn(spool.clone().replay(producer.clone(), cfg.send_timeout));
+                Some(spool)
+            }
+            None => None,
+        };
+
+        let max_in_flight = cfg.max_in_flight.max(1);
+        Ok(Self {
+            inner: producer,
+            routing: Arc::new(cfg.routing.clone()),
+            partitioning: Arc::new(cfg.partitioning.clone()),
+            oversize: Arc::new(cfg.oversize.clone()),
+            redactor: cfg
+                .redaction
+                .as_ref()
+                .map(Redactor::new)
+                .transpose()?
+                .map(Arc::new),
+            throttle: cfg
+                .throttle
+                .as_ref()
+                .map(Throttle::new)
+                .transpose()?
+                .map(Arc::new),
+            backpressure: Arc::new(Backpressure::new(&cfg.backpressure)?),
+            encoder: Arc::new(PayloadEncoder::new(&cfg.encoding)?),
+            spool,
+            send_timeout: cfg.send_timeout,
+            max_in_flight,
+            in_flight: Arc::new(Semaphore::new(max_in_flight)),
+            enqueued: Arc::new(Mutex::new(DeliveryReport::default())),
+            metrics: Arc::new(Mutex::new(DeliveryMetrics::default())),
+            statistics,
+            transactions,
+            closed: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Starts a transaction. Only one can be open per producer at a time; this waits for the
+    /// current one to finish. Fails unless the exactly-once guarantee is configured.
+    pub async fn begin(&self) -> Result<Transaction<'_>, BoxError> {
+        if self.is_closed() {
+            return Err(Transient::boxed(SHUT_DOWN));
+        }
+        let state = self
+            .transactions
+            .as_ref()
+            .ok_or("transactions need the exactly-once delivery guarantee")?;
+        Transaction::begin(self, state).await
     }
+
+    /// Stops accepting logs, then waits up to `deadline` for enqueued logs to be handed over
+    /// and for librdkafka to deliver everything it has queued. Sends made after this fail.
+    pub async fn shutdown(&self, deadline: Duration) -> ShutdownReport {
+        self.closed.store(true, Ordering::SeqCst);
+        let collapsed = self
+            .throttle
+            .as_ref()
+            .map(|throttle| throttle.release())
+            .unwrap_or_default();
+        let started = Instant::now();
+        let _ = tokio::time::timeout(deadline, async {
+            join_all(collapsed.into_iter().map(|c| self.deliver_collapsed(c))).await;
+            self.wait_enqueued().await;
+        })
+        .await;
+        let pending_enqueued = self.pending_enqueued();
+
+        let remaining = deadline.saturating_sub(started.elapsed());
+        let inner = self.inner.clone();
+        let undelivered = tokio::task::spawn_blocking(move || {
+            // A timeout is reported through the in-flight count below.
+            let _ = inner.flush(remaining);
+            inner.in_flight_count()
+        })
+        .await
+        .unwrap_or_else(|_| self.inner.in_flight_count());
+
+        ShutdownReport {
+            undelivered: undelivered.max(0) as u64,
+            pending_enqueued,
+            spooled_bytes: self.spool_metrics().map_or(0, |m| m.spooled_bytes),
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn send_timeout(&self) -> Duration {
+        self.send_timeout
+    }
+
+    pub fn spool_metrics(&self) -> Option<SpoolMetrics> {
+        self.spool.as_ref().map(|spool| spool.metrics())
+    }
+
+    pub fn redaction_metrics(&self) -> Option<RedactionMetrics> {
+        self.redactor.as_ref().map(|redactor| redactor.metrics())
+    }
+
+    pub fn throttle_metrics(&self) -> Option<ThrottleMetrics> {
+        self.throttle.as_ref().map(|throttle| throttle.metrics())
+    }
+
+    pub fn backpressure_metrics(&self) -> BackpressureMetrics {
+        self.backpressure.metrics()
+    }
+
+    pub fn delivery_metrics(&self) -> DeliveryMetrics {
+        self.metrics
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// The latest statistics librdkafka reported, if `statistics.interval.ms` is set.
+    pub fn statistics(&self) -> Option<Statistics> {
+        self.statistics
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Enqueued logs whose delivery task has not finished.
+    pub fn pending_enqueued(&self) -> u64 {
+        (self.max_in_flight - self.in_flight.available_permits()) as u64
+    }
+
+    /// Produces a log and waits for it to settle. Logs that were held back or dropped on purpose
+    /// are not errors; [`Sent`] tells them apart from delivered ones.
     pub async fn send<T: Loggable>(
         &self,
         entry: T,
         send_timeout: &Duration,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let payload = serde_json::to_string(&entry)?;
-        let key = get_hostname();
-        let topic = entry.topic();
-        //Key is used for partioning , If we want a roound robin fashion we should not specify key !
-        // let record = FutureRecord::<(),_>::to(topic).payload(&payload);
-        //Key is used for partioning , If we want a roound robin fashion we should not specify key !
-        let record = FutureRecord::to(topic).payload(&payload).key(&key);
-        match self.inner.send(record, *send_timeout).await {
-            Ok(delivery) => {
-                println!("Delieverred -->{:?}", delivery);
-                Ok(())
-            }
-            Err((err, _owned_message)) => Err(Box::new(err)),
+    ) -> Result<Sent, BoxError> {
+        match self.deliver(entry, *send_timeout).await {
+            Outcome::Delivered { .. } => Ok(Sent::Delivered),
+            Outcome::Spooled { .. } => Ok(Sent::Spooled),
+            Outcome::Suppressed => Ok(Sent::Suppressed),
+            Outcome::Dropped { .. } => Ok(Sent::Dropped),
+            Outcome::Failed { error, .. } => Err(error),
+        }
+    }
+
+    /// Sends every log, keeping up to `max_in_flight` deliveries outstanding at once so
+    /// librdkafka can batch them, and waits for all of them to settle.
+    pub async fn send_many<T, I>(&self, entries: I, send_timeout: &Duration) -> DeliveryReport
+    where
+        T: Loggable,
+        I: IntoIterator<Item = T>,
+    {
+        let send_timeout = *send_timeout;
+        if self.transactions.is_some() {
+            return self.send_transaction(entries).await;
         }
+        stream::iter(entries)
+            .map(|entry| self.deliver(entry, send_timeout))
+            .buffer_unordered(self.max_in_flight)
+            .fold(
+                DeliveryReport::default(),
+                |mut report, outcome| async move {
+                    report.record(outcome);
+                    report
+                },
+            )
+            .await
+    }
+
+    /// Hands the log to a background delivery task and returns as soon as it is queued. Waits
+    /// only while `max_in_flight` deliveries are already outstanding. Outcomes are collected
+    /// until [`KafkaProducer::take_report`] is called.
+    pub async fn enqueue<T: Loggable>(&self, entry: T) {
+        let Ok(permit) = self.in_flight.clone().acquire_owned().await else {
+            return;
+        };
+        let producer = self.clone();
+        tokio::spawn(async move {
+            let outcome = producer.deliver(entry, producer.send_timeout).await;
+            producer
+                .enqueued
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .record(outcome);
+            drop(permit);
+        });
     }
+
+    /// Waits until every enqueued log has settled.
+    pub async fn wait_enqueued(&self) {
+        if let Ok(permits) = self.in_flight.acquire_many(self.max_in_flight as u32).await {
+            drop(permits);
+        }
+    }
+
+    /// Returns the outcomes of enqueued logs settled so far and starts a new report.
+    pub fn take_report(&self) -> DeliveryReport {
+        std::mem::take(&mut *self.enqueued.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    async fn deliver<T: Loggable>(&self, entry: T, send_timeout: Duration) -> Outcome {
+        if self.is_closed() {
+            let outcome = Outcome::Failed {
+                topic: None,
+                error: Transient::boxed(SHUT_DOWN),
+            };
+            self.observe(&outcome, Duration::ZERO);
+            return outcome;
+        }
+        if let Some(throttle) = &self.throttle {
+            match throttle.admit(&entry) {
+                Admission::Produce => {}
+                Admission::OpenWindow(key, window) => {
+                    self.collapse_after(throttle.clone(), key, window)
+                }
+                Admission::Suppress => {
+                    self.observe(&Outcome::Suppressed, Duration::ZERO);
+                    return Outcome::Suppressed;
+                }
+            }
+        }
+        let started = Instant::now();
+        let outcome = self.deliver_admitted(entry, send_timeout).await;
+        self.observe(&outcome, started.elapsed());
+        outcome
+    }
+
+    /// Produces the duplicates of a log as one collapsed log once its dedup window has passed,
+    /// unless shutdown has already taken care of them.
+    fn collapse_after(&self, throttle: Arc<Throttle>, key: DedupKey, window: Duration) {
+        let producer = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(window).await;
+            if let Some(collapsed) = throttle.close(&key) {
+                producer.deliver_collapsed(collapsed).await;
+            }
+        });
+    }
+
+    /// Nobody waits on a collapsed log, so a failure is only reported here and in the metrics.
+    async fn deliver_collapsed(&self, collapsed: Collapsed) {
+        let started = Instant::now();
+        let outcome = self.deliver_admitted(collapsed, self.send_timeout).await;
+        self.observe(&outcome, started.elapsed());
+        if let Outcome::Failed { topic, error } = &outcome {
+            eprintln!(
+                "Failed to produce collapsed duplicates to {}: {}",
+                topic.as_deref().unwrap_or("an unrouted topic"),
+                error
+            );
+        }
+    }
+
+    pub(crate) fn observe(&self, outcome: &Outcome, latency: Duration) {
+        self.metrics
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .observe(outcome, latency);
+    }
+
+    async fn deliver_admitted<T: Loggable>(&self, entry: T, send_timeout: Duration) -> Outcome {
+        if self.transactions.is_some() {
+            return self.deliver_transaction(entry).await;
+        }
+        let records = match self.prepare(&entry).await {
+            Ok(records) => records,
+            Err((topic, error)) => return Outcome::Failed { topic, error },
+        };
+        let topic = records[0].topic.clone();
+        let log_type = entry.log_type();
+
+        if let Some(spool) = &self.spool
+            && spool.has_pending()
+        {
+            // Older records are still on disk; queue behind them to keep the order.
+            for prepared in records {
+                let outcome =
+                    spool_record(spool, prepared.into_spooled(), Transient::boxed(SPOOL_FULL));
+                if let Outcome::Failed { .. } = outcome {
+                    return outcome;
+                }
+            }
+            return Outcome::Spooled { topic };
+        }
+
+        // A split log is several records; they are queued in order and the log is delivered
+        // once all of them are.
+        let sends = records.into_iter().map(|prepared| async move {
+            let result = self.produce(&prepared, log_type, send_timeout).await;
+            (prepared, result)
+        });
+        let mut outcome = Outcome::Delivered { topic };
+        for (prepared, result) in join_all(sends).await {
+            let Err(refused) = result else {
+                continue;
+            };
+            outcome = match (refused, &self.spool) {
+                (Refused::Dropped, _) => Outcome::Dropped {
+                    topic: prepared.topic,
+                },
+                (Refused::Spill, Some(spool)) => spool_record(
+                    spool,
+                    prepared.into_spooled(),
+                    Transient::boxed(QUEUE_AND_SPOOL_FULL),
+                ),
+                (Refused::CircuitOpen, Some(spool)) => spool_record(
+                    spool,
+                    prepared.into_spooled(),
+                    Transient::boxed(CIRCUIT_OPEN),
+                ),
+                (Refused::CircuitOpen, None) => Outcome::Failed {
+                    topic: Some(prepared.topic),
+                    error: Transient::boxed(CIRCUIT_OPEN),
+                },
+                (Refused::Failed(err), Some(spool)) if is_spoolable(&err) => {
+                    spool_record(spool, prepared.into_spooled(), Box::new(err))
+                }
+                (Refused::Failed(err), _) => Outcome::Failed {
+                    topic: Some(prepared.topic),
+                    error: Box::new(err),
+                },
+                // Config validation keeps the spill policy from being used without a spool.
+                (Refused::Spill, None) => Outcome::Failed {
+                    topic: Some(prepared.topic),
+                    error: Box::new(KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull)),
+                },
+            };
+            if let Outcome::Failed { .. } = outcome {
+                return outcome;
+            }
+        }
+        outcome
+    }
+
+    /// Hands a record to librdkafka through the [`Backpressure`] and waits for its delivery,
+    /// which is reported to the circuit breaker.
+    async fn produce(
+        &self,
+        prepared: &Prepared,
+        log_type: LogType,
+        send_timeout: Duration,
+    ) -> Result<(), Refused> {
+        let delivery = self
+            .backpressure
+            .enqueue(&self.inner, prepared.record(), log_type, send_timeout)
+            .await?;
+        let err = match delivery.await {
+            Ok(Ok(_)) => {
+                self.backpressure.delivered();
+                return Ok(());
+            }
+            Ok(Err((err, _owned_message))) => err,
+            Err(_canceled) => KafkaError::Canceled,
+        };
+        if is_spoolable(&err) {
+            self.backpressure.broker_failed();
+        }
+        Err(Refused::Failed(err))
+    }
+
+    /// Serializes, redacts, routes and encodes a log, then applies the topic's [`OversizePolicy`] if the
+    /// record is too large. This gives one record, or several for a split log. On failure the
+    /// topic is returned when routing got that far.
+    pub(crate) async fn prepare<T: Loggable>(
+        &self,
+        entry: &T,
+    ) -> Result<Vec<Prepared>, (Option<String>, BoxError)> {
+        let mut payload = self
+            .encoder
+            .serialize(entry)
+            .map_err(|e| (None, e.into()))?;
+        if let Some(redactor) = &self.redactor {
+            payload = redactor
+                .redact_json(payload)
+                .map_err(|e| (None, e.into()))?;
+        }
+        let hostname = get_hostname();
+        let topic = match entry.topic() {
+            Some(topic) => format!("{}{}", self.routing.prefix(), topic),
+            None => self.routing.route(entry.log_type(), &payload),
+        };
+        let key = entry.key().or_else(|| {
+            self.partitioning
+                .strategy_for(&topic)
+                .key(entry.log_type(), &hostname, &payload)
+        });
+        let prepared = self
+            .encode(entry, topic, key, &hostname, payload.clone())
+            .await?;
+
+        let size = oversize::record_size(&prepared);
+        let max_bytes = self.oversize.max_bytes;
+        if size <= max_bytes {
+            return Ok(vec![prepared]);
+        }
+        let topic = prepared.topic.clone();
+        match self.oversize.policy_for(&topic) {
+            OversizePolicy::Reject => Err((
+                Some(topic),
+                format!(
+                    "record of {} bytes is over the {} byte limit",
+                    size, max_bytes
+                )
+                .into(),
+            )),
+            OversizePolicy::Truncate => {
+                let original_size = (ORIGINAL_SIZE_HEADER.to_string(), size.to_string());
+                let mut shrink = size - max_bytes + original_size.0.len() + original_size.1.len();
+                for _ in 0..TRUNCATE_ATTEMPTS {
+                    let Some(truncated) = oversize::truncate_message(&payload, shrink) else {
+                        break;
+                    };
+                    let mut prepared = self
+                        .encode(
+                            entry,
+                            topic.clone(),
+                            prepared.key.clone(),
+                            &hostname,
+                            truncated,
+                        )
+                        .await?;
+                    prepared.headers.push((
+                        original_size.0.clone(),
+                        original_size.1.clone().into_bytes(),
+                    ));
+                    let size = oversize::record_size(&prepared);
+                    if size <= max_bytes {
+                        return Ok(vec![prepared]);
+                    }
+                    shrink += size - max_bytes;
+                }
+                Err((
+                    Some(topic),
+                    format!(
+                        "record of {} bytes cannot be truncated to {} bytes",
+                        size, max_bytes
+                    )
+                    .into(),
+                ))
+            }
+            OversizePolicy::Split => {
+                oversize::split(prepared, max_bytes).map_err(|error| (Some(topic), error))
+            }
+            OversizePolicy::DeadLetter => {
+                let dead_letter = format!(
+                    "{}{}",
+                    self.routing.prefix(),
+                    self.oversize.dead_letter_topic
+                );
+                Ok(vec![oversize::dead_letter(
+                    prepared,
+                    dead_letter,
+                    max_bytes,
+                )])
+            }
+        }
+    }
+
+    /// Encodes the serialized log for its topic and adds the record metadata.
+    async fn encode<T: Loggable>(
+        &self,
+        entry: &T,
+        topic: String,
+        key: Option<Vec<u8>>,
+        hostname: &str,
+        payload: String,
+    ) -> Result<Prepared, (Option<String>, BoxError)> {
+        let format = self.encoder.format_for(&topic);
+        if format != PayloadFormat::Json && !entry.fits_log_schema() {
+            return Err((
+                Some(topic.clone()),
+                format!(
+                    "topic {} uses the {:?} log schemas, which this log does not fit; only JSON can carry it",
+                    topic, format
+                )
+                .into(),
+            ));
+        }
+        let encoded = match self.encoder.encode(&topic, entry.log_type(), payload).await {
+            Ok(encoded) => encoded,
+            Err(e) => return Err((Some(topic), e.into())),
+        };
+        Ok(Prepared {
+            headers: record_headers(entry, encoded.content_type, hostname),
+            topic,
+            key,
+            timestamp: entry.timestamp().map(|at| at.timestamp_millis()),
+            payload: encoded.bytes,
+        })
+    }
+
+    /// Commits a single log in its own transaction. Unlike [`KafkaProducer::begin`] this works
+    /// during shutdown, for the collapsed logs still to be produced.
+    async fn deliver_transaction<T: Loggable>(&self, entry: T) -> Outcome {
+        let Some(state) = &self.transactions else {
+            return Outcome::Failed {
+                topic: None,
+                error: "transactions need the exactly-once delivery guarantee".into(),
+            };
+        };
+        let mut transaction = match Transaction::begin(self, state).await {
+            Ok(transaction) => transaction,
+            Err(error) => return Outcome::Failed { topic: None, error },
+        };
+        if let Err(error) = transaction.send(entry).await {
+            let _ = transaction.abort().await;
+            return Outcome::Failed { topic: None, error };
+        }
+        let topic = transaction.topics().next();
+        match transaction.commit_outcomes().await {
+            Ok(mut outcomes) => outcomes.pop().unwrap_or(Outcome::Failed {
+                topic,
+                error: "transaction committed without the log".into(),
+            }),
+            Err(error) => Outcome::Failed { topic, error },
+        }
+    }
+
+    /// Sends every log in one transaction. Nothing is committed if any of them fails.
+    async fn send_transaction<T, I>(&self, entries: I) -> DeliveryReport
+    where
+        T: Loggable,
+        I: IntoIterator<Item = T>,
+    {
+        let entries: Vec<T> = entries.into_iter().collect();
+        let total = entries.len();
+        let mut transaction = match self.begin().await {
+            Ok(transaction) => transaction,
+            Err(error) => return self.aborted(vec![None; total], &error),
+        };
+        for entry in entries {
+            if let Err(error) = transaction.send(entry).await {
+                let mut topics: Vec<_> = transaction.topics().map(Some).collect();
+                topics.resize(total, None);
+                let _ = transaction.abort().await;
+                return self.aborted(topics, &error);
+            }
+        }
+        let topics: Vec<_> = transaction.topics().map(Some).collect();
+        match transaction.commit().await {
+            Ok(report) => report,
+            Err(error) => self.aborted(topics, &error),
+        }
+    }
+
+    /// Counts a log per topic as failed with `error`, e.g. when their transaction is aborted.
+    fn aborted(&self, topics: Vec<Option<String>>, error: &BoxError) -> DeliveryReport {
+        let mut report = DeliveryReport::default();
+        let retryable = is_retryable(error);
+        for topic in topics {
+            let error = if retryable {
+                Transient::boxed(error.to_string())
+            } else {
+                error.to_string().into()
+            };
+            let outcome = Outcome::Failed { topic, error };
+            self.observe(&outcome, Duration::ZERO);
+            report.record(outcome);
+        }
+        report
+    }
+}
+
+/// Headers describing the record, followed by the ones the log adds itself.
+fn record_headers<T: Loggable>(
+    entry: &T,
+    content_type: String,
+    hostname: &str,
+) -> Vec<(String, Vec<u8>)> {
+    let mut headers = vec![
+        (
+            LOG_TYPE_HEADER.to_string(),
+            entry.log_type().as_str().into(),
+        ),
+        (SCHEMA_VERSION_HEADER.to_string(), LOG_SCHEMA_VERSION.into()),
+        (CONTENT_TYPE_HEADER.to_string(), content_type.into_bytes()),
+        (HOSTNAME_HEADER.to_string(), hostname.into()),
+    ];
+    if let Some(trace_id) = entry.trace_id() {
+        headers.push((TRACE_ID_HEADER.to_string(), trace_id.into()));
+    }
+    if let Some(span_id) = entry.span_id() {
+        headers.push((SPAN_ID_HEADER.to_string(), span_id.into()));
+    }
+    headers.extend(
+        entry
+            .headers()
+            .into_iter()
+            .map(|(name, value)| (name, value.into_bytes())),
+    );
+    headers
+}
+
+/// Appends the record to the spool. `when_full` is reported if the spool has no room left.
+fn spool_record(spool: &Spool, record: SpooledRecord, when_full: BoxError) -> Outcome {
+    let topic = record.topic.clone();
+    match spool.append(&record) {
+        Ok(true) => Outcome::Spooled { topic },
+        Ok(false) => Outcome::Failed {
+            topic: Some(topic),
+            error: when_full,
+        },
+        Err(e) => Outcome::Failed {
+            topic: Some(topic),
+            error: Box::new(e),
+        },
+    }
+}
+
+/// A failure that is not the log's fault and that sending it again later may fix, such as an
+/// open circuit breaker or a full spool.
+#[derive(Debug)]
+pub(crate) struct Transient(String);
+
+impl Transient {
+    pub(crate) fn boxed(message: impl Into<String>) -> BoxError {
+        Box::new(Transient(message.into()))
+    }
+}
+
+impl std::fmt::Display for Transient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Transient {}
+
+/// Whether sending the log again later may succeed. See [`DeliveryReport::retryable`].
+pub(crate) fn is_retryable(error: &BoxError) -> bool {
+    if let Some(err) = error.downcast_ref::<KafkaError>() {
+        return is_spoolable(err);
+    }
+    error.is::<Transient>() || error.is::<std::io::Error>()
+}
+
+/// Errors that replaying later cannot fix are returned to the caller instead of spooled.
+pub(crate) fn is_spoolable(err: &KafkaError) -> bool {
+    !matches!(
+        err.rdkafka_error_code(),
+        Some(
+            RDKafkaErrorCode::MessageSizeTooLarge
+                | RDKafkaErrorCode::InvalidMessageSize
+                | RDKafkaErrorCode::InvalidMessage
+                | RDKafkaErrorCode::TopicAuthorizationFailed
+                | RDKafkaErrorCode::UnknownTopic
+        )
+    )
 }
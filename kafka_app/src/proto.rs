@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+use prost::Message;
+use serde_json::{Map, Value, json};
+
+use crate::helper::json_text;
+use crate::models::LogType;
+
+pub const INFO_LOG_MESSAGE: &str = "kafka_app.logs.InfoLog";
+pub const WARN_LOG_MESSAGE: &str = "kafka_app.logs.WarnLog";
+pub const ERROR_LOG_MESSAGE: &str = "kafka_app.logs.ErrorLog";
+
+// `InfoLog`, `WarnLog` and `ErrorLog`, generated from `proto/logs.proto` by the build script.
+include!(concat!(env!("OUT_DIR"), "/kafka_app.logs.rs"));
+
+pub fn message_type(log_type: LogType) -> &'static str {
+    match log_type {
+        LogType::Info => INFO_LOG_MESSAGE,
+        LogType::Warn => WARN_LOG_MESSAGE,
+        LogType::Error => ERROR_LOG_MESSAGE,
+    }
+}
+
+/// Encodes the JSON form of a log as the protobuf message for its type.
+pub fn encode(log_type: LogType, doc: &Value) -> Vec<u8> {
+    let context = Context::from_doc(doc);
+    match log_type {
+        LogType::Info => InfoLog {
+            level: text(doc, "level"),
+            message: text(doc, "message"),
+            hostname: text(doc, "hostname"),
+            timestamp: text(doc, "timestamp"),
+            target: context.target,
+            spans: context.spans,
+            fields: context.fields,
+        }
+        .encode_to_vec(),
+        LogType::Warn => WarnLog {
+            level: text(doc, "level"),
+            message: text(doc, "message"),
+            hostname: text(doc, "hostname"),
+            timestamp: text(doc, "timestamp"),
+            reason: text(doc, "reason"),
+            target: context.target,
+            spans: context.spans,
+            fields: context.fields,
+        }
+        .encode_to_vec(),
+        LogType::Error => ErrorLog {
+            level: text(doc, "level"),
+            message: text(doc, "message"),
+            hostname: text(doc, "hostname"),
+            timestamp: text(doc, "timestamp"),
+            error_code: doc["error_code"].as_u64().unwrap_or_default(),
+            target: context.target,
+            spans: context.spans,
+            fields: context.fields,
+        }
+        .encode_to_vec(),
+    }
+}
+
+/// Decodes a protobuf payload back into the JSON form of the log.
+pub fn decode(message_type: &str, payload: &[u8]) -> anyhow::Result<Value> {
+    let (mut doc, context) = match message_type {
+        INFO_LOG_MESSAGE => {
+            let log = InfoLog::decode(payload)?;
+            (
+                json!({
+                    "level": log.level,
+                    "message": log.message,
+                    "hostname": log.hostname,
+                    "timestamp": log.timestamp,
+                }),
+                Context {
+                    target: log.target,
+                    spans: log.spans,
+                    fields: log.fields,
+                },
+            )
+        }
+        WARN_LOG_MESSAGE => {
+            let log = WarnLog::decode(payload)?;
+            (
+                json!({
+                    "level": log.level,
+                    "message": log.message,
+                    "hostname": log.hostname,
+                    "timestamp": log.timestamp,
+                    "reason": log.reason,
+                }),
+                Context {
+                    target: log.target,
+                    spans: log.spans,
+                    fields: log.fields,
+                },
+            )
+        }
+        ERROR_LOG_MESSAGE => {
+            let log = ErrorLog::decode(payload)?;
+            (
+                json!({
+                    "level": log.level,
+                    "message": log.message,
+                    "hostname": log.hostname,
+                    "timestamp": log.timestamp,
+                    "error_code": log.error_code,
+                }),
+                Context {
+                    target: log.target,
+                    spans: log.spans,
+                    fields: log.fields,
+                },
+            )
+        }
+        other => bail!("unknown protobuf message type {:?}", other),
+    };
+    context.into_doc(&mut doc);
+    Ok(doc)
+}
+
+struct Context {
+    target: Option<String>,
+    spans: Vec<String>,
+    fields: HashMap<String, String>,
+}
+
+impl Context {
+    fn from_doc(doc: &Value) -> Self {
+        Self {
+            target: doc["target"].as_str().map(str::to_string),
+            spans: doc["spans"]
+                .as_array()
+                .map(|spans| spans.iter().map(json_text).collect())
+                .unwrap_or_default(),
+            fields: doc["fields"]
+                .as_object()
+                .map(|fields| {
+                    fields
+                        .iter()
+                        .map(|(k, v)| (k.clone(), v.to_string()))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    fn into_doc(self, doc: &mut Value) {
+        if let Some(target) = self.target {
+            doc["target"] = Value::String(target);
+        }
+        if !self.spans.is_empty() {
+            doc["spans"] = json!(self.spans);
+        }
+        if !self.fields.is_empty() {
+            let fields: Map<String, Value> = self
+                .fields
+                .into_iter()
+                .map(|(k, v)| {
+                    let value = serde_json::from_str(&v).unwrap_or(Value::String(v));
+                    (k, value)
+                })
+                .collect();
+            doc["fields"] = Value::Object(fields);
+        }
+    }
+}
+
+fn text(doc: &Value, name: &str) -> String {
+    json_text(&doc[name])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_each_log_type() {
+        let base = json!({
+            "level": "ERROR",
+            "message": "disk full",
+            "hostname": "host-1",
+            "timestamp": "2024-05-01T12:00:00Z",
+            "target": "app::disk",
+            "spans": ["request"],
+            "fields": {"free": 0, "mount": "/var"},
+        });
+        for (log_type, extra) in [
+            (LogType::Info, json!({})),
+            (LogType::Warn, json!({"reason": "almost full"})),
+            (LogType::Error, json!({"error_code": 28})),
+        ] {
+            let mut doc = base.clone();
+            for (key, value) in extra.as_object().unwrap() {
+                doc[key] = value.clone();
+            }
+            let payload = encode(log_type, &doc);
+            assert_eq!(decode(message_type(log_type), &payload).unwrap(), doc);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_message_types() {
+        assert!(decode("kafka_app.logs.DebugLog", &[]).is_err());
+    }
+}
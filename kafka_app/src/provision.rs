@@ -0,0 +1,274 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use rdkafka::admin::{AdminClient, AdminOptions, NewTopic, ResourceSpecifier, TopicReplication};
+use rdkafka::client::DefaultClientContext;
+use rdkafka::types::RDKafkaErrorCode;
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::oversize::OversizePolicy;
+
+/// Partition count or replication factor that lets the broker pick its own default.
+const BROKER_DEFAULT: i32 = -1;
+
+/// How a topic should look. Unset values are left to the broker.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct TopicSpec {
+    pub partitions: Option<i32>,
+    pub replication_factor: Option<i32>,
+    pub retention_ms: Option<i64>,
+    /// `delete`, `compact` or `compact,delete`.
+    pub cleanup_policy: Option<String>,
+    /// Any other topic config, e.g. `"min.insync.replicas" = "2"`.
+    pub config: BTreeMap<String, String>,
+}
+
+impl TopicSpec {
+    /// This spec with the values it leaves unset taken from `defaults`.
+    fn or(&self, defaults: &TopicSpec) -> TopicSpec {
+        let mut config = defaults.config.clone();
+        config.extend(self.config.clone());
+        TopicSpec {
+            partitions: self.partitions.or(defaults.partitions),
+            replication_factor: self.replication_factor.or(defaults.replication_factor),
+            retention_ms: self.retention_ms.or(defaults.retention_ms),
+            cleanup_policy: self
+                .cleanup_policy
+                .clone()
+                .or_else(|| defaults.cleanup_policy.clone()),
+            config,
+        }
+    }
+
+    /// The topic configs the spec declares, by their Kafka names.
+    fn configs(&self) -> BTreeMap<String, String> {
+        let mut configs = self.config.clone();
+        if let Some(retention_ms) = self.retention_ms {
+            configs.insert("retention.ms".into(), retention_ms.to_string());
+        }
+        if let Some(policy) = &self.cleanup_policy {
+            configs.insert("cleanup.policy".into(), policy.clone());
+        }
+        configs
+    }
+}
+
+/// The `[provisioning]` section of the config file.
+///
+/// The topics checked are every topic routing can lead to, the dead-letter topic when an
+/// oversize policy uses it, and the topics listed in `topics`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ProvisioningConfig {
+    /// Create the topics that do not exist. Otherwise they are only reported.
+    pub create_missing: bool,
+    pub timeout_ms: u64,
+    /// Applied to every topic.
+    pub defaults: TopicSpec,
+    /// Per-topic overrides of `defaults`, keyed by the full topic name (prefix included).
+    /// Listed topics are provisioned even when no routing rule leads to them, e.g. the topic of
+    /// a `#[loggable(topic = "...")]` struct.
+    pub topics: HashMap<String, TopicSpec>,
+}
+
+impl Default for ProvisioningConfig {
+    fn default() -> Self {
+        Self {
+            create_missing: false,
+            timeout_ms: 10_000,
+            defaults: TopicSpec::default(),
+            topics: HashMap::new(),
+        }
+    }
+}
+
+impl ProvisioningConfig {
+    pub fn spec_for(&self, topic: &str) -> TopicSpec {
+        match self.topics.get(topic) {
+            Some(spec) => spec.or(&self.defaults),
+            None => self.defaults.clone(),
+        }
+    }
+}
+
+/// A setting of an existing topic that differs from its [`TopicSpec`].
+#[derive(Debug, Clone)]
+pub struct TopicDrift {
+    pub topic: String,
+    /// `partitions`, `replication_factor` or a topic config name.
+    pub setting: String,
+    pub declared: String,
+    /// `None` when the broker did not report the setting.
+    pub actual: Option<String>,
+}
+
+/// What [`provision_topics`] found and did.
+#[derive(Debug, Default, Clone)]
+pub struct ProvisionReport {
+    pub existing: Vec<String>,
+    pub created: Vec<String>,
+    /// Topics that do not exist and were not created because `create_missing` is off.
+    pub missing: Vec<String>,
+    pub drift: Vec<TopicDrift>,
+}
+
+/// The topics [`provision_topics`] looks at.
+pub fn declared_topics(cfg: &Config) -> BTreeSet<String> {
+    let mut topics = cfg.routing.all_topics();
+    let dead_letters = cfg.oversize.default == OversizePolicy::DeadLetter
+        || cfg
+            .oversize
+            .topics
+            .values()
+            .any(|policy| *policy == OversizePolicy::DeadLetter);
+    if dead_letters {
+        topics.insert(format!(
+            "{}{}",
+            cfg.routing.prefix(),
+            cfg.oversize.dead_letter_topic
+        ));
+    }
+    if let Some(provisioning) = &cfg.provisioning {
+        topics.extend(provisioning.topics.keys().cloned());
+    }
+    topics
+}
+
+/// Checks the declared topics against the cluster: missing ones are created when
+/// `create_missing` is on, and existing ones are compared with their [`TopicSpec`].
+pub async fn provision_topics(cfg: &Config) -> anyhow::Result<ProvisionReport> {
+    let provisioning = cfg.provisioning.clone().unwrap_or_default();
+    let timeout = Duration::from_millis(provisioning.timeout_ms);
+    let admin: Arc<AdminClient<DefaultClientContext>> = Arc::new(
+        cfg.admin_config()
+            .create()
+            .context("creating the admin client")?,
+    );
+    let options = AdminOptions::new()
+        .request_timeout(Some(timeout))
+        .operation_timeout(Some(timeout));
+
+    let metadata = {
+        let admin = admin.clone();
+        tokio::task::spawn_blocking(move || admin.inner().fetch_metadata(None, timeout))
+            .await?
+            .context("fetching cluster metadata")?
+    };
+    // Partition count and replication factor of each topic in the cluster.
+    let layout: HashMap<&str, (usize, usize)> = metadata
+        .topics()
+        .iter()
+        .map(|topic| {
+            let replicas = topic
+                .partitions()
+                .first()
+                .map_or(0, |partition| partition.replicas().len());
+            (topic.name(), (topic.partitions().len(), replicas))
+        })
+        .collect();
+
+    let mut report = ProvisionReport::default();
+    let mut to_create = Vec::new();
+    for topic in declared_topics(cfg) {
+        let spec = provisioning.spec_for(&topic);
+        match layout.get(topic.as_str()) {
+            Some(&(partitions, replicas)) => {
+                let mut drift = |setting: &str, declared: Option<i32>, actual: usize| {
+                    if let Some(declared) = declared
+                        && declared != BROKER_DEFAULT
+                        && declared as usize != actual
+                    {
+                        report.drift.push(TopicDrift {
+                            topic: topic.clone(),
+                            setting: setting.into(),
+                            declared: declared.to_string(),
+                            actual: Some(actual.to_string()),
+                        });
+                    }
+                };
+                drift("partitions", spec.partitions, partitions);
+                drift("replication_factor", spec.replication_factor, replicas);
+                report.existing.push(topic);
+            }
+            None if provisioning.create_missing => to_create.push((topic, spec)),
+            None => report.missing.push(topic),
+        }
+    }
+
+    if !to_create.is_empty() {
+        let configs: Vec<_> = to_create.iter().map(|(_, spec)| spec.configs()).collect();
+        let new_topics: Vec<NewTopic> = to_create
+            .iter()
+            .zip(&configs)
+            .map(|((topic, spec), configs)| {
+                let replication = spec.replication_factor.unwrap_or(BROKER_DEFAULT);
+                configs.iter().fold(
+                    NewTopic::new(
+                        topic,
+                        spec.partitions.unwrap_or(BROKER_DEFAULT),
+                        TopicReplication::Fixed(replication),
+                    ),
+                    |new_topic, (name, value)| new_topic.set(name, value),
+                )
+            })
+            .collect();
+        for result in admin
+            .create_topics(&new_topics, &options)
+            .await
+            .context("creating topics")?
+        {
+            match result {
+                Ok(topic) => report.created.push(topic),
+                // Created by someone else in the meantime.
+                Err((topic, RDKafkaErrorCode::TopicAlreadyExists)) => report.existing.push(topic),
+                Err((topic, code)) => anyhow::bail!("creating topic {}: {}", topic, code),
+            }
+        }
+    }
+
+    let checked: Vec<(String, BTreeMap<String, String>)> = report
+        .existing
+        .iter()
+        .map(|topic| (topic.clone(), provisioning.spec_for(topic).configs()))
+        .filter(|(_, configs)| !configs.is_empty())
+        .collect();
+    if !checked.is_empty() {
+        let resources: Vec<_> = checked
+            .iter()
+            .map(|(topic, _)| ResourceSpecifier::Topic(topic))
+            .collect();
+        // Config drift is only a warning, so failing to check it is not fatal either.
+        let described = match admin.describe_configs(&resources, &options).await {
+            Ok(described) => described,
+            Err(e) => {
+                eprintln!("Could not describe topic configs: {}", e);
+                Vec::new()
+            }
+        };
+        for ((topic, declared), result) in checked.iter().zip(described) {
+            let resource = match result {
+                Ok(resource) => resource,
+                Err(code) => {
+                    eprintln!("Could not describe topic {}: {}", topic, code);
+                    continue;
+                }
+            };
+            for (name, value) in declared {
+                let actual = resource.get(name).and_then(|entry| entry.value.clone());
+                if actual.as_deref() != Some(value.as_str()) {
+                    report.drift.push(TopicDrift {
+                        topic: topic.clone(),
+                        setting: name.clone(),
+                        declared: value.clone(),
+                        actual,
+                    });
+                }
+            }
+        }
+    }
+    Ok(report)
+}
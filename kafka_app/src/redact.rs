@@ -0,0 +1,620 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::Ipv6Addr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Context;
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::helper::json_text;
+
+/// Top-level keys that are never redacted.
+const SKIPPED_FIELDS: [&str; 2] = ["level", "timestamp"];
+/// Top-level keys every log model needs. Dropping one empties it instead of removing it.
+const REQUIRED_FIELDS: [&str; 4] = ["message", "hostname", "reason", "error_code"];
+/// Bytes of the salted SHA-256 digest kept by [`RedactAction::Hash`].
+const HASH_BYTES: usize = 8;
+
+const TOKEN_PATTERN: &str = r#"(?i)(?:(?:\bbearer\s+|\b(?:api[_-]?key|access[_-]?token|auth[_-]?token|token|secret|password|passwd|pwd)["']?\s*[=:]\s*["']?)([^\s"',;&]{6,})|\b((?-i:eyJ)[\w-]+\.[\w-]+\.[\w-]*))"#;
+const EMAIL_PATTERN: &str = r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}";
+const CARD_NUMBER_PATTERN: &str = r"\b(?:\d[ -]?){12,18}\d\b";
+const IPV6_PATTERN: &str = r"(?i)(?:[0-9a-f]{0,4}:){2,7}(?:[0-9a-f]{1,4}|(?:\d{1,3}\.){3}\d{1,3})?";
+const IPV4_PATTERN: &str =
+    r"\b(?:(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\.){3}(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\b";
+
+/// Built-in detectors of personal data and secrets.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Detector {
+    /// Bearer tokens, JWTs and the values of `password=`, `api_key:`, `token=` and the like.
+    /// Only the secret is redacted, not the name in front of it.
+    Token,
+    Email,
+    /// 13 to 19 digits, optionally grouped by spaces or dashes, that pass the Luhn check.
+    CardNumber,
+    Ipv6,
+    Ipv4,
+}
+
+impl Detector {
+    /// Every detector, in the order they run by default.
+    pub const ALL: [Detector; 5] = [
+        Detector::Token,
+        Detector::Email,
+        Detector::CardNumber,
+        Detector::Ipv6,
+        Detector::Ipv4,
+    ];
+
+    pub fn parse(raw: &str) -> anyhow::Result<Self> {
+        match raw.to_ascii_lowercase().replace('-', "_").as_str() {
+            "token" => Ok(Detector::Token),
+            "email" => Ok(Detector::Email),
+            "card_number" => Ok(Detector::CardNumber),
+            "ipv6" => Ok(Detector::Ipv6),
+            "ipv4" => Ok(Detector::Ipv4),
+            other => anyhow::bail!("unsupported redaction detector {:?}", other),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Detector::Token => "token",
+            Detector::Email => "email",
+            Detector::CardNumber => "card_number",
+            Detector::Ipv6 => "ipv6",
+            Detector::Ipv4 => "ipv4",
+        }
+    }
+
+    fn pattern(&self) -> &'static str {
+        match self {
+            Detector::Token => TOKEN_PATTERN,
+            Detector::Email => EMAIL_PATTERN,
+            Detector::CardNumber => CARD_NUMBER_PATTERN,
+            Detector::Ipv6 => IPV6_PATTERN,
+            Detector::Ipv4 => IPV4_PATTERN,
+        }
+    }
+
+    /// Rules out matches of the pattern that are not what the detector looks for.
+    fn check(&self) -> Option<fn(&str) -> bool> {
+        match self {
+            Detector::CardNumber => Some(luhn_valid),
+            Detector::Ipv6 => Some(|text| text.parse::<Ipv6Addr>().is_ok()),
+            _ => None,
+        }
+    }
+}
+
+/// What replaces the data a rule finds.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RedactAction {
+    /// Replaced by `[REDACTED:<rule>]`.
+    #[default]
+    Mask,
+    /// Replaced by `[<rule>:<digest>]`, a salted SHA-256 digest, so equal values can still be
+    /// correlated across logs without being readable.
+    Hash,
+    /// The whole value holding the data is removed. Required top-level fields (`message`,
+    /// `hostname`, `reason`, `error_code`) are emptied instead.
+    Drop,
+}
+
+impl RedactAction {
+    pub fn parse(raw: &str) -> anyhow::Result<Self> {
+        match raw.to_ascii_lowercase().as_str() {
+            "mask" => Ok(RedactAction::Mask),
+            "hash" => Ok(RedactAction::Hash),
+            "drop" => Ok(RedactAction::Drop),
+            other => anyhow::bail!("unsupported redaction action {:?}", other),
+        }
+    }
+}
+
+/// A custom pattern. When the regex has capture groups, only the first group that took part
+/// in a match is redacted, so `(?i)ssn[=:](\d{9})` keeps the `ssn=` in front; use `(?:...)`
+/// for groups that are not meant for that.
+#[derive(Deserialize, Debug, Clone)]
+pub struct PatternRule {
+    pub name: String,
+    pub regex: String,
+    /// Defaults to [`RedactionConfig::action`].
+    #[serde(default)]
+    pub action: Option<RedactAction>,
+}
+
+/// The `[redaction]` section of the config file.
+///
+/// Custom `patterns` run first, then the `detectors` in the order listed, over every string in
+/// the serialized log except `level` and `timestamp`, and over numbers such as card numbers
+/// logged as integers. A value whose key (or dotted path, e.g. `fields.user.email`) has a rule
+/// in `fields` is redacted as a whole instead of being scanned. Record headers are not
+/// redacted.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct RedactionConfig {
+    pub detectors: Vec<Detector>,
+    /// Action of the detectors and of patterns without their own.
+    pub action: RedactAction,
+    /// Per-detector overrides of `action`, keyed by detector name.
+    pub actions: HashMap<String, RedactAction>,
+    pub patterns: Vec<PatternRule>,
+    pub fields: HashMap<String, RedactAction>,
+    /// Mixed into hashed values so they cannot be reversed by hashing guesses. Keep it secret
+    /// and stable: changing it changes every digest.
+    pub hash_salt: String,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        Self {
+            detectors: Detector::ALL.to_vec(),
+            action: RedactAction::default(),
+            actions: HashMap::new(),
+            patterns: Vec::new(),
+            fields: HashMap::new(),
+            hash_salt: String::new(),
+        }
+    }
+}
+
+impl fmt::Debug for RedactionConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedactionConfig")
+            .field("detectors", &self.detectors)
+            .field("action", &self.action)
+            .field("actions", &self.actions)
+            .field("patterns", &self.patterns)
+            .field("fields", &self.fields)
+            .field("hash_salt", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Counters of a [`Redactor`] since it was created.
+#[derive(Debug, Default, Clone)]
+pub struct RedactionMetrics {
+    /// Logs with at least one redaction.
+    pub redacted_logs: u64,
+    /// Redactions per detector, pattern and field rule. Field rules are keyed `field:<name>`.
+    pub redactions: BTreeMap<String, u64>,
+}
+
+struct PatternMatcher {
+    name: String,
+    regex: Regex,
+    check: Option<fn(&str) -> bool>,
+    action: RedactAction,
+    count: AtomicU64,
+}
+
+impl PatternMatcher {
+    fn finds(&self, text: &str) -> bool {
+        self.regex
+            .find_iter(text)
+            .any(|m| self.check.is_none_or(|check| check(m.as_str())))
+    }
+}
+
+struct FieldRule {
+    name: String,
+    action: RedactAction,
+    count: AtomicU64,
+}
+
+enum Scan {
+    Unchanged,
+    Changed(String),
+    Drop,
+}
+
+/// Applies a [`RedactionConfig`] to serialized logs.
+pub struct Redactor {
+    patterns: Vec<PatternMatcher>,
+    fields: Vec<FieldRule>,
+    hash_salt: String,
+    redacted_logs: AtomicU64,
+}
+
+impl Redactor {
+    pub fn new(cfg: &RedactionConfig) -> anyhow::Result<Self> {
+        for name in cfg.actions.keys() {
+            Detector::parse(name)?;
+        }
+        let mut patterns = Vec::new();
+        for rule in &cfg.patterns {
+            patterns.push(PatternMatcher {
+                name: rule.name.clone(),
+                regex: Regex::new(&rule.regex)
+                    .with_context(|| format!("invalid redaction pattern {}", rule.name))?,
+                check: None,
+                action: rule.action.unwrap_or(cfg.action),
+                count: AtomicU64::new(0),
+            });
+        }
+        for detector in &cfg.detectors {
+            patterns.push(PatternMatcher {
+                name: detector.name().into(),
+                regex: Regex::new(detector.pattern())?,
+                check: detector.check(),
+                action: cfg
+                    .actions
+                    .get(detector.name())
+                    .copied()
+                    .unwrap_or(cfg.action),
+                count: AtomicU64::new(0),
+            });
+        }
+        let fields = cfg
+            .fields
+            .iter()
+            .map(|(name, action)| FieldRule {
+                name: name.clone(),
+                action: *action,
+                count: AtomicU64::new(0),
+            })
+            .collect();
+
+        Ok(Self {
+            patterns,
+            fields,
+            hash_salt: cfg.hash_salt.clone(),
+            redacted_logs: AtomicU64::new(0),
+        })
+    }
+
+    /// Redacts a serialized log. Logs nothing applies to come back as they were.
+    pub fn redact_json(&self, json: String) -> anyhow::Result<String> {
+        // Escaping never hides a match, so a log no pattern matches needs no parsing.
+        if self.fields.is_empty() && !self.patterns.iter().any(|p| p.finds(&json)) {
+            return Ok(json);
+        }
+        let mut doc: Value = serde_json::from_str(&json).context("parsing log to redact")?;
+        if self.redact(&mut doc) == 0 {
+            return Ok(json);
+        }
+        Ok(serde_json::to_string(&doc)?)
+    }
+
+    /// Redacts a log in place and returns how many redactions were made.
+    pub fn redact(&self, doc: &mut Value) -> u64 {
+        let Value::Object(map) = doc else {
+            return 0;
+        };
+        let mut hits = 0;
+        let keys: Vec<String> = map.keys().cloned().collect();
+        for key in keys {
+            if SKIPPED_FIELDS.contains(&key.as_str()) {
+                continue;
+            }
+            let Some(value) = map.get_mut(&key) else {
+                continue;
+            };
+            if self.redact_value(&key, &key, value, &mut hits) {
+                if REQUIRED_FIELDS.contains(&key.as_str()) {
+                    *value = emptied(value);
+                } else {
+                    map.remove(&key);
+                }
+            }
+        }
+        if hits > 0 {
+            self.redacted_logs.fetch_add(1, Ordering::Relaxed);
+        }
+        hits
+    }
+
+    pub fn metrics(&self) -> RedactionMetrics {
+        let mut redactions = BTreeMap::new();
+        for pattern in &self.patterns {
+            *redactions.entry(pattern.name.clone()).or_default() +=
+                pattern.count.load(Ordering::Relaxed);
+        }
+        for field in &self.fields {
+            redactions.insert(
+                format!("field:{}", field.name),
+                field.count.load(Ordering::Relaxed),
+            );
+        }
+        RedactionMetrics {
+            redacted_logs: self.redacted_logs.load(Ordering::Relaxed),
+            redactions,
+        }
+    }
+
+    /// Redacts one value. Returns `true` when the value has to be dropped.
+    fn redact_value(&self, key: &str, path: &str, value: &mut Value, hits: &mut u64) -> bool {
+        if let Some(rule) = self
+            .fields
+            .iter()
+            .find(|rule| rule.name == key || rule.name == path)
+        {
+            rule.count.fetch_add(1, Ordering::Relaxed);
+            *hits += 1;
+            match rule.action {
+                RedactAction::Drop => return true,
+                action => {
+                    *value = Value::String(self.replace(&rule.name, action, &json_text(value)))
+                }
+            }
+            return false;
+        }
+
+        match value {
+            Value::String(text) => match self.scan(text, hits) {
+                Scan::Unchanged => false,
+                Scan::Changed(redacted) => {
+                    *text = redacted;
+                    false
+                }
+                Scan::Drop => true,
+            },
+            Value::Number(number) => match self.scan(&number.to_string(), hits) {
+                Scan::Unchanged => false,
+                Scan::Changed(redacted) => {
+                    *value = Value::String(redacted);
+                    false
+                }
+                Scan::Drop => true,
+            },
+            Value::Array(items) => {
+                items.retain_mut(|item| !self.redact_value(key, path, item, hits));
+                false
+            }
+            Value::Object(map) => {
+                map.retain(|child, item| {
+                    !self.redact_value(child, &format!("{}.{}", path, child), item, hits)
+                });
+                false
+            }
+            Value::Bool(_) | Value::Null => false,
+        }
+    }
+
+    fn scan(&self, text: &str, hits: &mut u64) -> Scan {
+        let mut current: Option<String> = None;
+        for pattern in &self.patterns {
+            let input = current.as_deref().unwrap_or(text);
+            let mut found = 0;
+            let redacted = pattern.regex.replace_all(input, |caps: &Captures| {
+                let whole = caps.get(0).map_or("", |m| m.as_str());
+                if pattern.check.is_some_and(|check| !check(whole)) {
+                    return whole.to_string();
+                }
+                found += 1;
+                match caps.iter().skip(1).flatten().next() {
+                    Some(secret) => {
+                        let start = secret.start() - caps.get(0).map_or(0, |m| m.start());
+                        format!(
+                            "{}{}{}",
+                            &whole[..start],
+                            self.replace(&pattern.name, pattern.action, secret.as_str()),
+                            &whole[start + secret.len()..]
+                        )
+                    }
+                    None => self.replace(&pattern.name, pattern.action, whole),
+                }
+            });
+            if found == 0 {
+                continue;
+            }
+            pattern.count.fetch_add(found, Ordering::Relaxed);
+            *hits += found;
+            if pattern.action == RedactAction::Drop {
+                return Scan::Drop;
+            }
+            current = Some(redacted.into_owned());
+        }
+        match current {
+            Some(redacted) => Scan::Changed(redacted),
+            None => Scan::Unchanged,
+        }
+    }
+
+    fn replace(&self, rule: &str, action: RedactAction, text: &str) -> String {
+        match action {
+            RedactAction::Hash => {
+                let digest = Sha256::new()
+                    .chain_update(self.hash_salt.as_bytes())
+                    .chain_update(text.as_bytes())
+                    .finalize();
+                let hex: String = digest[..HASH_BYTES]
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect();
+                format!("[{}:{}]", rule, hex)
+            }
+            RedactAction::Mask | RedactAction::Drop => format!("[REDACTED:{}]", rule),
+        }
+    }
+}
+
+fn emptied(value: &Value) -> Value {
+    match value {
+        Value::Number(_) => Value::from(0),
+        _ => Value::String(String::new()),
+    }
+}
+
+fn luhn_valid(text: &str) -> bool {
+    let digits: Vec<u32> = text.chars().filter_map(|c| c.to_digit(10)).collect();
+    if !(13..=19).contains(&digits.len()) {
+        return false;
+    }
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, digit)| match (i % 2 == 1, digit * 2) {
+            (true, doubled) if doubled > 9 => doubled - 9,
+            (true, doubled) => doubled,
+            (false, _) => *digit,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::models::{ErrorLog, InfoLog, LogContext, WarnLog};
+
+    fn redactor(cfg: RedactionConfig) -> Redactor {
+        Redactor::new(&cfg).unwrap()
+    }
+
+    fn redacted<T: serde::Serialize>(redactor: &Redactor, entry: &T) -> Value {
+        let json = serde_json::to_string(entry).unwrap();
+        serde_json::from_str(&redactor.redact_json(json).unwrap()).unwrap()
+    }
+
+    fn context(fields: Value) -> LogContext {
+        LogContext {
+            fields: fields.as_object().cloned().unwrap(),
+            ..LogContext::default()
+        }
+    }
+
+    #[test]
+    fn masks_info_log_message_and_hostname() {
+        let redactor = redactor(RedactionConfig::default());
+        let log = InfoLog::new(
+            "INFO".into(),
+            "login by jane.doe@example.com from 10.1.2.3 and fe80::1ff:fe23:4567:890a".into(),
+            "10.1.2.3".into(),
+            Utc::now(),
+        );
+        let doc = redacted(&redactor, &log);
+        assert_eq!(
+            doc["message"],
+            "login by [REDACTED:email] from [REDACTED:ipv4] and [REDACTED:ipv6]"
+        );
+        assert_eq!(doc["hostname"], "[REDACTED:ipv4]");
+        assert_eq!(doc["level"], "INFO");
+
+        let metrics = redactor.metrics();
+        assert_eq!(metrics.redacted_logs, 1);
+        assert_eq!(metrics.redactions["email"], 1);
+        assert_eq!(metrics.redactions["ipv4"], 2);
+        assert_eq!(metrics.redactions["ipv6"], 1);
+        assert_eq!(metrics.redactions["card_number"], 0);
+    }
+
+    #[test]
+    fn keeps_token_names_and_checks_card_numbers() {
+        let redactor = redactor(RedactionConfig::default());
+        let log = WarnLog::new(
+            "WARN".into(),
+            "retrying with Authorization: Bearer abcdef123456 and password=hunter22".into(),
+            "web-1".into(),
+            Utc::now(),
+            "card 4111 1111 1111 1111 declined, order 1234567890123 kept".into(),
+        );
+        let doc = redacted(&redactor, &log);
+        assert_eq!(
+            doc["message"],
+            "retrying with Authorization: Bearer [REDACTED:token] and password=[REDACTED:token]"
+        );
+        assert_eq!(
+            doc["reason"],
+            "card [REDACTED:card_number] declined, order 1234567890123 kept"
+        );
+        assert_eq!(doc["hostname"], "web-1");
+    }
+
+    #[test]
+    fn applies_field_rules_to_error_log_context() {
+        let redactor = redactor(RedactionConfig {
+            fields: HashMap::from([
+                ("password".into(), RedactAction::Drop),
+                ("fields.user.email".into(), RedactAction::Hash),
+            ]),
+            hash_salt: "pepper".into(),
+            ..RedactionConfig::default()
+        });
+        let log = ErrorLog::new(
+            "ERROR".into(),
+            "payment failed".into(),
+            "web-1".into(),
+            Utc::now(),
+            500,
+        )
+        .with_context(context(json!({
+            "password": "hunter22",
+            "user": {"email": "jane.doe@example.com", "id": 42},
+            "card": 4111111111111111u64,
+            "peers": ["ok", "jane.doe@example.com"],
+        })));
+        let doc = redacted(&redactor, &log);
+        let fields = &doc["fields"];
+        assert!(fields.get("password").is_none());
+        let hashed = fields["user"]["email"].as_str().unwrap();
+        assert!(hashed.starts_with("[fields.user.email:"));
+        assert_eq!(fields["user"]["id"], 42);
+        assert_eq!(fields["card"], "[REDACTED:card_number]");
+        assert_eq!(fields["peers"], json!(["ok", "[REDACTED:email]"]));
+        assert_eq!(doc["error_code"], 500);
+
+        let again = redacted(&redactor, &log);
+        assert_eq!(again["fields"]["user"]["email"], hashed);
+        assert_eq!(redactor.metrics().redactions["field:password"], 2);
+    }
+
+    #[test]
+    fn drop_and_custom_patterns() {
+        let redactor = redactor(RedactionConfig {
+            detectors: vec![Detector::Email],
+            actions: HashMap::from([("email".into(), RedactAction::Drop)]),
+            patterns: vec![PatternRule {
+                name: "employee_id".into(),
+                regex: r"(?i)employee[=:](E\d{6})".into(),
+                action: Some(RedactAction::Hash),
+            }],
+            ..RedactionConfig::default()
+        });
+        let log = InfoLog::new(
+            "INFO".into(),
+            "mail sent to jane.doe@example.com".into(),
+            "web-1".into(),
+            Utc::now(),
+        )
+        .with_context(context(json!({"by": "employee=E123456", "cc": "a@b.io"})));
+        let doc = redacted(&redactor, &log);
+        assert_eq!(doc["message"], "");
+        assert!(doc["fields"].get("cc").is_none());
+        let by = doc["fields"]["by"].as_str().unwrap();
+        assert!(by.starts_with("employee=[employee_id:"), "{}", by);
+    }
+
+    #[test]
+    fn leaves_clean_logs_untouched() {
+        let redactor = redactor(RedactionConfig::default());
+        let log = InfoLog::new(
+            "INFO".into(),
+            "started in 12:30:45, build 1.2.3".into(),
+            "web-1".into(),
+            Utc::now(),
+        );
+        let json = serde_json::to_string(&log).unwrap();
+        assert_eq!(redactor.redact_json(json.clone()).unwrap(), json);
+        assert_eq!(redactor.metrics().redacted_logs, 0);
+    }
+
+    #[test]
+    fn rejects_unknown_detectors() {
+        assert!(Detector::parse("ssn").is_err());
+        let cfg = RedactionConfig {
+            actions: HashMap::from([("ssn".into(), RedactAction::Hash)]),
+            ..RedactionConfig::default()
+        };
+        assert!(Redactor::new(&cfg).is_err());
+    }
+}
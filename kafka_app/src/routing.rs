@@ -0,0 +1,314 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::constant::{ERROR_TOPIC, INFO_TOPIC, WARN_TOPIC};
+use crate::helper::{log_field, wildcard_match};
+use crate::models::LogType;
+
+/// A routing rule. Every condition that is set must match; `*` in a pattern matches any run of
+/// characters. `fields` names are looked up at the top level of the serialized log first, then
+/// in its `fields` map.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RoutingRule {
+    pub topic: String,
+    #[serde(default)]
+    pub log_type: Option<LogType>,
+    #[serde(default)]
+    pub level: Option<String>,
+    #[serde(default)]
+    pub hostname: Option<String>,
+    #[serde(default)]
+    pub fields: HashMap<String, String>,
+}
+
+impl RoutingRule {
+    fn needs_document(&self) -> bool {
+        self.level.is_some() || self.hostname.is_some() || !self.fields.is_empty()
+    }
+
+    fn matches(&self, log_type: LogType, doc: Option<&Value>) -> bool {
+        if self.log_type.is_some_and(|t| t != log_type) {
+            return false;
+        }
+        if !self.needs_document() {
+            return true;
+        }
+        let Some(doc) = doc else {
+            return false;
+        };
+
+        let field_matches = |name: &str, pattern: &str| match log_field(doc, name) {
+            Some(Value::String(s)) => wildcard_match(pattern, s),
+            Some(other) => wildcard_match(pattern, &other.to_string()),
+            None => false,
+        };
+
+        self.level
+            .as_deref()
+            .is_none_or(|pattern| field_matches("level", pattern))
+            && self
+                .hostname
+                .as_deref()
+                .is_none_or(|pattern| field_matches("hostname", pattern))
+            && self
+                .fields
+                .iter()
+                .all(|(name, pattern)| field_matches(name, pattern))
+    }
+}
+
+/// Topic routing table, loaded from the `[routing]` section of the config file.
+///
+/// Rules are checked in order and the first match wins. Otherwise the topic for the log type is
+/// used, then `default_topic`. The prefix for the current `environment` is prepended to whichever
+/// topic is chosen.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RoutingConfig {
+    pub default_topic: String,
+    pub topics: HashMap<LogType, String>,
+    pub rules: Vec<RoutingRule>,
+    pub environment: Option<String>,
+    pub prefixes: HashMap<String, String>,
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        Self {
+            default_topic: INFO_TOPIC.into(),
+            topics: HashMap::from([
+                (LogType::Info, INFO_TOPIC.into()),
+                (LogType::Warn, WARN_TOPIC.into()),
+                (LogType::Error, ERROR_TOPIC.into()),
+            ]),
+            rules: Vec::new(),
+            environment: None,
+            prefixes: HashMap::new(),
+        }
+    }
+}
+
+impl RoutingConfig {
+    pub fn prefix(&self) -> &str {
+        self.environment
+            .as_ref()
+            .and_then(|env| self.prefixes.get(env))
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+
+    /// Resolves the topic for a log. `payload` is the serialized log and is only parsed when a
+    /// rule needs to look inside it.
+    pub fn route(&self, log_type: LogType, payload: &str) -> String {
+        let doc = if self.rules.iter().any(RoutingRule::needs_document) {
+            serde_json::from_str::<Value>(payload).ok()
+        } else {
+            None
+        };
+
+        let topic = self
+            .rules
+            .iter()
+            .find(|rule| rule.matches(log_type, doc.as_ref()))
+            .map(|rule| rule.topic.as_str())
+            .or_else(|| self.topics.get(&log_type).map(String::as_str))
+            .unwrap_or(&self.default_topic);
+
+        format!("{}{}", self.prefix(), topic)
+    }
+
+    /// Every topic a log can be routed to, prefix included.
+    pub fn all_topics(&self) -> BTreeSet<String> {
+        self.rules
+            .iter()
+            .map(|rule| rule.topic.as_str())
+            .chain(self.topics.values().map(String::as_str))
+            .chain([self.default_topic.as_str()])
+            .map(|topic| format!("{}{}", self.prefix(), topic))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn rule(topic: &str) -> RoutingRule {
+        RoutingRule {
+            topic: topic.into(),
+            log_type: None,
+            level: None,
+            hostname: None,
+            fields: HashMap::new(),
+        }
+    }
+
+    fn routing(rules: Vec<RoutingRule>) -> RoutingConfig {
+        RoutingConfig {
+            rules,
+            ..RoutingConfig::default()
+        }
+    }
+
+    fn payload(level: &str, hostname: &str) -> String {
+        json!({
+            "level": level,
+            "message": "m",
+            "hostname": hostname,
+            "fields": {"service": "checkout", "attempt": 3},
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn without_rules_the_log_type_picks_the_topic() {
+        let routing = RoutingConfig::default();
+        assert_eq!(routing.route(LogType::Info, "{}"), INFO_TOPIC);
+        assert_eq!(routing.route(LogType::Warn, "{}"), WARN_TOPIC);
+        assert_eq!(routing.route(LogType::Error, "not json"), ERROR_TOPIC);
+    }
+
+    #[test]
+    fn the_default_topic_covers_unmapped_log_types() {
+        let routing = RoutingConfig {
+            default_topic: "logs.other".into(),
+            topics: HashMap::from([(LogType::Error, "logs.errors".into())]),
+            ..RoutingConfig::default()
+        };
+        assert_eq!(routing.route(LogType::Error, "{}"), "logs.errors");
+        assert_eq!(routing.route(LogType::Warn, "{}"), "logs.other");
+        assert_eq!(routing.route(LogType::Info, "{}"), "logs.other");
+    }
+
+    #[test]
+    fn the_first_matching_rule_wins() {
+        let routing = routing(vec![
+            RoutingRule {
+                hostname: Some("db-*".into()),
+                ..rule("logs.db")
+            },
+            RoutingRule {
+                log_type: Some(LogType::Error),
+                ..rule("logs.all-errors")
+            },
+            RoutingRule {
+                log_type: Some(LogType::Error),
+                ..rule("logs.never")
+            },
+        ]);
+        assert_eq!(
+            routing.route(LogType::Error, &payload("ERROR", "db-1")),
+            "logs.db"
+        );
+        assert_eq!(
+            routing.route(LogType::Error, &payload("ERROR", "web-1")),
+            "logs.all-errors"
+        );
+        assert_eq!(
+            routing.route(LogType::Warn, &payload("WARN", "web-1")),
+            WARN_TOPIC
+        );
+    }
+
+    #[test]
+    fn level_and_hostname_patterns_match_the_log() {
+        let routing = routing(vec![RoutingRule {
+            level: Some("CRIT*".into()),
+            hostname: Some("*.eu".into()),
+            ..rule("logs.eu-critical")
+        }]);
+        assert_eq!(
+            routing.route(LogType::Error, &payload("CRITICAL", "web-1.eu")),
+            "logs.eu-critical"
+        );
+        // Every condition must match.
+        assert_eq!(
+            routing.route(LogType::Error, &payload("CRITICAL", "web-1.us")),
+            ERROR_TOPIC
+        );
+        assert_eq!(
+            routing.route(LogType::Error, &payload("ERROR", "web-1.eu")),
+            ERROR_TOPIC
+        );
+    }
+
+    #[test]
+    fn field_patterns_look_in_the_fields_map_and_match_non_strings() {
+        let routing = routing(vec![
+            RoutingRule {
+                fields: HashMap::from([("service".into(), "check*".into())]),
+                ..rule("logs.checkout")
+            },
+            RoutingRule {
+                fields: HashMap::from([("attempt".into(), "3".into())]),
+                ..rule("logs.third-attempt")
+            },
+            RoutingRule {
+                fields: HashMap::from([("missing".into(), "*".into())]),
+                ..rule("logs.never")
+            },
+        ]);
+        assert_eq!(
+            routing.route(LogType::Info, &payload("INFO", "web-1")),
+            "logs.checkout"
+        );
+        let other_service = json!({"fields": {"service": "cart", "attempt": 3}}).to_string();
+        assert_eq!(
+            routing.route(LogType::Info, &other_service),
+            "logs.third-attempt"
+        );
+        let bare = json!({"fields": {}}).to_string();
+        assert_eq!(routing.route(LogType::Info, &bare), INFO_TOPIC);
+    }
+
+    #[test]
+    fn rules_that_look_inside_the_log_skip_unparsable_payloads() {
+        let routing = routing(vec![
+            RoutingRule {
+                level: Some("*".into()),
+                ..rule("logs.any-level")
+            },
+            RoutingRule {
+                log_type: Some(LogType::Warn),
+                ..rule("logs.warnings")
+            },
+        ]);
+        assert_eq!(routing.route(LogType::Warn, "not json"), "logs.warnings");
+        assert_eq!(routing.route(LogType::Info, "not json"), INFO_TOPIC);
+    }
+
+    #[test]
+    fn the_environment_prefix_is_prepended() {
+        let routing = RoutingConfig {
+            rules: vec![RoutingRule {
+                log_type: Some(LogType::Error),
+                ..rule("logs.errors")
+            }],
+            environment: Some("staging".into()),
+            prefixes: HashMap::from([
+                ("staging".into(), "stg.".into()),
+                ("prod".into(), "prd.".into()),
+            ]),
+            ..RoutingConfig::default()
+        };
+        assert_eq!(routing.prefix(), "stg.");
+        assert_eq!(routing.route(LogType::Error, "{}"), "stg.logs.errors");
+        assert_eq!(
+            routing.route(LogType::Info, "{}"),
+            format!("stg.{}", INFO_TOPIC)
+        );
+        assert!(routing.all_topics().iter().all(|t| t.starts_with("stg.")));
+
+        // An environment without a prefix routes to the bare topic.
+        let unprefixed = RoutingConfig {
+            environment: Some("dev".into()),
+            ..routing
+        };
+        assert_eq!(unprefixed.prefix(), "");
+        assert_eq!(unprefixed.route(LogType::Error, "{}"), "logs.errors");
+    }
+}
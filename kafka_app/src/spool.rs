@@ -0,0 +1,806 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rdkafka::error::KafkaError;
+use rdkafka::producer::{DeliveryFuture, FutureRecord};
+use rdkafka::types::RDKafkaErrorCode;
+use serde::Deserialize;
+use tokio::time::sleep;
+
+use crate::helper::owned_headers;
+use crate::producer::{InnerProducer, is_spoolable};
+
+const SEGMENT_EXTENSION: &str = "seg";
+const OFFSET_EXTENSION: &str = "offset";
+/// Key length written for records produced without a key.
+const NO_KEY: u32 = u32::MAX;
+/// Timestamp written for records produced without one.
+const NO_TIMESTAMP: i64 = i64::MIN;
+/// Set in the length prefix of frames whose body starts with a format version byte. Frames
+/// written before the format was versioned never have it: their bodies are far below 2 GiB.
+const VERSIONED: u32 = 1 << 31;
+/// Format of the frames written now. Frames without a version are in the original layout.
+const FORMAT_VERSION: u8 = 1;
+/// How many replayed records may go by before the segment offset is persisted again.
+const OFFSET_SYNC_EVERY: u64 = 100;
+/// How many replayed records may wait for their delivery report at once. An idempotent producer
+/// (the at-least-once and exactly-once guarantees) still writes them in spool order.
+const REPLAY_WINDOW: usize = 500;
+/// Pause before retrying a replayed record librdkafka's queue had no room for.
+const QUEUE_FULL_BACKOFF: Duration = Duration::from_millis(50);
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SpoolConfig {
+    pub dir: PathBuf,
+    /// A segment is sealed and a new one started once it grows past this size.
+    pub segment_bytes: u64,
+    /// Records that would push the spool past this size are dropped.
+    pub max_bytes: u64,
+    pub replay_interval_ms: u64,
+}
+
+impl Default for SpoolConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("spool"),
+            segment_bytes: 16 * 1024 * 1024,
+            max_bytes: 1024 * 1024 * 1024,
+            replay_interval_ms: 1000,
+        }
+    }
+}
+
+/// A record exactly as it would have been handed to librdkafka.
+#[derive(Debug, Clone)]
+pub struct SpooledRecord {
+    pub topic: String,
+    pub key: Option<Vec<u8>>,
+    /// Record timestamp in milliseconds since the epoch.
+    pub timestamp: Option<i64>,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub payload: Vec<u8>,
+}
+
+impl SpooledRecord {
+    /// `[u32 body len | VERSIONED][u8 FORMAT_VERSION][u16 topic len][topic]
+    /// [u32 key len | NO_KEY][key][i64 timestamp | NO_TIMESTAMP][u16 header count]
+    /// ([u16 name len][name][u32 value len][value])*[payload]`, little endian.
+    ///
+    /// Unversioned frames from older spools are `[u32 body len][u16 topic len][topic]
+    /// [u32 key len | NO_KEY][key][payload]`.
+    fn encode(&self) -> Vec<u8> {
+        let key = self.key.as_deref().unwrap_or_default();
+        let headers_len: usize = self
+            .headers
+            .iter()
+            .map(|(name, value)| 2 + name.len() + 4 + value.len())
+            .sum();
+        let body_len =
+            1 + 2 + self.topic.len() + 4 + key.len() + 8 + 2 + headers_len + self.payload.len();
+
+        let mut buf = Vec::with_capacity(4 + body_len);
+        buf.extend_from_slice(&(body_len as u32 | VERSIONED).to_le_bytes());
+        buf.push(FORMAT_VERSION);
+        buf.extend_from_slice(&(self.topic.len() as u16).to_le_bytes());
+        buf.extend_from_slice(self.topic.as_bytes());
+        match &self.key {
+            Some(key) => buf.extend_from_slice(&(key.len() as u32).to_le_bytes()),
+            None => buf.extend_from_slice(&NO_KEY.to_le_bytes()),
+        }
+        buf.extend_from_slice(key);
+        buf.extend_from_slice(&self.timestamp.unwrap_or(NO_TIMESTAMP).to_le_bytes());
+        buf.extend_from_slice(&(self.headers.len() as u16).to_le_bytes());
+        for (name, value) in &self.headers {
+            buf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            buf.extend_from_slice(name.as_bytes());
+            buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            buf.extend_from_slice(value);
+        }
+        buf.extend_from_slice(&self.payload);
+        buf
+    }
+
+    /// Splits a length prefix into the body length and whether the body is versioned.
+    fn frame_header(prefix: [u8; 4]) -> (usize, bool) {
+        let prefix = u32::from_le_bytes(prefix);
+        ((prefix & !VERSIONED) as usize, prefix & VERSIONED != 0)
+    }
+
+    fn decode(body: &[u8], versioned: bool) -> io::Result<Self> {
+        let invalid = || io::Error::new(ErrorKind::InvalidData, "corrupt spool record");
+
+        let body = if versioned {
+            match body.split_first() {
+                Some((&FORMAT_VERSION, rest)) => rest,
+                Some((version, _)) => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("unknown spool format version {}", version),
+                    ));
+                }
+                None => return Err(invalid()),
+            }
+        } else {
+            body
+        };
+
+        let (topic_len, rest) = body.split_first_chunk::<2>().ok_or_else(invalid)?;
+        let topic_len = u16::from_le_bytes(*topic_len) as usize;
+        if rest.len() < topic_len {
+            return Err(invalid());
+        }
+        let (topic, rest) = rest.split_at(topic_len);
+        let topic = String::from_utf8(topic.to_vec()).map_err(|_| invalid())?;
+
+        let (key_len, rest) = rest.split_first_chunk::<4>().ok_or_else(invalid)?;
+        let (key, rest) = match u32::from_le_bytes(*key_len) {
+            NO_KEY => (None, rest),
+            len if rest.len() >= len as usize => {
+                let (key, rest) = rest.split_at(len as usize);
+                (Some(key.to_vec()), rest)
+            }
+            _ => return Err(invalid()),
+        };
+        if !versioned {
+            return Ok(Self {
+                topic,
+                key,
+                timestamp: None,
+                headers: Vec::new(),
+                payload: rest.to_vec(),
+            });
+        }
+
+        let (timestamp, rest) = rest.split_first_chunk::<8>().ok_or_else(invalid)?;
+        let timestamp = match i64::from_le_bytes(*timestamp) {
+            NO_TIMESTAMP => None,
+            timestamp => Some(timestamp),
+        };
+
+        let (count, mut rest) = rest.split_first_chunk::<2>().ok_or_else(invalid)?;
+        let mut headers = Vec::with_capacity(u16::from_le_bytes(*count) as usize);
+        for _ in 0..u16::from_le_bytes(*count) {
+            let (name_len, tail) = rest.split_first_chunk::<2>().ok_or_else(invalid)?;
+            let name_len = u16::from_le_bytes(*name_len) as usize;
+            if tail.len() < name_len {
+                return Err(invalid());
+            }
+            let (name, tail) = tail.split_at(name_len);
+            let (value_len, tail) = tail.split_first_chunk::<4>().ok_or_else(invalid)?;
+            let value_len = u32::from_le_bytes(*value_len) as usize;
+            if tail.len() < value_len {
+                return Err(invalid());
+            }
+            let (value, tail) = tail.split_at(value_len);
+            let name = String::from_utf8(name.to_vec()).map_err(|_| invalid())?;
+            headers.push((name, value.to_vec()));
+            rest = tail;
+        }
+
+        Ok(Self {
+            topic,
+            key,
+            timestamp,
+            headers,
+            payload: rest.to_vec(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpoolMetrics {
+    /// Bytes currently on disk waiting to be replayed.
+    pub spooled_bytes: u64,
+    pub spooled_records: u64,
+    pub replayed_records: u64,
+    pub dropped_records: u64,
+    /// Spooled records skipped on replay because they could not be decoded or the broker
+    /// rejected them for good.
+    pub discarded_records: u64,
+}
+
+struct ActiveSegment {
+    seq: u64,
+    file: Option<File>,
+    len: u64,
+}
+
+/// Local write-ahead spool for records the broker could not take.
+///
+/// Records are appended to numbered segment files. [`Spool::replay`] produces them again in order,
+/// oldest segment first, and deletes each segment once all of its records are delivered. Up to
+/// `REPLAY_WINDOW` records are in flight at once, and the position inside the segment being
+/// replayed only moves past records whose delivery is settled. It is kept in a side file so a
+/// restart resumes there.
+pub struct Spool {
+    cfg: SpoolConfig,
+    active: Mutex<ActiveSegment>,
+    bytes: AtomicU64,
+    spooled: AtomicU64,
+    replayed: AtomicU64,
+    dropped: AtomicU64,
+    discarded: AtomicU64,
+}
+
+impl Spool {
+    /// Opens (or creates) the spool directory and picks up segments left by a previous run.
+    pub fn open(cfg: SpoolConfig) -> io::Result<Self> {
+        fs::create_dir_all(&cfg.dir)?;
+
+        let mut pending = 0;
+        let mut next_seq = 0;
+        for seq in list_segments(&cfg.dir)? {
+            let len = fs::metadata(segment_path(&cfg.dir, seq))?.len();
+            pending += len.saturating_sub(read_offset(&cfg.dir, seq)?);
+            next_seq = seq + 1;
+        }
+
+        Ok(Self {
+            cfg,
+            active: Mutex::new(ActiveSegment {
+                seq: next_seq,
+                file: None,
+                len: 0,
+            }),
+            bytes: AtomicU64::new(pending),
+            spooled: AtomicU64::new(0),
+            replayed: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            discarded: AtomicU64::new(0),
+        })
+    }
+
+    /// Whether records are waiting on disk. While they are, new records should be spooled too so
+    /// they are not delivered ahead of older ones.
+    pub fn has_pending(&self) -> bool {
+        self.bytes.load(Ordering::Acquire) > 0
+    }
+
+    pub fn metrics(&self) -> SpoolMetrics {
+        SpoolMetrics {
+            spooled_bytes: self.bytes.load(Ordering::Relaxed),
+            spooled_records: self.spooled.load(Ordering::Relaxed),
+            replayed_records: self.replayed.load(Ordering::Relaxed),
+            dropped_records: self.dropped.load(Ordering::Relaxed),
+            discarded_records: self.discarded.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Appends a record. Returns `Ok(false)` when the record was dropped because the spool is full.
+    pub fn append(&self, record: &SpooledRecord) -> io::Result<bool> {
+        let frame = record.encode();
+        let frame_len = frame.len() as u64;
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+
+        if self.bytes.load(Ordering::Acquire) + frame_len > self.cfg.max_bytes {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return Ok(false);
+        }
+        if active.len > 0 && active.len + frame_len > self.cfg.segment_bytes {
+            seal(&mut active);
+        }
+        if active.file.is_none() {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(segment_path(&self.cfg.dir, active.seq))?;
+            active.file = Some(file);
+        }
+        let written_len = active.len;
+        if let Some(file) = active.file.as_mut()
+            && let Err(e) = file.write_all(&frame)
+        {
+            // Cut off whatever part of the frame made it to disk so the segment stays readable.
+            // If that fails too, the torn frame is left as the tail of a sealed segment.
+            if let Err(truncate) = file.set_len(written_len) {
+                eprintln!(
+                    "Could not truncate spool segment {}: {}",
+                    active.seq, truncate
+                );
+                seal(&mut active);
+            }
+            return Err(e);
+        }
+
+        active.len += frame_len;
+        self.bytes.fetch_add(frame_len, Ordering::Release);
+        self.spooled.fetch_add(1, Ordering::Relaxed);
+        Ok(true)
+    }
+
+    /// Replays spooled records forever, retrying every `replay_interval_ms` after a failure.
+    pub async fn replay(self: Arc<Self>, producer: InnerProducer, send_timeout: Duration) {
+        let interval = Duration::from_millis(self.cfg.replay_interval_ms);
+        loop {
+            sleep(interval).await;
+            if !self.has_pending() {
+                continue;
+            }
+            for seq in self.sealed_segments() {
+                if let Err(e) = self.replay_segment(seq, &producer, send_timeout).await {
+                    eprintln!("Spool replay paused at segment {}: {}", seq, e);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Segments that are no longer written to. The active segment is sealed when nothing else is
+    /// left, so a quiet spool still drains completely.
+    fn sealed_segments(&self) -> Vec<u64> {
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        let mut sealed: Vec<u64> = list_segments(&self.cfg.dir)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|seq| *seq < active.seq)
+            .collect();
+        if sealed.is_empty() && active.len > 0 {
+            sealed.push(active.seq);
+            seal(&mut active);
+        }
+        sealed
+    }
+
+    async fn replay_segment(
+        &self,
+        seq: u64,
+        producer: &InnerProducer,
+        send_timeout: Duration,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let path = segment_path(&self.cfg.dir, seq);
+        let mut file = File::open(&path)?;
+        let file_len = file.metadata()?.len();
+        // `offset` is where every record before it is delivered or discarded; `read_at` is where
+        // the next frame starts, past the records still in flight.
+        let mut offset = read_offset(&self.cfg.dir, seq)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::new(file);
+        let mut read_at = offset;
+        let mut in_flight: VecDeque<InFlight> = VecDeque::new();
+        let mut since_sync = 0;
+
+        loop {
+            let mut len = [0u8; 4];
+            match reader.read_exact(&mut len) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            let (body_len, versioned) = SpooledRecord::frame_header(len);
+            // A torn write from a crash, or a corrupt prefix; nothing after it can be trusted.
+            if body_len as u64 > file_len.saturating_sub(read_at + 4) {
+                break;
+            }
+            let mut body = vec![0u8; body_len];
+            reader.read_exact(&mut body)?;
+            let frame_len = 4 + body_len as u64;
+
+            let record = match SpooledRecord::decode(&body, versioned) {
+                Ok(record) => record,
+                Err(e) => {
+                    eprintln!(
+                        "Discarding spooled record at segment {} offset {}: {}",
+                        seq, read_at, e
+                    );
+                    in_flight.push_back(InFlight::discarded(read_at, frame_len));
+                    read_at += frame_len;
+                    continue;
+                }
+            };
+            let mut waited = Duration::ZERO;
+            let delivery = loop {
+                let mut future_record = FutureRecord::to(&record.topic)
+                    .payload(&record.payload)
+                    .headers(owned_headers(&record.headers));
+                if let Some(key) = &record.key {
+                    future_record = future_record.key(key);
+                }
+                if let Some(timestamp) = record.timestamp {
+                    future_record = future_record.timestamp(timestamp);
+                }
+                match producer.send_result(future_record) {
+                    Ok(delivery) => break Ok(delivery),
+                    // The local queue is full: make room by waiting for the oldest delivery, or
+                    // for other traffic to drain for up to `send_timeout`.
+                    Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), _))
+                        if !in_flight.is_empty() =>
+                    {
+                        self.complete(seq, &mut offset, &mut in_flight, &mut since_sync)
+                            .await?;
+                    }
+                    Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), _))
+                        if waited < send_timeout =>
+                    {
+                        sleep(QUEUE_FULL_BACKOFF).await;
+                        waited += QUEUE_FULL_BACKOFF;
+                    }
+                    Err((err, _)) => break Err(err),
+                }
+            };
+            in_flight.push_back(InFlight {
+                start: read_at,
+                frame_len,
+                topic: record.topic,
+                delivery: Some(delivery),
+            });
+            read_at += frame_len;
+            while in_flight.len() >= REPLAY_WINDOW {
+                self.complete(seq, &mut offset, &mut in_flight, &mut since_sync)
+                    .await?;
+            }
+        }
+        while !in_flight.is_empty() {
+            self.complete(seq, &mut offset, &mut in_flight, &mut since_sync)
+                .await?;
+        }
+
+        // Whatever was left unread (a torn tail) no longer counts as pending.
+        self.bytes
+            .fetch_sub(file_len.saturating_sub(offset), Ordering::Release);
+        fs::remove_file(&path)?;
+        let _ = fs::remove_file(offset_path(&self.cfg.dir, seq));
+        Ok(())
+    }
+
+    /// Waits for the oldest replayed record and moves `offset` past it. A record that failed for
+    /// a reason a retry can fix stops the replay, with `offset` left at that record.
+    async fn complete(
+        &self,
+        seq: u64,
+        offset: &mut u64,
+        in_flight: &mut VecDeque<InFlight>,
+        since_sync: &mut u64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(oldest) = in_flight.pop_front() else {
+            return Ok(());
+        };
+        let result = match oldest.delivery {
+            None => None,
+            Some(Ok(delivery)) => Some(match delivery.await {
+                Ok(Ok(_)) => Ok(()),
+                Ok(Err((err, _))) => Err(err),
+                Err(_) => Err(KafkaError::Canceled),
+            }),
+            Some(Err(err)) => Some(Err(err)),
+        };
+        match result {
+            Some(Ok(())) => {
+                self.replayed.fetch_add(1, Ordering::Relaxed);
+            }
+            // Retrying would fail the same way and hold back everything behind it.
+            Some(Err(err)) if !is_spoolable(&err) => {
+                eprintln!(
+                    "Discarding spooled record for {} at segment {} offset {}: {}",
+                    oldest.topic, seq, oldest.start, err
+                );
+                self.discarded.fetch_add(1, Ordering::Relaxed);
+            }
+            // Records after this one may be delivered too; they are sent again on the next
+            // replay, so the spool delivers at least once.
+            Some(Err(err)) => {
+                write_offset(&self.cfg.dir, seq, *offset)?;
+                return Err(Box::new(err));
+            }
+            // Undecodable, already reported.
+            None => {
+                self.discarded.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        *offset = oldest.start + oldest.frame_len;
+        self.bytes.fetch_sub(oldest.frame_len, Ordering::Release);
+        *since_sync += 1;
+        if *since_sync == OFFSET_SYNC_EVERY {
+            write_offset(&self.cfg.dir, seq, *offset)?;
+            *since_sync = 0;
+        }
+        Ok(())
+    }
+}
+
+/// A replayed record waiting for its delivery report.
+struct InFlight {
+    start: u64,
+    frame_len: u64,
+    topic: String,
+    /// `None` for a frame that could not be decoded, which only has to be skipped in order.
+    delivery: Option<Result<DeliveryFuture, KafkaError>>,
+}
+
+impl InFlight {
+    fn discarded(start: u64, frame_len: u64) -> Self {
+        Self {
+            start,
+            frame_len,
+            topic: String::new(),
+            delivery: None,
+        }
+    }
+}
+
+fn seal(active: &mut ActiveSegment) {
+    active.file = None;
+    active.len = 0;
+    active.seq += 1;
+}
+
+fn segment_path(dir: &Path, seq: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", seq, SEGMENT_EXTENSION))
+}
+
+fn offset_path(dir: &Path, seq: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", seq, OFFSET_EXTENSION))
+}
+
+fn list_segments(dir: &Path) -> io::Result<Vec<u64>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(SEGMENT_EXTENSION) {
+            continue;
+        }
+        if let Some(seq) = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse::<u64>().ok())
+        {
+            segments.push(seq);
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+fn read_offset(dir: &Path, seq: u64) -> io::Result<u64> {
+    match fs::read_to_string(offset_path(dir, seq)) {
+        Ok(raw) => Ok(raw.trim().parse().unwrap_or(0)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+fn write_offset(dir: &Path, seq: u64, offset: u64) -> io::Result<()> {
+    fs::write(offset_path(dir, seq), offset.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use rdkafka::ClientConfig;
+    use rdkafka::mocking::MockCluster;
+    use rdkafka::producer::DefaultProducerContext;
+
+    use super::*;
+    use crate::metrics::StatsContext;
+
+    fn record(topic: &str, payload: &[u8]) -> SpooledRecord {
+        SpooledRecord {
+            topic: topic.into(),
+            key: Some(b"key".to_vec()),
+            timestamp: Some(1_700_000_000_000),
+            headers: vec![("content-type".into(), b"application/json".to_vec())],
+            payload: payload.to_vec(),
+        }
+    }
+
+    fn spool(dir: &Path) -> Spool {
+        Spool::open(SpoolConfig {
+            dir: dir.to_path_buf(),
+            ..SpoolConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let records = [
+            record("logs.info", b"{\"message\":\"hi\"}"),
+            SpooledRecord {
+                topic: "logs.error".into(),
+                key: None,
+                timestamp: None,
+                headers: Vec::new(),
+                payload: Vec::new(),
+            },
+        ];
+        for original in records {
+            let frame = original.encode();
+            let (body_len, versioned) = SpooledRecord::frame_header(frame[..4].try_into().unwrap());
+            assert_eq!(body_len, frame.len() - 4);
+            assert!(versioned);
+
+            let decoded = SpooledRecord::decode(&frame[4..], versioned).unwrap();
+            assert_eq!(decoded.topic, original.topic);
+            assert_eq!(decoded.key, original.key);
+            assert_eq!(decoded.timestamp, original.timestamp);
+            assert_eq!(decoded.headers, original.headers);
+            assert_eq!(decoded.payload, original.payload);
+        }
+    }
+
+    #[test]
+    fn truncated_frames_are_corrupt() {
+        let frame = record("logs.info", b"payload").encode();
+        // Cut inside the version, the topic, the key and the header.
+        for cut in [4, 6, 21, 31] {
+            assert!(SpooledRecord::decode(&frame[4..cut], true).is_err());
+        }
+    }
+
+    #[test]
+    fn unversioned_frames_decode_in_the_original_layout() {
+        let mut body = Vec::new();
+        body.extend_from_slice(&9u16.to_le_bytes());
+        body.extend_from_slice(b"logs.warn");
+        body.extend_from_slice(&3u32.to_le_bytes());
+        body.extend_from_slice(b"key");
+        body.extend_from_slice(b"{\"message\":\"old\"}");
+        let mut frame = (body.len() as u32).to_le_bytes().to_vec();
+        frame.extend_from_slice(&body);
+
+        let (body_len, versioned) = SpooledRecord::frame_header(frame[..4].try_into().unwrap());
+        assert_eq!(body_len, body.len());
+        assert!(!versioned);
+
+        let decoded = SpooledRecord::decode(&frame[4..], versioned).unwrap();
+        assert_eq!(decoded.topic, "logs.warn");
+        assert_eq!(decoded.key.as_deref(), Some(&b"key"[..]));
+        assert_eq!(decoded.timestamp, None);
+        assert!(decoded.headers.is_empty());
+        assert_eq!(decoded.payload, b"{\"message\":\"old\"}");
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let mut frame = record("logs.info", b"payload").encode();
+        frame[4] = FORMAT_VERSION + 1;
+        assert!(SpooledRecord::decode(&frame[4..], true).is_err());
+    }
+
+    #[tokio::test]
+    async fn replay_skips_corrupt_and_rejected_records() {
+        let cluster = MockCluster::<DefaultProducerContext>::new(1).unwrap();
+        cluster.create_topic("logs.info", 1, 1).unwrap();
+        let producer: InnerProducer = ClientConfig::new()
+            .set("bootstrap.servers", cluster.bootstrap_servers())
+            .set("message.max.bytes", "2000")
+            .create_with_context(StatsContext::default())
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let spool = spool(dir.path());
+        spool.append(&record("logs.info", b"first")).unwrap();
+        {
+            // A frame whose topic length runs past the end of its body.
+            let mut active = spool.active.lock().unwrap();
+            let corrupt = [4, 0, 0, 0, 0xff, 0xff, 0, 0];
+            active.file.as_mut().unwrap().write_all(&corrupt).unwrap();
+            active.len += corrupt.len() as u64;
+            spool
+                .bytes
+                .fetch_add(corrupt.len() as u64, Ordering::Release);
+        }
+        // Larger than message.max.bytes, which no retry will fix.
+        spool.append(&record("logs.info", &[b'x'; 4000])).unwrap();
+        spool.append(&record("logs.info", b"last")).unwrap();
+
+        for seq in spool.sealed_segments() {
+            spool
+                .replay_segment(seq, &producer, Duration::from_secs(5))
+                .await
+                .unwrap();
+        }
+
+        let metrics = spool.metrics();
+        assert_eq!(metrics.replayed_records, 2);
+        assert_eq!(metrics.discarded_records, 2);
+        assert!(!spool.has_pending());
+        assert!(list_segments(dir.path()).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn replay_resumes_after_a_broker_outage() {
+        let cluster = MockCluster::<DefaultProducerContext>::new(1).unwrap();
+        cluster.create_topic("logs.info", 1, 1).unwrap();
+        let producer: InnerProducer = ClientConfig::new()
+            .set("bootstrap.servers", cluster.bootstrap_servers())
+            .set("message.timeout.ms", "1000")
+            .create_with_context(StatsContext::default())
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let spool = spool(dir.path());
+        spool.append(&record("logs.info", b"first")).unwrap();
+        spool.append(&record("logs.info", b"second")).unwrap();
+        let seq = spool.sealed_segments()[0];
+
+        cluster.broker_down(1).unwrap();
+        assert!(
+            spool
+                .replay_segment(seq, &producer, Duration::from_millis(500))
+                .await
+                .is_err()
+        );
+        assert!(spool.has_pending());
+        assert_eq!(spool.metrics().discarded_records, 0);
+
+        cluster.broker_up(1).unwrap();
+        spool
+            .replay_segment(seq, &producer, Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(spool.metrics().replayed_records, 2);
+        assert!(!spool.has_pending());
+    }
+
+    #[tokio::test]
+    async fn replay_keeps_many_records_in_flight() {
+        let cluster = MockCluster::<DefaultProducerContext>::new(1).unwrap();
+        cluster.create_topic("logs.info", 1, 1).unwrap();
+        let producer: InnerProducer = ClientConfig::new()
+            .set("bootstrap.servers", cluster.bootstrap_servers())
+            .set("linger.ms", "100")
+            .set("enable.idempotence", "true")
+            .create_with_context(StatsContext::default())
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let spool = spool(dir.path());
+        for i in 0..2000 {
+            spool
+                .append(&record("logs.info", format!("log {}", i).as_bytes()))
+                .unwrap();
+        }
+        let seq = spool.sealed_segments()[0];
+
+        // One record per linger would take over three minutes.
+        tokio::time::timeout(
+            Duration::from_secs(30),
+            spool.replay_segment(seq, &producer, Duration::from_secs(5)),
+        )
+        .await
+        .expect("replay waited on each record in turn")
+        .unwrap();
+        assert_eq!(spool.metrics().replayed_records, 2000);
+        assert!(!spool.has_pending());
+    }
+
+    #[tokio::test]
+    async fn replay_stops_at_a_length_prefix_past_the_end_of_the_segment() {
+        let cluster = MockCluster::<DefaultProducerContext>::new(1).unwrap();
+        cluster.create_topic("logs.info", 1, 1).unwrap();
+        let producer: InnerProducer = ClientConfig::new()
+            .set("bootstrap.servers", cluster.bootstrap_servers())
+            .create_with_context(StatsContext::default())
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let spool = spool(dir.path());
+        spool.append(&record("logs.info", b"first")).unwrap();
+        {
+            // Claims a body of almost 2 GiB, which must not be allocated.
+            let mut active = spool.active.lock().unwrap();
+            let corrupt = [0xff, 0xff, 0xff, 0x7f, 0, 0];
+            active.file.as_mut().unwrap().write_all(&corrupt).unwrap();
+            active.len += corrupt.len() as u64;
+            spool
+                .bytes
+                .fetch_add(corrupt.len() as u64, Ordering::Release);
+        }
+
+        for seq in spool.sealed_segments() {
+            spool
+                .replay_segment(seq, &producer, Duration::from_secs(5))
+                .await
+                .unwrap();
+        }
+        assert_eq!(spool.metrics().replayed_records, 1);
+        assert!(!spool.has_pending());
+        assert!(list_segments(dir.path()).unwrap().is_empty());
+    }
+}
@@ -12,7 +12,7 @@ impl AppState {
         let kafka_producer = KafkaProducer::new(cfg)?;
 
         Ok(Self {
-            producer: Arc::new(kafka_producer)
+            producer: Arc::new(kafka_producer),
         })
     }
 }
@@ -0,0 +1,602 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeDelta, TimeZone, Utc};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+use crate::helper::get_hostname;
+use crate::lines::level_type;
+use crate::models::{ErrorLog, InfoLog, LogContext, LogRecord, LogType, WarnLog};
+use crate::producer::{DeliveryReport, KafkaProducer};
+
+const SEVERITIES: [&str; 8] = [
+    "EMERG", "ALERT", "CRIT", "ERR", "WARNING", "NOTICE", "INFO", "DEBUG",
+];
+const FACILITIES: [&str; 24] = [
+    "kern",
+    "user",
+    "mail",
+    "daemon",
+    "auth",
+    "syslog",
+    "lpr",
+    "news",
+    "uucp",
+    "cron",
+    "authpriv",
+    "ftp",
+    "ntp",
+    "security",
+    "console",
+    "solaris-cron",
+    "local0",
+    "local1",
+    "local2",
+    "local3",
+    "local4",
+    "local5",
+    "local6",
+    "local7",
+];
+/// RFC 3164 says a message without a PRI part is user-level and of notice severity.
+const DEFAULT_PRI: u8 = 13;
+/// The value of an RFC 5424 header field that is not present.
+const NIL: &str = "-";
+/// Longest octet count accepted in front of a TCP frame, including the space after it.
+const MAX_FRAME_LENGTH_DIGITS: u64 = 11;
+/// How often messages that failed delivery are reported.
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SyslogConfig {
+    /// Where to receive syslog datagrams, e.g. `0.0.0.0:514`. Not bound when unset.
+    pub udp_addr: Option<String>,
+    /// Where to accept syslog over TCP, framed by octet counts or newlines (RFC 6587). Not
+    /// bound when unset.
+    pub tcp_addr: Option<String>,
+    /// Messages longer than this many bytes are cut.
+    pub max_message_size: usize,
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        Self {
+            udp_addr: None,
+            tcp_addr: None,
+            max_message_size: 64 * 1024,
+        }
+    }
+}
+
+/// Parses an RFC 5424 or RFC 3164 message into a log model. The severity picks the log type;
+/// facility, app name, process id, message id and structured data go into the context fields.
+/// `peer` stands in for the hostname when the message does not carry one.
+pub fn parse_syslog(raw: &str, peer: Option<SocketAddr>) -> LogRecord {
+    let raw = raw.trim_end_matches(['\r', '\n', '\0']);
+    let (pri, rest) = parse_pri(raw);
+    let mut fields = Map::new();
+    fields.insert(
+        "facility".into(),
+        Value::String(FACILITIES[(pri >> 3) as usize].into()),
+    );
+    fields.insert("severity".into(), Value::from(pri & 7));
+
+    let header = match rest.strip_prefix("1 ") {
+        Some(rest) => parse_rfc5424(rest, &mut fields),
+        None => parse_rfc3164(rest, &mut fields),
+    };
+    if let Some(peer) = peer {
+        fields.insert("peer".into(), Value::String(peer.to_string()));
+    }
+
+    let level = SEVERITIES[(pri & 7) as usize].to_string();
+    let hostname = header
+        .hostname
+        .or_else(|| peer.map(|peer| peer.ip().to_string()))
+        .unwrap_or_else(get_hostname);
+    let timestamp = header.timestamp.unwrap_or_else(Utc::now);
+    let context = LogContext {
+        fields,
+        ..LogContext::default()
+    };
+
+    match level_type(&level) {
+        LogType::Error => ErrorLog::new(level, header.message, hostname, timestamp, 0)
+            .with_context(context)
+            .into(),
+        LogType::Warn => WarnLog::new(level, header.message, hostname, timestamp, String::new())
+            .with_context(context)
+            .into(),
+        LogType::Info => InfoLog::new(level, header.message, hostname, timestamp)
+            .with_context(context)
+            .into(),
+    }
+}
+
+struct Header {
+    timestamp: Option<DateTime<Utc>>,
+    hostname: Option<String>,
+    message: String,
+}
+
+/// Splits off `<PRI>`. Values out of range count as missing.
+fn parse_pri(raw: &str) -> (u8, &str) {
+    raw.strip_prefix('<')
+        .and_then(|rest| rest.split_once('>'))
+        .and_then(|(pri, rest)| Some((pri.parse::<u8>().ok().filter(|pri| *pri < 192)?, rest)))
+        .unwrap_or((DEFAULT_PRI, raw))
+}
+
+/// `TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA [MSG]`, after the version.
+fn parse_rfc5424(rest: &str, fields: &mut Map<String, Value>) -> Header {
+    let mut parts = rest.splitn(6, ' ');
+    let timestamp = parts.next().and_then(|ts| ts.parse().ok());
+    let hostname = parts.next().filter(|host| *host != NIL).map(String::from);
+    for name in ["app_name", "proc_id", "msg_id"] {
+        if let Some(value) = parts.next().filter(|value| *value != NIL) {
+            fields.insert(name.into(), Value::String(value.into()));
+        }
+    }
+
+    let rest = parts.next().unwrap_or_default();
+    let (data, message) = match rest.strip_prefix(NIL) {
+        Some(message) => (Map::new(), message),
+        None => parse_structured_data(rest),
+    };
+    if !data.is_empty() {
+        fields.insert("structured_data".into(), Value::Object(data));
+    }
+    let message = message.strip_prefix(' ').unwrap_or(message);
+    Header {
+        timestamp,
+        hostname,
+        message: message.trim_start_matches('\u{feff}').to_string(),
+    }
+}
+
+/// `[id name="value" ...][id ...]`, returned as `{id: {name: value}}` with the rest of the input.
+fn parse_structured_data(mut rest: &str) -> (Map<String, Value>, &str) {
+    let mut data = Map::new();
+    while let Some(element) = rest.strip_prefix('[') {
+        let id_end = element.find([' ', ']']).unwrap_or(element.len());
+        let mut params = Map::new();
+        let mut cursor = &element[id_end..];
+        loop {
+            cursor = cursor.trim_start_matches(' ');
+            if let Some(after) = cursor.strip_prefix(']') {
+                cursor = after;
+                break;
+            }
+            let Some((name, value)) = cursor.split_once("=\"") else {
+                // Malformed: keep what was read and treat the rest as the message.
+                cursor = "";
+                break;
+            };
+            let (value, after) = unescape_param(value);
+            params.insert(name.into(), Value::String(value));
+            cursor = after;
+        }
+        data.insert(element[..id_end].into(), Value::Object(params));
+        rest = cursor;
+    }
+    (data, rest)
+}
+
+/// Reads a parameter value up to its closing quote, undoing the `\"`, `\\` and `\]` escapes.
+fn unescape_param(raw: &str) -> (String, &str) {
+    let mut value = String::new();
+    let mut chars = raw.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return (value, &raw[i + 1..]),
+            '\\' => match chars.next() {
+                Some((_, escaped @ ('"' | '\\' | ']'))) => value.push(escaped),
+                Some((_, other)) => {
+                    value.push('\\');
+                    value.push(other);
+                }
+                None => value.push('\\'),
+            },
+            other => value.push(other),
+        }
+    }
+    (value, "")
+}
+
+/// `Mmm dd hh:mm:ss HOSTNAME TAG: MSG`. Devices that leave out the timestamp and hostname send
+/// only the message; some send an RFC 3339 timestamp instead.
+fn parse_rfc3164(rest: &str, fields: &mut Map<String, Value>) -> Header {
+    let (timestamp, after) = match parse_bsd_timestamp(rest) {
+        Some((timestamp, after)) => (Some(timestamp), after),
+        None => match rest.split_once(' ') {
+            Some((ts, after)) if ts.parse::<DateTime<Utc>>().is_ok() => (ts.parse().ok(), after),
+            _ => (None, rest),
+        },
+    };
+    let (hostname, message) = match (timestamp, after.split_once(' ')) {
+        (Some(_), Some((hostname, message))) => (Some(hostname.to_string()), message),
+        _ => (None, after),
+    };
+
+    // The tag is the program name, optionally with `[pid]`, ending in a colon.
+    let message = match message.split_once(": ") {
+        Some((tag, text)) if is_tag(tag) => {
+            let (app, pid) = match tag.split_once('[') {
+                Some((app, pid)) => (app, pid.strip_suffix(']')),
+                None => (tag, None),
+            };
+            fields.insert("app_name".into(), Value::String(app.into()));
+            if let Some(pid) = pid {
+                fields.insert("proc_id".into(), Value::String(pid.into()));
+            }
+            text
+        }
+        _ => message,
+    };
+    Header {
+        timestamp,
+        hostname,
+        message: message.to_string(),
+    }
+}
+
+fn is_tag(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag.len() <= 48
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./[]".contains(c))
+}
+
+/// The RFC 3164 timestamp has no year or zone: it is read as local time in the current year, or
+/// in the previous one when that would put it in the future (a message from December 31st read
+/// on January 1st).
+fn parse_bsd_timestamp(rest: &str) -> Option<(DateTime<Utc>, &str)> {
+    let raw = rest.get(..15)?;
+    let after = rest[15..].strip_prefix(' ').unwrap_or(&rest[15..]);
+    let now = Local::now();
+    let at_year = |year: i32| {
+        let naive =
+            NaiveDateTime::parse_from_str(&format!("{} {}", year, raw), "%Y %b %e %H:%M:%S")
+                .ok()?;
+        Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|local| local.with_timezone(&Utc))
+    };
+    let timestamp = at_year(now.year())?;
+    if timestamp > now.with_timezone(&Utc) + TimeDelta::days(1) {
+        return Some((at_year(now.year() - 1)?, after));
+    }
+    Some((timestamp, after))
+}
+
+/// Listens on the addresses in a [`SyslogConfig`] and enqueues every message received.
+pub struct SyslogReceiver {
+    cfg: SyslogConfig,
+    producer: Arc<KafkaProducer>,
+}
+
+impl SyslogReceiver {
+    pub fn new(producer: Arc<KafkaProducer>, cfg: SyslogConfig) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            cfg.udp_addr.is_some() || cfg.tcp_addr.is_some(),
+            "syslog needs a UDP or TCP address"
+        );
+        anyhow::ensure!(
+            cfg.max_message_size > 0,
+            "syslog max_message_size must be positive"
+        );
+        Ok(Self { cfg, producer })
+    }
+
+    /// Receives until the future is dropped or a socket fails. Messages that could not be
+    /// delivered are reported every [`REPORT_INTERVAL`].
+    pub async fn run(self) -> anyhow::Result<()> {
+        let udp = match &self.cfg.udp_addr {
+            Some(addr) => Some(
+                UdpSocket::bind(addr)
+                    .await
+                    .with_context(|| format!("binding syslog UDP {}", addr))?,
+            ),
+            None => None,
+        };
+        let tcp = match &self.cfg.tcp_addr {
+            Some(addr) => Some(
+                TcpListener::bind(addr)
+                    .await
+                    .with_context(|| format!("binding syslog TCP {}", addr))?,
+            ),
+            None => None,
+        };
+
+        let udp = async {
+            match udp {
+                Some(socket) => self.serve_udp(socket).await,
+                None => Ok(()),
+            }
+        };
+        let tcp = async {
+            match tcp {
+                Some(listener) => self.serve_tcp(listener).await,
+                None => Ok(()),
+            }
+        };
+        let report = async {
+            let mut interval = tokio::time::interval(REPORT_INTERVAL);
+            loop {
+                interval.tick().await;
+                report_failures(&self.producer.take_report());
+            }
+        };
+        tokio::select! {
+            result = async { tokio::try_join!(udp, tcp) } => {
+                result?;
+            }
+            _ = report => {}
+        }
+        Ok(())
+    }
+
+    async fn serve_udp(&self, socket: UdpSocket) -> anyhow::Result<()> {
+        let mut buf = vec![0; self.cfg.max_message_size];
+        loop {
+            let (len, peer) = socket.recv_from(&mut buf).await?;
+            let raw = String::from_utf8_lossy(&buf[..len]);
+            self.producer.enqueue(parse_syslog(&raw, Some(peer))).await;
+        }
+    }
+
+    async fn serve_tcp(&self, listener: TcpListener) -> anyhow::Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let producer = self.producer.clone();
+            let max_message_size = self.cfg.max_message_size;
+            tokio::spawn(async move {
+                if let Err(e) = read_tcp(stream, peer, producer, max_message_size).await {
+                    eprintln!("Syslog connection from {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+/// Reads frames until the peer disconnects.
+async fn read_tcp(
+    stream: TcpStream,
+    peer: SocketAddr,
+    producer: Arc<KafkaProducer>,
+    max_message_size: usize,
+) -> anyhow::Result<()> {
+    let mut reader = BufReader::new(stream);
+    while let Some(frame) = read_frame(&mut reader, max_message_size).await? {
+        let raw = String::from_utf8_lossy(&frame);
+        if !raw.trim().is_empty() {
+            producer.enqueue(parse_syslog(&raw, Some(peer))).await;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the next frame, or `None` at the end of the stream. A frame starting with a digit is
+/// octet-counted (`<length> <message>`); any other is terminated by a newline. Frames are cut
+/// to `max_message_size` bytes and the rest is skipped without being buffered.
+async fn read_frame<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    max_message_size: usize,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let Some(&first) = reader.fill_buf().await?.first() else {
+        return Ok(None);
+    };
+    let mut frame = Vec::new();
+    if first.is_ascii_digit() {
+        let mut length = Vec::new();
+        (&mut *reader)
+            .take(MAX_FRAME_LENGTH_DIGITS)
+            .read_until(b' ', &mut length)
+            .await?;
+        let length: u64 = std::str::from_utf8(&length)?
+            .trim_end()
+            .parse()
+            .context("invalid syslog frame length")?;
+        let kept = length.min(max_message_size as u64);
+        (&mut *reader).take(kept).read_to_end(&mut frame).await?;
+        tokio::io::copy(
+            &mut (&mut *reader).take(length - kept),
+            &mut tokio::io::sink(),
+        )
+        .await?;
+    } else {
+        (&mut *reader)
+            .take(max_message_size as u64)
+            .read_until(b'\n', &mut frame)
+            .await?;
+        if frame.last() != Some(&b'\n') && frame.len() == max_message_size {
+            // Drop the rest of an oversized line.
+            let mut rest = Vec::new();
+            loop {
+                rest.clear();
+                let read = (&mut *reader)
+                    .take(max_message_size as u64)
+                    .read_until(b'\n', &mut rest)
+                    .await?;
+                if read == 0 || rest.last() == Some(&b'\n') {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(Some(frame))
+}
+
+/// Prints the enqueued messages that failed since the last report.
+fn report_failures(report: &DeliveryReport) {
+    if report.failed == 0 {
+        return;
+    }
+    eprintln!("{} syslog messages could not be delivered", report.failed);
+    for (topic, deliveries) in &report.topics {
+        if let Some(error) = &deliveries.last_error {
+            eprintln!(
+                "  {}: {} failed, last error: {}",
+                topic, deliveries.failed, error
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::models::Loggable;
+
+    fn doc(record: &LogRecord) -> Value {
+        serde_json::to_value(record).unwrap()
+    }
+
+    #[test]
+    fn parses_rfc5424() {
+        let record = parse_syslog(
+            "<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 \
+             [exampleSDID@32473 iut=\"3\" eventSource=\"Application\"] An application event\n",
+            None,
+        );
+        assert_eq!(record.log_type(), LogType::Info);
+        let doc = doc(&record);
+        assert_eq!(doc["level"], "NOTICE");
+        assert_eq!(doc["hostname"], "mymachine.example.com");
+        assert_eq!(doc["message"], "An application event");
+        assert_eq!(doc["timestamp"], "2003-10-11T22:14:15.003Z");
+        let fields = &doc["fields"];
+        assert_eq!(fields["facility"], "local4");
+        assert_eq!(fields["severity"], 5);
+        assert_eq!(fields["app_name"], "evntslog");
+        assert_eq!(fields["msg_id"], "ID47");
+        assert!(fields.get("proc_id").is_none());
+        assert_eq!(
+            fields["structured_data"],
+            json!({ "exampleSDID@32473": { "iut": "3", "eventSource": "Application" } })
+        );
+    }
+
+    #[test]
+    fn severity_picks_the_log_type() {
+        let error = parse_syslog("<11>1 - host app 42 - - disk failed", None);
+        assert_eq!(error.log_type(), LogType::Error);
+        assert_eq!(doc(&error)["fields"]["proc_id"], "42");
+        let warn = parse_syslog("<12>1 - host app - - - disk almost full", None);
+        assert_eq!(warn.log_type(), LogType::Warn);
+        assert_eq!(doc(&warn)["level"], "WARNING");
+    }
+
+    #[test]
+    fn missing_or_invalid_pri_is_user_notice() {
+        for raw in ["plain message", "<200>plain message"] {
+            let doc = doc(&parse_syslog(raw, None));
+            assert_eq!(doc["level"], "NOTICE");
+            assert_eq!(doc["fields"]["facility"], "user");
+        }
+        assert_eq!(
+            doc(&parse_syslog("<200>plain", None))["message"],
+            "<200>plain"
+        );
+    }
+
+    #[test]
+    fn peer_stands_in_for_a_missing_hostname() {
+        let peer: SocketAddr = "10.0.0.7:5140".parse().unwrap();
+        let doc = doc(&parse_syslog("<14>1 - - app - - - hello", Some(peer)));
+        assert_eq!(doc["hostname"], "10.0.0.7");
+        assert_eq!(doc["fields"]["peer"], "10.0.0.7:5140");
+    }
+
+    #[test]
+    fn structured_data_unescapes_values() {
+        let (data, rest) =
+            parse_structured_data(r#"[a x="q\"uote\]" y="back\\slash" z="\n"][b] message"#);
+        assert_eq!(
+            Value::Object(data),
+            json!({ "a": { "x": "q\"uote]", "y": "back\\slash", "z": "\\n" }, "b": {} })
+        );
+        assert_eq!(rest, " message");
+    }
+
+    #[test]
+    fn malformed_structured_data_keeps_what_was_read() {
+        let (data, rest) = parse_structured_data(r#"[a x="1" y=2] message"#);
+        assert_eq!(Value::Object(data), json!({ "a": { "x": "1" } }));
+        assert_eq!(rest, "");
+    }
+
+    #[test]
+    fn parses_rfc3164() {
+        let mut fields = Map::new();
+        let header = parse_rfc3164(
+            "Oct 11 22:14:15 mymachine su[123]: 'su root' failed",
+            &mut fields,
+        );
+        assert!(header.timestamp.is_some());
+        assert_eq!(header.hostname.as_deref(), Some("mymachine"));
+        assert_eq!(header.message, "'su root' failed");
+        assert_eq!(fields["app_name"], "su");
+        assert_eq!(fields["proc_id"], "123");
+    }
+
+    #[test]
+    fn rfc3164_with_an_rfc3339_timestamp() {
+        let mut fields = Map::new();
+        let header = parse_rfc3164("2024-01-02T03:04:05Z router kernel: link up", &mut fields);
+        assert_eq!(
+            header.timestamp,
+            Some("2024-01-02T03:04:05Z".parse().unwrap())
+        );
+        assert_eq!(header.hostname.as_deref(), Some("router"));
+        assert_eq!(header.message, "link up");
+        assert_eq!(fields["app_name"], "kernel");
+    }
+
+    #[test]
+    fn rfc3164_message_only() {
+        let mut fields = Map::new();
+        let header = parse_rfc3164("link down: eth0 lost carrier", &mut fields);
+        assert!(header.timestamp.is_none());
+        assert!(header.hostname.is_none());
+        // "link down" has a space, so it is not a tag.
+        assert_eq!(header.message, "link down: eth0 lost carrier");
+        assert!(fields.is_empty());
+    }
+
+    async fn frames(mut input: &[u8], max_message_size: usize) -> Vec<String> {
+        let mut frames = Vec::new();
+        while let Some(frame) = read_frame(&mut input, max_message_size).await.unwrap() {
+            frames.push(String::from_utf8(frame).unwrap());
+        }
+        frames
+    }
+
+    #[tokio::test]
+    async fn octet_counted_frames_are_cut_and_skipped() {
+        assert_eq!(
+            frames(b"5 hello20 aaaaaaaaaaaaaaaaaaaa3 bye", 8).await,
+            ["hello", "aaaaaaaa", "bye"]
+        );
+        // A huge count is not buffered; the stream just ends.
+        assert_eq!(frames(b"9999999999 short", 8).await, ["short"]);
+    }
+
+    #[tokio::test]
+    async fn newline_frames_are_cut_and_skipped() {
+        assert_eq!(
+            frames(b"first\nthis line is too long\nlast\n", 8).await,
+            ["first\n", "this lin", "last\n"]
+        );
+    }
+}
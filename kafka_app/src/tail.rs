@@ -0,0 +1,394 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::time::sleep;
+
+use crate::lines::{LineFormat, parse_line};
+use crate::models::{LogContext, LogRecord};
+use crate::multiline::{MultilineAggregator, MultilineConfig};
+use crate::producer::KafkaProducer;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TailConfig {
+    /// Glob patterns of the files to follow, e.g. `/var/log/app/*.log`.
+    pub paths: Vec<String>,
+    pub format: LineFormat,
+    /// Where read offsets are kept between runs.
+    pub state_file: PathBuf,
+    pub poll_interval_ms: u64,
+    /// Whether files that already exist at the first start, with no saved offset, are read from
+    /// the beginning. Files that appear later are always read in full.
+    pub from_beginning: bool,
+    /// Most lines read per poll. Offsets are saved after each batch is delivered.
+    pub max_batch: usize,
+    /// Joins the lines of a stack trace into one log. Each line is a log of its own when unset.
+    pub multiline: Option<MultilineConfig>,
+}
+
+impl Default for TailConfig {
+    fn default() -> Self {
+        Self {
+            paths: Vec::new(),
+            format: LineFormat::default(),
+            state_file: PathBuf::from("tail_state.json"),
+            poll_interval_ms: 500,
+            from_beginning: true,
+            max_batch: 1000,
+            multiline: None,
+        }
+    }
+}
+
+/// Offsets saved in the state file, keyed by file identity rather than path so a file renamed
+/// by rotation is resumed under its new name.
+#[derive(Serialize, Deserialize, Default)]
+struct TailState {
+    files: BTreeMap<String, SavedOffset>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct SavedOffset {
+    path: PathBuf,
+    offset: u64,
+}
+
+struct TailedFile {
+    path: PathBuf,
+    reader: BufReader<File>,
+    /// End of the last complete line read.
+    offset: u64,
+    /// Bytes of a line whose newline has not been written yet.
+    partial: Vec<u8>,
+    multiline: Option<MultilineAggregator>,
+    /// Where the event still held by `multiline` begins.
+    pending_start: u64,
+}
+
+impl TailedFile {
+    fn open(
+        path: PathBuf,
+        offset: u64,
+        multiline: Option<MultilineAggregator>,
+    ) -> io::Result<Self> {
+        let mut file = File::open(&path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Self {
+            path,
+            reader: BufReader::new(file),
+            offset,
+            partial: Vec::new(),
+            multiline,
+            pending_start: offset,
+        })
+    }
+
+    /// Starts over when the file was truncated in place (`copytruncate` rotation).
+    fn check_truncated(&mut self, events: &mut Vec<String>) -> io::Result<()> {
+        if self.reader.get_ref().metadata()?.len() < self.offset {
+            events.extend(self.finish());
+            self.reader.seek(SeekFrom::Start(0))?;
+            self.offset = 0;
+            self.pending_start = 0;
+            self.partial.clear();
+        }
+        Ok(())
+    }
+
+    /// Reads complete lines until EOF or `budget` lines, adding the events they complete.
+    /// Returns how many lines were read and whether EOF was reached.
+    fn read_lines(&mut self, budget: usize, events: &mut Vec<String>) -> io::Result<(usize, bool)> {
+        for read in 0..budget {
+            if self.reader.read_until(b'\n', &mut self.partial)? == 0 {
+                return Ok((read, true));
+            }
+            if self.partial.last() != Some(&b'\n') {
+                // The writer is mid-line; the rest is picked up on a later poll.
+                return Ok((read, true));
+            }
+            let start = self.offset;
+            self.offset += self.partial.len() as u64;
+            let line = String::from_utf8_lossy(&self.partial);
+            if !line.trim().is_empty() {
+                match &mut self.multiline {
+                    Some(multiline) => {
+                        let was_pending = multiline.is_pending();
+                        let completed = multiline.push(&line);
+                        if !was_pending || completed.is_some() {
+                            self.pending_start = start;
+                        }
+                        events.extend(completed);
+                    }
+                    None => events.push(line.into_owned()),
+                }
+            }
+            self.partial.clear();
+        }
+        Ok((budget, false))
+    }
+
+    /// The pending event, once no line has been added to it for the multiline timeout.
+    fn flush_expired(&mut self) -> Option<String> {
+        self.multiline.as_mut()?.flush_expired(Instant::now())
+    }
+
+    fn finish(&mut self) -> Option<String> {
+        self.multiline.as_mut()?.finish()
+    }
+
+    /// Goes back to `offset` and forgets what was read since, so it is read again.
+    fn rewind(&mut self, offset: u64, multiline: Option<MultilineAggregator>) -> io::Result<()> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.offset = offset;
+        self.pending_start = offset;
+        self.partial.clear();
+        self.multiline = multiline;
+        Ok(())
+    }
+
+    /// Where reading resumes after a restart: the start of an event not produced yet, so its
+    /// lines are read again rather than lost.
+    fn resume_offset(&self) -> u64 {
+        match &self.multiline {
+            Some(multiline) if multiline.is_pending() => self.pending_start,
+            _ => self.offset,
+        }
+    }
+}
+
+/// Follows the files matching [`TailConfig::paths`] and produces every new line.
+///
+/// Files are tracked by device and inode, so a file renamed by rotation is read to its end while
+/// the new file under the old name is picked up from the start. Offsets are written to the state
+/// file once a batch has been delivered (or spooled), so a restart resumes where it left off and
+/// may at worst repeat the last batch. When a line of a batch fails for a reason a retry may fix
+/// (see [`crate::DeliveryReport::retryable`]), the offsets are kept and the whole batch is read
+/// again on the next poll. Lines that can never be produced, e.g. oversize ones with the reject
+/// policy, are reported and skipped.
+pub struct Tailer {
+    cfg: TailConfig,
+    producer: Arc<KafkaProducer>,
+    files: HashMap<String, TailedFile>,
+    saved: BTreeMap<String, SavedOffset>,
+    started: bool,
+}
+
+impl Tailer {
+    pub fn new(producer: Arc<KafkaProducer>, cfg: TailConfig) -> anyhow::Result<Self> {
+        anyhow::ensure!(!cfg.paths.is_empty(), "tail needs at least one path");
+        for pattern in &cfg.paths {
+            glob::Pattern::new(pattern)
+                .with_context(|| format!("invalid tail path pattern {}", pattern))?;
+        }
+        if let Some(multiline) = &cfg.multiline {
+            MultilineAggregator::new(multiline)?;
+        }
+        let saved = match fs::read(&cfg.state_file) {
+            Ok(raw) => {
+                serde_json::from_slice::<TailState>(&raw)
+                    .with_context(|| format!("parsing {}", cfg.state_file.display()))?
+                    .files
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).context("reading the tail state file"),
+        };
+        Ok(Self {
+            cfg,
+            producer,
+            files: HashMap::new(),
+            saved,
+            started: false,
+        })
+    }
+
+    /// Polls until the future is dropped or an error occurs.
+    pub async fn run(mut self) -> anyhow::Result<()> {
+        loop {
+            if self.poll().await? < self.cfg.max_batch {
+                sleep(Duration::from_millis(self.cfg.poll_interval_ms)).await;
+            }
+        }
+    }
+
+    /// Reads and produces one batch of new lines. Returns how many lines were read, or 0 when
+    /// the batch is to be read again.
+    pub async fn poll(&mut self) -> anyhow::Result<usize> {
+        let matched = self.discover()?;
+        let batch_start: HashMap<String, u64> = self
+            .files
+            .iter()
+            .map(|(id, file)| (id.clone(), file.resume_offset()))
+            .collect();
+
+        let mut records: Vec<LogRecord> = Vec::new();
+        let mut read = 0;
+        let mut finished = Vec::new();
+        for (id, file) in &mut self.files {
+            let budget = self.cfg.max_batch.saturating_sub(read);
+            if budget == 0 {
+                break;
+            }
+            let mut events = Vec::new();
+            file.check_truncated(&mut events)?;
+            let (lines, at_eof) = file.read_lines(budget, &mut events)?;
+            read += lines;
+            // Rotated away or deleted: once drained there is nothing more to read.
+            if at_eof && !matched.contains(id) {
+                events.extend(file.finish());
+                finished.push(id.clone());
+            } else {
+                events.extend(file.flush_expired());
+            }
+            records.extend(
+                events
+                    .iter()
+                    .map(|event| parse_line(event, self.cfg.format, file_context(&file.path))),
+            );
+        }
+
+        if !records.is_empty() {
+            let report = self
+                .producer
+                .send_many(records, &self.producer.send_timeout())
+                .await;
+            if report.retryable > 0 {
+                eprintln!(
+                    "{} tailed lines could not be delivered, reading the batch again",
+                    report.retryable
+                );
+                self.rewind(&batch_start)?;
+                return Ok(0);
+            }
+            // Sending these again would fail the same way and hold the files back for good.
+            if report.failed > 0 {
+                eprintln!(
+                    "Skipping {} tailed lines that cannot be produced",
+                    report.failed
+                );
+                for (topic, deliveries) in &report.topics {
+                    if let Some(error) = &deliveries.last_error {
+                        eprintln!(
+                            "  {}: {} failed, last error: {}",
+                            topic, deliveries.failed, error
+                        );
+                    }
+                }
+            }
+        }
+        for id in finished {
+            self.files.remove(&id);
+        }
+        self.save_state()?;
+        Ok(read)
+    }
+
+    /// Takes every file back to where the batch started.
+    fn rewind(&mut self, batch_start: &HashMap<String, u64>) -> anyhow::Result<()> {
+        for (id, file) in &mut self.files {
+            let Some(&offset) = batch_start.get(id) else {
+                continue;
+            };
+            let multiline = self
+                .cfg
+                .multiline
+                .as_ref()
+                .map(MultilineAggregator::new)
+                .transpose()?;
+            file.rewind(offset, multiline)
+                .with_context(|| format!("rewinding {}", file.path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Opens files that newly match the patterns and returns the identities of all matches.
+    fn discover(&mut self) -> anyhow::Result<HashSet<String>> {
+        let mut matched = HashSet::new();
+        for pattern in &self.cfg.paths {
+            for path in glob::glob(pattern)?.filter_map(Result::ok) {
+                let Ok(metadata) = fs::metadata(&path) else {
+                    continue;
+                };
+                if !metadata.is_file() {
+                    continue;
+                }
+                let id = file_id(&path, &metadata);
+                matched.insert(id.clone());
+                if let Some(file) = self.files.get_mut(&id) {
+                    file.path = path;
+                    continue;
+                }
+
+                let offset = match self.saved.get(&id) {
+                    Some(saved) if saved.offset <= metadata.len() => saved.offset,
+                    _ if self.started || self.cfg.from_beginning => 0,
+                    _ => metadata.len(),
+                };
+                let multiline = self
+                    .cfg
+                    .multiline
+                    .as_ref()
+                    .map(MultilineAggregator::new)
+                    .transpose()?;
+                match TailedFile::open(path.clone(), offset, multiline) {
+                    Ok(file) => {
+                        self.files.insert(id, file);
+                    }
+                    Err(e) => eprintln!("Cannot tail {}: {}", path.display(), e),
+                }
+            }
+        }
+        self.started = true;
+        Ok(matched)
+    }
+
+    fn save_state(&mut self) -> anyhow::Result<()> {
+        self.saved = self
+            .files
+            .iter()
+            .map(|(id, file)| {
+                let saved = SavedOffset {
+                    path: file.path.clone(),
+                    offset: file.resume_offset(),
+                };
+                (id.clone(), saved)
+            })
+            .collect();
+        let state = TailState {
+            files: self.saved.clone(),
+        };
+
+        // Write then rename, so a crash never leaves a half-written state file.
+        let tmp = self.cfg.state_file.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&state)?)
+            .with_context(|| format!("writing {}", tmp.display()))?;
+        fs::rename(&tmp, &self.cfg.state_file)
+            .with_context(|| format!("replacing {}", self.cfg.state_file.display()))?;
+        Ok(())
+    }
+}
+
+fn file_context(path: &Path) -> LogContext {
+    let mut context = LogContext::default();
+    context
+        .fields
+        .insert("file".into(), Value::String(path.display().to_string()));
+    context
+}
+
+#[cfg(unix)]
+fn file_id(_path: &Path, metadata: &Metadata) -> String {
+    use std::os::unix::fs::MetadataExt;
+    format!("{}:{}", metadata.dev(), metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn file_id(path: &Path, _metadata: &Metadata) -> String {
+    path.display().to_string()
+}
@@ -0,0 +1,524 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{Map, Value};
+
+use crate::constant::REPEAT_COUNT_HEADER;
+use crate::helper::get_hostname;
+use crate::models::{LogType, Loggable};
+
+/// Context field that carries how many identical logs a collapsed log stands for.
+pub const REPEAT_COUNT_FIELD: &str = "repeat_count";
+/// Rate limit buckets kept before the full ones are swept out.
+const BUCKET_SWEEP_MIN: usize = 1024;
+
+/// The `[throttle]` section of the config file.
+///
+/// Each log goes through sampling, then duplicate suppression, then rate limiting; a duplicate
+/// collapsed into an earlier log does not use up the rate limit.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ThrottleConfig {
+    /// Share of logs kept per level, from 0 to 1, e.g. `{ DEBUG = 0.1, INFO = 0.5 }`. Levels
+    /// are matched case-insensitively; levels not listed are all kept. Sampling is
+    /// deterministic: a rate of 0.25 keeps every fourth log of the level.
+    pub sample: HashMap<String, f64>,
+    pub rate_limit: Option<RateLimitConfig>,
+    /// The first log of a kind is produced right away; later logs with the same type, level,
+    /// hostname and message within this many milliseconds of it are held back. When the window
+    /// ends, one copy stands in for them with a `repeat_count` context field and header. Not
+    /// collapsed when unset.
+    pub dedup_window_ms: Option<u64>,
+}
+
+/// A token bucket per log type and hostname.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Logs let through per second once the burst is used up.
+    pub per_second: f64,
+    /// Logs let through at once after a quiet period.
+    pub burst: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            per_second: 100.0,
+            burst: 200,
+        }
+    }
+}
+
+/// Counters of a [`Throttle`] since it was created.
+#[derive(Debug, Default, Clone)]
+pub struct ThrottleMetrics {
+    pub sampled_out: u64,
+    pub rate_limited: u64,
+    /// Duplicates collapsed into an earlier log.
+    pub collapsed: u64,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    /// The tokens the bucket holds at `now`.
+    fn refilled(&self, now: Instant, rate_limit: &RateLimitConfig) -> f64 {
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        (self.tokens + elapsed * rate_limit.per_second).min(f64::from(rate_limit.burst))
+    }
+}
+
+/// The token buckets by log type and hostname. A bucket that has refilled to the burst is the
+/// same as a new one, so those are dropped whenever the map doubles in size; hostnames seen once
+/// do not stay in memory.
+struct Buckets {
+    map: HashMap<(LogType, String), Bucket>,
+    sweep_at: usize,
+}
+
+impl Buckets {
+    fn new() -> Self {
+        Self {
+            map: HashMap::new(),
+            sweep_at: BUCKET_SWEEP_MIN,
+        }
+    }
+
+    fn sweep(&mut self, now: Instant, rate_limit: &RateLimitConfig) {
+        let burst = f64::from(rate_limit.burst);
+        self.map
+            .retain(|_, bucket| bucket.refilled(now, rate_limit) < burst);
+        self.sweep_at = (self.map.len() * 2).max(BUCKET_SWEEP_MIN);
+    }
+}
+
+#[derive(Hash, PartialEq, Eq, Clone)]
+pub(crate) struct DedupKey {
+    log_type: LogType,
+    level: String,
+    hostname: String,
+    message: String,
+}
+
+/// What [`Throttle::admit`] decided about a log.
+pub(crate) enum Admission {
+    Produce,
+    /// Produce, and call [`Throttle::close`] with the key once the dedup window has passed.
+    OpenWindow(DedupKey, Duration),
+    Suppress,
+}
+
+/// Decides which logs a [`crate::producer::KafkaProducer`] produces, as set by a
+/// [`ThrottleConfig`].
+pub struct Throttle {
+    sample: HashMap<String, f64>,
+    rate_limit: Option<RateLimitConfig>,
+    dedup_window: Option<Duration>,
+    /// Sampling credit per level; a log is kept each time it reaches 1.
+    credits: Mutex<HashMap<String, f64>>,
+    buckets: Mutex<Buckets>,
+    /// The logs whose dedup window is open, with the duplicates held back so far.
+    pending: Mutex<HashMap<DedupKey, Collapsed>>,
+    sampled_out: AtomicU64,
+    rate_limited: AtomicU64,
+    collapsed: AtomicU64,
+}
+
+impl Throttle {
+    pub fn new(cfg: &ThrottleConfig) -> anyhow::Result<Self> {
+        for (level, rate) in &cfg.sample {
+            anyhow::ensure!(
+                (0.0..=1.0).contains(rate),
+                "sample rate of {} must be between 0 and 1, got {}",
+                level,
+                rate
+            );
+        }
+        if let Some(rate_limit) = &cfg.rate_limit {
+            anyhow::ensure!(
+                rate_limit.per_second > 0.0 && rate_limit.burst > 0,
+                "rate limit needs a positive per_second and burst"
+            );
+        }
+        Ok(Self {
+            sample: cfg
+                .sample
+                .iter()
+                .map(|(level, rate)| (level.to_ascii_uppercase(), *rate))
+                .collect(),
+            rate_limit: cfg.rate_limit.clone(),
+            dedup_window: cfg.dedup_window_ms.map(Duration::from_millis),
+            credits: Mutex::new(HashMap::new()),
+            buckets: Mutex::new(Buckets::new()),
+            pending: Mutex::new(HashMap::new()),
+            sampled_out: AtomicU64::new(0),
+            rate_limited: AtomicU64::new(0),
+            collapsed: AtomicU64::new(0),
+        })
+    }
+
+    /// Decides whether to produce a log. The first log of a dedup window is produced right
+    /// away; its duplicates are counted until the window is closed.
+    pub(crate) fn admit<T: Loggable>(&self, entry: &T) -> Admission {
+        let Ok(doc) = serde_json::to_value(entry) else {
+            // Left for the producer to report.
+            return Admission::Produce;
+        };
+        let text = |name: &str| doc.get(name).and_then(Value::as_str).unwrap_or_default();
+        let level = text("level").to_ascii_uppercase();
+        let hostname = match text("hostname") {
+            "" => get_hostname(),
+            hostname => hostname.to_string(),
+        };
+
+        if !self.sampled(&level) {
+            self.sampled_out.fetch_add(1, Ordering::Relaxed);
+            return Admission::Suppress;
+        }
+        let Some(window) = self.dedup_window else {
+            return if self.take_token(entry.log_type(), &hostname) {
+                Admission::Produce
+            } else {
+                Admission::Suppress
+            };
+        };
+
+        let key = DedupKey {
+            log_type: entry.log_type(),
+            level,
+            hostname,
+            message: text("message").to_string(),
+        };
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(collapsed) = pending.get_mut(&key) {
+            collapsed.count += 1;
+            self.collapsed.fetch_add(1, Ordering::Relaxed);
+            return Admission::Suppress;
+        }
+        if !self.take_token(key.log_type, &key.hostname) {
+            return Admission::Suppress;
+        }
+        pending.insert(key.clone(), Collapsed::new(entry, doc));
+        Admission::OpenWindow(key, window)
+    }
+
+    /// Ends a dedup window. Returns the log to produce in place of its duplicates, if any came.
+    pub(crate) fn close(&self, key: &DedupKey) -> Option<Collapsed> {
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(key)
+            .filter(|collapsed| collapsed.count > 0)
+    }
+
+    /// Ends every dedup window now, e.g. at shutdown, and returns the logs to produce in place
+    /// of their duplicates.
+    pub(crate) fn release(&self) -> Vec<Collapsed> {
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .drain()
+            .map(|(_, collapsed)| collapsed)
+            .filter(|collapsed| collapsed.count > 0)
+            .collect()
+    }
+
+    pub fn metrics(&self) -> ThrottleMetrics {
+        ThrottleMetrics {
+            sampled_out: self.sampled_out.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            collapsed: self.collapsed.load(Ordering::Relaxed),
+        }
+    }
+
+    fn sampled(&self, level: &str) -> bool {
+        let Some(rate) = self.sample.get(level) else {
+            return true;
+        };
+        let mut credits = self.credits.lock().unwrap_or_else(|e| e.into_inner());
+        let credit = credits.entry(level.to_string()).or_insert(0.0);
+        *credit += rate;
+        if *credit >= 1.0 {
+            *credit -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn take_token(&self, log_type: LogType, hostname: &str) -> bool {
+        let Some(rate_limit) = &self.rate_limit else {
+            return true;
+        };
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.map.len() >= buckets.sweep_at {
+            buckets.sweep(now, rate_limit);
+        }
+        let bucket = buckets
+            .map
+            .entry((log_type, hostname.to_string()))
+            .or_insert(Bucket {
+                tokens: f64::from(rate_limit.burst),
+                refilled_at: now,
+            });
+        bucket.tokens = bucket.refilled(now, rate_limit);
+        bucket.refilled_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            self.rate_limited.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+}
+
+/// A copy of the first log of a dedup window, produced in place of the `count` duplicates that
+/// followed it. The count is added to the `repeat_count` context field and, since Avro and
+/// Protobuf schemas have no room for it, sent as a header too.
+pub(crate) struct Collapsed {
+    doc: Value,
+    count: u64,
+    log_type: LogType,
+    timestamp: Option<DateTime<Utc>>,
+    trace_id: Option<String>,
+    span_id: Option<String>,
+    headers: Vec<(String, String)>,
+    topic: Option<String>,
+    key: Option<Vec<u8>>,
+    fits_log_schema: bool,
+}
+
+impl Collapsed {
+    fn new<T: Loggable>(entry: &T, doc: Value) -> Self {
+        Self {
+            doc,
+            count: 0,
+            log_type: entry.log_type(),
+            timestamp: entry.timestamp(),
+            trace_id: entry.trace_id().map(str::to_string),
+            span_id: entry.span_id().map(str::to_string),
+            headers: entry.headers(),
+            topic: entry.topic().map(str::to_string),
+            key: entry.key(),
+            fits_log_schema: entry.fits_log_schema(),
+        }
+    }
+}
+
+impl Serialize for Collapsed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut doc = self.doc.clone();
+        if let Value::Object(map) = &mut doc {
+            let fields = map
+                .entry("fields")
+                .or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(fields) = fields {
+                fields.insert(REPEAT_COUNT_FIELD.into(), Value::from(self.count));
+            }
+        }
+        doc.serialize(serializer)
+    }
+}
+
+impl Loggable for Collapsed {
+    fn log_type(&self) -> LogType {
+        self.log_type
+    }
+
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.timestamp
+    }
+
+    fn trace_id(&self) -> Option<&str> {
+        self.trace_id.as_deref()
+    }
+
+    fn span_id(&self) -> Option<&str> {
+        self.span_id.as_deref()
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        let mut headers = self.headers.clone();
+        headers.push((REPEAT_COUNT_HEADER.to_string(), self.count.to_string()));
+        headers
+    }
+
+    fn topic(&self) -> Option<&str> {
+        self.topic.as_deref()
+    }
+
+    fn key(&self) -> Option<Vec<u8>> {
+        self.key.clone()
+    }
+
+    fn fits_log_schema(&self) -> bool {
+        self.fits_log_schema
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::InfoLog;
+
+    fn info(message: &str) -> InfoLog {
+        InfoLog::new("INFO".into(), message.into(), "host-a".into(), Utc::now())
+    }
+
+    fn throttle(cfg: ThrottleConfig) -> Throttle {
+        Throttle::new(&cfg).unwrap()
+    }
+
+    #[test]
+    fn duplicates_are_collapsed_after_the_first_log() {
+        let throttle = throttle(ThrottleConfig {
+            dedup_window_ms: Some(60_000),
+            ..ThrottleConfig::default()
+        });
+        let Admission::OpenWindow(key, window) = throttle.admit(&info("disk full")) else {
+            panic!("the first log should open a window");
+        };
+        assert_eq!(window, Duration::from_secs(60));
+        for _ in 0..3 {
+            assert!(matches!(
+                throttle.admit(&info("disk full")),
+                Admission::Suppress
+            ));
+        }
+        assert!(matches!(
+            throttle.admit(&info("disk ok")),
+            Admission::OpenWindow(..)
+        ));
+
+        let collapsed = throttle.close(&key).unwrap();
+        assert_eq!(collapsed.count, 3);
+        assert_eq!(throttle.metrics().collapsed, 3);
+        let doc = serde_json::to_value(&collapsed).unwrap();
+        assert_eq!(doc["message"], "disk full");
+        assert_eq!(doc["fields"][REPEAT_COUNT_FIELD], 3);
+        assert!(
+            collapsed
+                .headers()
+                .contains(&(REPEAT_COUNT_HEADER.to_string(), "3".to_string()))
+        );
+
+        // The window is over, so the next copy starts a new one.
+        assert!(matches!(
+            throttle.admit(&info("disk full")),
+            Admission::OpenWindow(..)
+        ));
+    }
+
+    #[test]
+    fn windows_without_duplicates_produce_nothing() {
+        let throttle = throttle(ThrottleConfig {
+            dedup_window_ms: Some(60_000),
+            ..ThrottleConfig::default()
+        });
+        let Admission::OpenWindow(key, _) = throttle.admit(&info("once")) else {
+            panic!("the first log should open a window");
+        };
+        assert!(throttle.close(&key).is_none());
+    }
+
+    #[test]
+    fn release_returns_every_open_window_with_duplicates() {
+        let throttle = throttle(ThrottleConfig {
+            dedup_window_ms: Some(60_000),
+            ..ThrottleConfig::default()
+        });
+        for message in ["a", "a", "b", "c", "c", "c"] {
+            throttle.admit(&info(message));
+        }
+        let mut counts: Vec<u64> = throttle.release().iter().map(|c| c.count).collect();
+        counts.sort_unstable();
+        assert_eq!(counts, [1, 2]);
+        assert!(throttle.release().is_empty());
+    }
+
+    #[test]
+    fn sampling_keeps_the_configured_share() {
+        let throttle = throttle(ThrottleConfig {
+            sample: HashMap::from([("info".to_string(), 0.25)]),
+            ..ThrottleConfig::default()
+        });
+        let kept = (0..100)
+            .filter(|_| matches!(throttle.admit(&info("x")), Admission::Produce))
+            .count();
+        assert_eq!(kept, 25);
+        assert_eq!(throttle.metrics().sampled_out, 75);
+    }
+
+    #[test]
+    fn rate_limit_allows_the_burst() {
+        let throttle = throttle(ThrottleConfig {
+            rate_limit: Some(RateLimitConfig {
+                per_second: 0.001,
+                burst: 5,
+            }),
+            ..ThrottleConfig::default()
+        });
+        let kept = (0..20)
+            .filter(|_| matches!(throttle.admit(&info("x")), Admission::Produce))
+            .count();
+        assert_eq!(kept, 5);
+        assert_eq!(throttle.metrics().rate_limited, 15);
+    }
+
+    #[test]
+    fn full_buckets_are_swept_out() {
+        let throttle = throttle(ThrottleConfig {
+            rate_limit: Some(RateLimitConfig {
+                per_second: 1_000_000.0,
+                burst: 1,
+            }),
+            ..ThrottleConfig::default()
+        });
+        let from =
+            |hostname: &str| InfoLog::new("INFO".into(), "x".into(), hostname.into(), Utc::now());
+        for n in 0..BUCKET_SWEEP_MIN * 4 {
+            assert!(matches!(
+                throttle.admit(&from(&format!("host-{}", n))),
+                Admission::Produce
+            ));
+        }
+        // Every bucket refills within a microsecond, so none outlives a sweep for long.
+        let buckets = throttle.buckets.lock().unwrap();
+        assert!(
+            buckets.map.len() <= BUCKET_SWEEP_MIN,
+            "{}",
+            buckets.map.len()
+        );
+    }
+
+    #[test]
+    fn buckets_still_limiting_survive_a_sweep() {
+        let throttle = throttle(ThrottleConfig {
+            rate_limit: Some(RateLimitConfig {
+                per_second: 0.001,
+                burst: 1,
+            }),
+            ..ThrottleConfig::default()
+        });
+        let from =
+            |hostname: &str| InfoLog::new("INFO".into(), "x".into(), hostname.into(), Utc::now());
+        assert!(matches!(throttle.admit(&from("noisy")), Admission::Produce));
+        for n in 0..BUCKET_SWEEP_MIN {
+            throttle.admit(&from(&format!("host-{}", n)));
+        }
+        assert!(matches!(
+            throttle.admit(&from("noisy")),
+            Admission::Suppress
+        ));
+    }
+}
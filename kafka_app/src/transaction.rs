@@ -0,0 +1,162 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::producer::Producer;
+use rdkafka::producer::future_producer::DeliveryFuture;
+use tokio::sync::{Mutex, MutexGuard};
+use tokio::time::{Instant, sleep};
+
+use crate::models::Loggable;
+use crate::producer::{BoxError, DeliveryReport, InnerProducer, KafkaProducer, Outcome};
+
+/// How long to wait before retrying a send while librdkafka's queue is full.
+const QUEUE_FULL_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Shared by the clones of a transactional [`KafkaProducer`].
+#[derive(Default)]
+pub(crate) struct TransactionState {
+    lock: Mutex<()>,
+    /// Set when a [`Transaction`] is dropped without being committed or aborted. The next
+    /// `begin` aborts it first.
+    abort_pending: AtomicBool,
+}
+
+/// An open Kafka transaction. Logs sent through it are only visible to `read_committed`
+/// consumers after [`Transaction::commit`]. Dropping it without committing aborts it.
+pub struct Transaction<'a> {
+    producer: &'a KafkaProducer,
+    state: &'a TransactionState,
+    _guard: MutexGuard<'a, ()>,
+    /// The topic and record deliveries of each log sent; a split log has several records.
+    pending: Vec<(String, Vec<DeliveryFuture>)>,
+    started: Instant,
+    finished: bool,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) async fn begin(
+        producer: &'a KafkaProducer,
+        state: &'a TransactionState,
+    ) -> Result<Self, BoxError> {
+        let guard = state.lock.lock().await;
+        // Cleared only once the abort went through, so a failed one is tried again.
+        if state.abort_pending.load(Ordering::SeqCst) {
+            abort(&producer.inner, producer.send_timeout()).await?;
+            state.abort_pending.store(false, Ordering::SeqCst);
+        }
+        producer.inner.begin_transaction()?;
+        Ok(Self {
+            producer,
+            state,
+            _guard: guard,
+            pending: Vec::new(),
+            started: Instant::now(),
+            finished: false,
+        })
+    }
+
+    /// Queues a log in the transaction. It is flushed by `commit`.
+    pub async fn send<T: Loggable>(&mut self, entry: T) -> Result<(), BoxError> {
+        let records = self
+            .producer
+            .prepare(&entry)
+            .await
+            .map_err(|(_, error)| error)?;
+
+        let deadline = Instant::now() + self.producer.send_timeout();
+        let mut deliveries = Vec::with_capacity(records.len());
+        for prepared in &records {
+            let mut record = prepared.record();
+            loop {
+                match self.producer.inner.send_result(record) {
+                    Ok(delivery) => {
+                        deliveries.push(delivery);
+                        break;
+                    }
+                    Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), returned))
+                        if Instant::now() < deadline =>
+                    {
+                        record = returned;
+                        sleep(QUEUE_FULL_BACKOFF).await;
+                    }
+                    Err((err, _)) => return Err(Box::new(err)),
+                }
+            }
+        }
+        self.pending.push((records[0].topic.clone(), deliveries));
+        Ok(())
+    }
+
+    /// Topics of the logs sent so far, in send order.
+    pub fn topics(&self) -> impl Iterator<Item = String> + '_ {
+        self.pending.iter().map(|(topic, _)| topic.clone())
+    }
+
+    /// Flushes and commits every log sent in the transaction. If the commit fails the
+    /// transaction is aborted and none of the logs become visible.
+    pub async fn commit(self) -> Result<DeliveryReport, BoxError> {
+        let producer = self.producer;
+        let started = self.started;
+        let outcomes = self.commit_outcomes().await?;
+        let latency = started.elapsed();
+        let mut report = DeliveryReport::default();
+        for outcome in outcomes {
+            producer.observe(&outcome, latency);
+            report.record(outcome);
+        }
+        Ok(report)
+    }
+
+    pub async fn abort(mut self) -> Result<(), BoxError> {
+        self.finished = true;
+        abort(&self.producer.inner, self.producer.send_timeout()).await
+    }
+
+    pub(crate) async fn commit_outcomes(mut self) -> Result<Vec<Outcome>, BoxError> {
+        self.finished = true;
+        let inner = self.producer.inner.clone();
+        let timeout = self.producer.send_timeout();
+        let committed =
+            tokio::task::spawn_blocking(move || inner.commit_transaction(timeout)).await?;
+        if let Err(err) = committed {
+            // A fatal error leaves nothing to abort; the original error is what matters.
+            let _ = abort(&self.producer.inner, timeout).await;
+            return Err(Box::new(err));
+        }
+
+        let mut outcomes = Vec::with_capacity(self.pending.len());
+        for (topic, deliveries) in self.pending.drain(..) {
+            let mut outcome = Outcome::Delivered {
+                topic: topic.clone(),
+            };
+            for delivery in deliveries {
+                let error: BoxError = match delivery.await {
+                    Ok(Ok(_)) => continue,
+                    Ok(Err((err, _))) => Box::new(err),
+                    Err(canceled) => Box::new(canceled),
+                };
+                outcome = Outcome::Failed {
+                    topic: Some(topic.clone()),
+                    error,
+                };
+            }
+            outcomes.push(outcome);
+        }
+        Ok(outcomes)
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.state.abort_pending.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+async fn abort(producer: &InnerProducer, timeout: Duration) -> Result<(), BoxError> {
+    let producer = producer.clone();
+    tokio::task::spawn_blocking(move || producer.abort_transaction(timeout)).await??;
+    Ok(())
+}
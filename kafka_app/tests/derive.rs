@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use kafka_app::{
+    Config, EncodingConfig, KafkaProducer, LogType, Loggable, MissingField, PayloadFormat, Sent,
+};
+use rdkafka::mocking::MockCluster;
+use rdkafka::producer::DefaultProducerContext;
+use serde::Serialize;
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Loggable, Debug)]
+#[loggable(topic = "payment_logs", key = "order_id", level = "WARN")]
+struct PaymentLog {
+    level: String,
+    message: String,
+    hostname: String,
+    timestamp: DateTime<Utc>,
+    order_id: u64,
+    #[loggable(default)]
+    retries: u32,
+    card_brand: Option<String>,
+}
+
+#[derive(Serialize, Loggable, Debug)]
+struct AccessLog {
+    level: String,
+    path: String,
+}
+
+fn payment() -> PaymentLog {
+    PaymentLog::builder()
+        .message("card declined")
+        .order_id(42u64)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn builder_fills_in_defaults() {
+    let log = payment();
+    assert_eq!(log.level, "WARN");
+    assert_eq!(log.hostname, kafka_app::helper::get_hostname());
+    assert_eq!(log.retries, 0);
+    assert_eq!(log.card_brand, None);
+    assert!(Utc::now() - log.timestamp < chrono::TimeDelta::seconds(5));
+}
+
+#[test]
+fn builder_takes_every_field() {
+    let at = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+    let log = PaymentLog::builder()
+        .level("ERROR")
+        .message("card declined")
+        .hostname("host-a")
+        .timestamp(at)
+        .order_id(7u64)
+        .retries(2u32)
+        .card_brand("visa")
+        .build()
+        .unwrap();
+    assert_eq!(log.level, "ERROR");
+    assert_eq!(log.hostname, "host-a");
+    assert_eq!(log.retries, 2);
+    assert_eq!(log.card_brand.as_deref(), Some("visa"));
+    assert_eq!(log.timestamp(), Some(at));
+}
+
+#[test]
+fn builder_reports_missing_fields() {
+    let err = PaymentLog::builder()
+        .message("no order")
+        .build()
+        .unwrap_err();
+    assert_eq!(err, MissingField("order_id"));
+    assert_eq!(
+        AccessLog::builder().level("INFO").build().unwrap_err(),
+        MissingField("path")
+    );
+}
+
+#[test]
+fn attributes_set_the_loggable_methods() {
+    let log = payment();
+    // The attribute picks the log type, whatever the field says.
+    assert_eq!(log.log_type(), LogType::Warn);
+    assert_eq!(log.topic(), Some("payment_logs"));
+    assert_eq!(log.key(), Some(b"42".to_vec()));
+    assert!(!log.fits_log_schema());
+}
+
+#[test]
+fn level_field_picks_the_log_type_without_the_attribute() {
+    let log = |level: &str| AccessLog::builder().level(level).path("/").build().unwrap();
+    assert_eq!(log("ERROR").log_type(), LogType::Error);
+    assert_eq!(log("warn").log_type(), LogType::Warn);
+    assert_eq!(log("DEBUG").log_type(), LogType::Info);
+    assert_eq!(log("INFO").topic(), None);
+    assert_eq!(log("INFO").key(), None);
+    assert_eq!(log("INFO").timestamp(), None);
+}
+
+fn config(cluster: &MockCluster<'static, DefaultProducerContext>, format: PayloadFormat) -> Config {
+    Config::builder()
+        .broker(cluster.bootstrap_servers())
+        .encoding(EncodingConfig {
+            format,
+            ..EncodingConfig::default()
+        })
+        .build()
+}
+
+#[tokio::test]
+async fn derived_logs_are_produced_as_json() {
+    let cluster = MockCluster::new(1).unwrap();
+    let producer = KafkaProducer::new(&config(&cluster, PayloadFormat::Json)).unwrap();
+
+    let sent = producer.send(payment(), &SEND_TIMEOUT).await.unwrap();
+    assert_eq!(sent, Sent::Delivered);
+    assert_eq!(
+        producer.delivery_metrics().topics["payment_logs"].delivered,
+        1
+    );
+}
+
+#[tokio::test]
+async fn derived_logs_do_not_fit_the_protobuf_schemas() {
+    let cluster = MockCluster::new(1).unwrap();
+    let producer = KafkaProducer::new(&config(&cluster, PayloadFormat::Protobuf)).unwrap();
+
+    let err = producer.send(payment(), &SEND_TIMEOUT).await.unwrap_err();
+    assert!(err.to_string().contains("only JSON"), "{}", err);
+}
@@ -0,0 +1,11 @@
+#[test]
+fn derive_ui() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/ui/derive_ok.rs");
+    t.compile_fail("tests/ui/unknown_level.rs");
+    t.compile_fail("tests/ui/missing_level.rs");
+    t.compile_fail("tests/ui/unknown_key.rs");
+    t.compile_fail("tests/ui/unsupported_attribute.rs");
+    t.compile_fail("tests/ui/unsupported_field_attribute.rs");
+    t.compile_fail("tests/ui/not_a_named_struct.rs");
+}
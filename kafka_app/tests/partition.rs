@@ -0,0 +1,110 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use kafka_app::{
+    Config, InfoLog, KafkaProducer, LogContext, LogType, PartitionStrategy, PartitioningConfig,
+};
+use rdkafka::consumer::{BaseConsumer, Consumer};
+use rdkafka::mocking::MockCluster;
+use rdkafka::producer::DefaultProducerContext;
+use rdkafka::{ClientConfig, Message, Offset, TopicPartitionList};
+use serde_json::Value;
+
+const PARTITIONS: i32 = 4;
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn request_log(request_id: &str) -> InfoLog {
+    let mut context = LogContext::default();
+    context
+        .fields
+        .insert("request_id".into(), Value::String(request_id.into()));
+    InfoLog::new("INFO".into(), "handled".into(), "host-a".into(), Utc::now()).with_context(context)
+}
+
+/// Produces the logs with `strategy` and returns the partitions each record key landed on.
+async fn partitions_by_key(
+    strategy: PartitionStrategy,
+    logs: Vec<InfoLog>,
+) -> BTreeMap<Option<Vec<u8>>, BTreeSet<i32>> {
+    let cluster = MockCluster::<DefaultProducerContext>::new(1).unwrap();
+    let cfg = Config::builder()
+        .broker(cluster.bootstrap_servers())
+        .partitioning(PartitioningConfig {
+            default: strategy,
+            ..PartitioningConfig::default()
+        })
+        .build();
+    let topic = cfg.routing.route(LogType::Info, "{}");
+    cluster.create_topic(&topic, PARTITIONS, 1).unwrap();
+
+    let producer = KafkaProducer::new(&cfg).unwrap();
+    let total = logs.len();
+    let report = producer.send_many(logs, &SEND_TIMEOUT).await;
+    assert_eq!(report.delivered as usize, total);
+
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", cluster.bootstrap_servers())
+        .set("group.id", "partition-test")
+        .create()
+        .unwrap();
+    let mut assignment = TopicPartitionList::new();
+    for partition in 0..PARTITIONS {
+        assignment
+            .add_partition_offset(&topic, partition, Offset::Beginning)
+            .unwrap();
+    }
+    consumer.assign(&assignment).unwrap();
+
+    let mut partitions: BTreeMap<Option<Vec<u8>>, BTreeSet<i32>> = BTreeMap::new();
+    let mut received = 0;
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while received < total && Instant::now() < deadline {
+        if let Some(Ok(message)) = consumer.poll(Duration::from_millis(100)) {
+            partitions
+                .entry(message.key().map(<[u8]>::to_vec))
+                .or_default()
+                .insert(message.partition());
+            received += 1;
+        }
+    }
+    assert_eq!(received, total);
+    partitions
+}
+
+#[tokio::test]
+async fn field_keeps_each_request_on_one_partition() {
+    let logs = (0..60)
+        .map(|i| request_log(&format!("req-{}", i % 12)))
+        .collect();
+    let partitions = partitions_by_key(PartitionStrategy::Field("request_id".into()), logs).await;
+
+    assert_eq!(partitions.len(), 12);
+    for (key, used) in &partitions {
+        assert!(key.is_some());
+        assert_eq!(used.len(), 1, "request {:?} spans partitions", key);
+    }
+    let spread: BTreeSet<i32> = partitions.values().flatten().copied().collect();
+    assert!(spread.len() > 1, "all requests landed on {:?}", spread);
+}
+
+#[tokio::test]
+async fn hostname_keeps_the_producer_on_one_partition() {
+    let logs = (0..20)
+        .map(|i| request_log(&format!("req-{}", i)))
+        .collect();
+    let partitions = partitions_by_key(PartitionStrategy::Hostname, logs).await;
+
+    assert_eq!(partitions.len(), 1);
+    assert_eq!(partitions.values().next().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn round_robin_sends_unkeyed_records() {
+    let logs = (0..20)
+        .map(|i| request_log(&format!("req-{}", i)))
+        .collect();
+    let partitions = partitions_by_key(PartitionStrategy::RoundRobin, logs).await;
+
+    assert_eq!(partitions.keys().collect::<Vec<_>>(), [&None]);
+}
@@ -0,0 +1,215 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use kafka_app::{Config, KafkaProducer, LogType, OversizeConfig, TailConfig, Tailer};
+use rdkafka::consumer::{BaseConsumer, Consumer};
+use rdkafka::mocking::MockCluster;
+use rdkafka::producer::DefaultProducerContext;
+use rdkafka::{ClientConfig, Message, Offset, TopicPartitionList};
+use serde_json::Value;
+
+fn saved_offsets(state_file: &Path) -> Vec<u64> {
+    let Ok(raw) = fs::read(state_file) else {
+        return Vec::new();
+    };
+    let state: Value = serde_json::from_slice(&raw).unwrap();
+    state["files"]
+        .as_object()
+        .unwrap()
+        .values()
+        .map(|file| file["offset"].as_u64().unwrap())
+        .collect()
+}
+
+fn append(path: &Path, text: &str) {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .unwrap()
+        .write_all(text.as_bytes())
+        .unwrap();
+}
+
+/// A mock cluster and a config producing to it. Tailed plain-text lines are informational, so
+/// they all go to the returned topic.
+fn cluster() -> (MockCluster<'static, DefaultProducerContext>, Config, String) {
+    let cluster = MockCluster::new(1).unwrap();
+    let cfg = Config::builder()
+        .broker(cluster.bootstrap_servers())
+        .build();
+    let topic = cfg.routing.route(LogType::Info, "{}");
+    cluster.create_topic(&topic, 1, 1).unwrap();
+    (cluster, cfg, topic)
+}
+
+fn tailer(cfg: &Config, pattern: &Path, state_file: &Path) -> Tailer {
+    Tailer::new(
+        Arc::new(KafkaProducer::new(cfg).unwrap()),
+        TailConfig {
+            paths: vec![pattern.display().to_string()],
+            state_file: state_file.to_path_buf(),
+            ..TailConfig::default()
+        },
+    )
+    .unwrap()
+}
+
+/// The messages of every log on `topic`, once `expected` arrived and nothing more followed.
+fn messages(
+    cluster: &MockCluster<'_, DefaultProducerContext>,
+    topic: &str,
+    expected: usize,
+) -> Vec<String> {
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", cluster.bootstrap_servers())
+        .set("group.id", "tail-test")
+        .create()
+        .unwrap();
+    let mut assignment = TopicPartitionList::new();
+    assignment
+        .add_partition_offset(topic, 0, Offset::Beginning)
+        .unwrap();
+    consumer.assign(&assignment).unwrap();
+
+    let mut messages = Vec::new();
+    let mut deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        if let Some(Ok(message)) = consumer.poll(Duration::from_millis(100)) {
+            let log: Value = serde_json::from_slice(message.payload().unwrap()).unwrap();
+            messages.push(log["message"].as_str().unwrap().to_string());
+            if messages.len() == expected {
+                // Long enough for a duplicate to show up.
+                deadline = Instant::now() + Duration::from_millis(500);
+            }
+        }
+    }
+    messages
+}
+
+#[tokio::test]
+async fn failed_batches_are_read_again() {
+    let cluster = MockCluster::<DefaultProducerContext>::new(1).unwrap();
+    let cfg = Config::builder()
+        .broker(cluster.bootstrap_servers())
+        .property("message.timeout.ms", "1000")
+        .build();
+
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("app.log");
+    let lines = "first line\nsecond line\n";
+    fs::write(&log, lines).unwrap();
+    let state_file = dir.path().join("state.json");
+    let mut tailer = tailer(&cfg, &log, &state_file);
+
+    cluster.broker_down(1).unwrap();
+    assert_eq!(tailer.poll().await.unwrap(), 0);
+    assert!(saved_offsets(&state_file).iter().all(|offset| *offset == 0));
+
+    cluster.broker_up(1).unwrap();
+    let mut read = 0;
+    for _ in 0..5 {
+        read = tailer.poll().await.unwrap();
+        if read > 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    assert_eq!(read, 2);
+    assert_eq!(saved_offsets(&state_file), [lines.len() as u64]);
+}
+
+#[tokio::test]
+async fn lines_that_can_never_be_produced_are_skipped() {
+    let cluster = MockCluster::<DefaultProducerContext>::new(1).unwrap();
+    let cfg = Config::builder()
+        .broker(cluster.bootstrap_servers())
+        .oversize(OversizeConfig {
+            max_bytes: 1000,
+            ..OversizeConfig::default()
+        })
+        .build();
+    let topic = cfg.routing.route(LogType::Info, "{}");
+    cluster.create_topic(&topic, 1, 1).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("app.log");
+    let lines = format!("{}\nsmall line\n", "x".repeat(2000));
+    fs::write(&log, &lines).unwrap();
+    let state_file = dir.path().join("state.json");
+    let mut tailer = tailer(&cfg, &log, &state_file);
+
+    // The oversize line is rejected, the batch is not held back by it.
+    assert_eq!(tailer.poll().await.unwrap(), 2);
+    assert_eq!(saved_offsets(&state_file), [lines.len() as u64]);
+    assert_eq!(messages(&cluster, &topic, 1), ["small line"]);
+}
+
+#[tokio::test]
+async fn rotated_files_are_read_to_the_end() {
+    let (cluster, cfg, topic) = cluster();
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("app.log");
+    fs::write(&log, "before rotation\n").unwrap();
+    let mut tailer = tailer(&cfg, &log, &dir.path().join("state.json"));
+    assert_eq!(tailer.poll().await.unwrap(), 1);
+
+    // Written just before the file is renamed away, then the new file starts.
+    append(&log, "last of the old file\n");
+    fs::rename(&log, dir.path().join("app.log.1")).unwrap();
+    fs::write(&log, "first of the new file\n").unwrap();
+    assert_eq!(tailer.poll().await.unwrap(), 2);
+
+    let mut received = messages(&cluster, &topic, 3);
+    received.sort();
+    assert_eq!(
+        received,
+        [
+            "before rotation",
+            "first of the new file",
+            "last of the old file"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn files_truncated_in_place_are_read_from_the_start() {
+    let (cluster, cfg, topic) = cluster();
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("app.log");
+    fs::write(&log, "copied line one\ncopied line two\n").unwrap();
+    let mut tailer = tailer(&cfg, &log, &dir.path().join("state.json"));
+    assert_eq!(tailer.poll().await.unwrap(), 2);
+
+    // copytruncate: the content is copied elsewhere and the file emptied, then written again.
+    fs::write(&log, "").unwrap();
+    append(&log, "after truncation\n");
+    assert_eq!(tailer.poll().await.unwrap(), 1);
+
+    assert_eq!(
+        messages(&cluster, &topic, 3),
+        ["copied line one", "copied line two", "after truncation"]
+    );
+}
+
+#[tokio::test]
+async fn a_restart_resumes_from_the_state_file() {
+    let (cluster, cfg, topic) = cluster();
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("app.log");
+    let state_file = dir.path().join("state.json");
+    fs::write(&log, "first run one\nfirst run two\n").unwrap();
+    assert_eq!(tailer(&cfg, &log, &state_file).poll().await.unwrap(), 2);
+
+    append(&log, "second run\n");
+    let mut restarted = tailer(&cfg, &log, &state_file);
+    assert_eq!(restarted.poll().await.unwrap(), 1);
+
+    assert_eq!(
+        messages(&cluster, &topic, 3),
+        ["first run one", "first run two", "second run"]
+    );
+}
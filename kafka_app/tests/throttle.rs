@@ -0,0 +1,78 @@
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use kafka_app::{Config, InfoLog, KafkaProducer, Sent, ThrottleConfig};
+use rdkafka::mocking::MockCluster;
+use rdkafka::producer::DefaultProducerContext;
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn info(message: &str) -> InfoLog {
+    InfoLog::new("INFO".into(), message.into(), "host-a".into(), Utc::now())
+}
+
+#[tokio::test]
+async fn duplicates_do_not_hold_up_the_first_log() {
+    let cluster = MockCluster::<DefaultProducerContext>::new(1).unwrap();
+    let window = Duration::from_secs(2);
+    let cfg = Config::builder()
+        .broker(cluster.bootstrap_servers())
+        .throttle(ThrottleConfig {
+            dedup_window_ms: Some(window.as_millis() as u64),
+            ..ThrottleConfig::default()
+        })
+        .build();
+    let producer = KafkaProducer::new(&cfg).unwrap();
+
+    let started = Instant::now();
+    let first = producer
+        .send(info("disk full"), &SEND_TIMEOUT)
+        .await
+        .unwrap();
+    assert_eq!(first, Sent::Delivered);
+    assert!(started.elapsed() < window);
+    for _ in 0..4 {
+        let duplicate = producer
+            .send(info("disk full"), &SEND_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(duplicate, Sent::Suppressed);
+    }
+
+    // The collapsed copy goes out once the window has passed.
+    tokio::time::sleep(window + Duration::from_secs(2)).await;
+    let metrics = producer.delivery_metrics();
+    let delivered: u64 = metrics.topics.values().map(|topic| topic.delivered).sum();
+    assert_eq!(delivered, 2);
+    assert_eq!(metrics.suppressed, 4);
+    assert_eq!(producer.throttle_metrics().unwrap().collapsed, 4);
+}
+
+#[tokio::test]
+async fn shutdown_produces_open_windows() {
+    let cluster = MockCluster::<DefaultProducerContext>::new(1).unwrap();
+    let cfg = Config::builder()
+        .broker(cluster.bootstrap_servers())
+        .throttle(ThrottleConfig {
+            dedup_window_ms: Some(60_000),
+            ..ThrottleConfig::default()
+        })
+        .build();
+    let producer = KafkaProducer::new(&cfg).unwrap();
+
+    for _ in 0..3 {
+        producer
+            .send(info("retrying"), &SEND_TIMEOUT)
+            .await
+            .unwrap();
+    }
+    producer.shutdown(Duration::from_secs(5)).await;
+
+    let delivered: u64 = producer
+        .delivery_metrics()
+        .topics
+        .values()
+        .map(|topic| topic.delivered)
+        .sum();
+    assert_eq!(delivered, 2);
+}
@@ -0,0 +1,153 @@
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use kafka_app::{ClientSettings, Config, DeliveryGuarantee, InfoLog, KafkaProducer, LogType, Sent};
+use rdkafka::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, Consumer};
+use rdkafka::mocking::MockCluster;
+use rdkafka::producer::DefaultProducerContext;
+use rdkafka::{Offset, TopicPartitionList};
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn info(message: &str) -> InfoLog {
+    InfoLog::new("INFO".into(), message.into(), "host-a".into(), Utc::now())
+}
+
+fn config(
+    cluster: &MockCluster<'static, DefaultProducerContext>,
+    guarantee: DeliveryGuarantee,
+) -> Config {
+    let cfg = Config::builder()
+        .broker(cluster.bootstrap_servers())
+        .client(ClientSettings {
+            guarantee: Some(guarantee),
+            transactional_id: Some("transaction-test".into()),
+            ..ClientSettings::default()
+        })
+        .send_timeout(SEND_TIMEOUT)
+        .build();
+    for topic in cfg.routing.all_topics() {
+        cluster.create_topic(&topic, 1, 1).unwrap();
+    }
+    cfg
+}
+
+/// Messages in the info topic that a `read_committed` consumer gets to see.
+fn committed_messages(
+    cluster: &MockCluster<'static, DefaultProducerContext>,
+    cfg: &Config,
+) -> usize {
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", cluster.bootstrap_servers())
+        .set("group.id", "transaction-test")
+        .set("isolation.level", "read_committed")
+        .set("enable.partition.eof", "true")
+        .create()
+        .unwrap();
+    let mut assignment = TopicPartitionList::new();
+    assignment
+        .add_partition_offset(
+            &cfg.routing.route(LogType::Info, "{}"),
+            0,
+            Offset::Beginning,
+        )
+        .unwrap();
+    consumer.assign(&assignment).unwrap();
+
+    let mut count = 0;
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        match consumer.poll(Duration::from_millis(100)) {
+            Some(Ok(_)) => count += 1,
+            Some(Err(rdkafka::error::KafkaError::PartitionEOF(_))) => break,
+            _ => {}
+        }
+    }
+    count
+}
+
+#[tokio::test]
+async fn commit_makes_the_logs_visible() {
+    let cluster = MockCluster::new(1).unwrap();
+    let cfg = config(&cluster, DeliveryGuarantee::ExactlyOnce);
+    let producer = KafkaProducer::new(&cfg).unwrap();
+
+    let mut transaction = producer.begin().await.unwrap();
+    for i in 0..3 {
+        transaction.send(info(&format!("log {}", i))).await.unwrap();
+    }
+    let report = transaction.commit().await.unwrap();
+    assert_eq!(report.delivered, 3);
+    assert_eq!(report.failed, 0);
+    assert_eq!(committed_messages(&cluster, &cfg), 3);
+}
+
+#[tokio::test]
+async fn aborted_logs_stay_hidden() {
+    let cluster = MockCluster::new(1).unwrap();
+    let cfg = config(&cluster, DeliveryGuarantee::ExactlyOnce);
+    let producer = KafkaProducer::new(&cfg).unwrap();
+
+    let mut transaction = producer.begin().await.unwrap();
+    transaction.send(info("aborted")).await.unwrap();
+    transaction.send(info("aborted too")).await.unwrap();
+    transaction.abort().await.unwrap();
+
+    let mut transaction = producer.begin().await.unwrap();
+    transaction.send(info("committed")).await.unwrap();
+    transaction.commit().await.unwrap();
+    assert_eq!(committed_messages(&cluster, &cfg), 1);
+}
+
+#[tokio::test]
+async fn a_dropped_transaction_is_aborted_by_the_next_begin() {
+    let cluster = MockCluster::new(1).unwrap();
+    let cfg = config(&cluster, DeliveryGuarantee::ExactlyOnce);
+    let producer = KafkaProducer::new(&cfg).unwrap();
+
+    {
+        let mut transaction = producer.begin().await.unwrap();
+        transaction.send(info("dropped")).await.unwrap();
+    }
+    let mut transaction = producer.begin().await.unwrap();
+    transaction.send(info("committed")).await.unwrap();
+    assert_eq!(transaction.commit().await.unwrap().delivered, 1);
+    assert_eq!(committed_messages(&cluster, &cfg), 1);
+}
+
+#[tokio::test]
+async fn exactly_once_sends_commit_on_their_own() {
+    let cluster = MockCluster::new(1).unwrap();
+    let cfg = config(&cluster, DeliveryGuarantee::ExactlyOnce);
+    let producer = KafkaProducer::new(&cfg).unwrap();
+
+    assert_eq!(
+        producer.send(info("single"), &SEND_TIMEOUT).await.unwrap(),
+        Sent::Delivered
+    );
+    let report = producer
+        .send_many((0..4).map(|i| info(&format!("batch {}", i))), &SEND_TIMEOUT)
+        .await;
+    assert_eq!(report.delivered, 4);
+    assert_eq!(committed_messages(&cluster, &cfg), 5);
+}
+
+#[tokio::test]
+async fn other_guarantees_deliver_without_transactions() {
+    for guarantee in [
+        DeliveryGuarantee::AtLeastOnce,
+        DeliveryGuarantee::AtMostOnce,
+    ] {
+        let cluster = MockCluster::new(1).unwrap();
+        let cfg = config(&cluster, guarantee);
+        let producer = KafkaProducer::new(&cfg).unwrap();
+
+        assert!(producer.begin().await.is_err());
+        let report = producer
+            .send_many((0..3).map(|i| info(&format!("log {}", i))), &SEND_TIMEOUT)
+            .await;
+        assert_eq!(report.delivered, 3, "{:?}", guarantee);
+        assert_eq!(committed_messages(&cluster, &cfg), 3, "{:?}", guarantee);
+    }
+}
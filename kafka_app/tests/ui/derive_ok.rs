@@ -0,0 +1,20 @@
+use chrono::{DateTime, Utc};
+use kafka_app::Loggable;
+use serde::Serialize;
+
+#[derive(Serialize, Loggable)]
+#[loggable(topic = "payment_logs", key = "order_id", level = "warning")]
+struct PaymentLog {
+    level: String,
+    message: String,
+    hostname: String,
+    timestamp: DateTime<Utc>,
+    order_id: u64,
+    #[loggable(default)]
+    retries: u32,
+    card_brand: Option<String>,
+}
+
+fn main() {
+    let _ = PaymentLog::builder().message("declined").order_id(42u64).build();
+}
@@ -0,0 +1,9 @@
+use kafka_app::Loggable;
+use serde::Serialize;
+
+#[derive(Serialize, Loggable)]
+struct AuditLog {
+    message: String,
+}
+
+fn main() {}
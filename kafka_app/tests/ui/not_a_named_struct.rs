@@ -0,0 +1,14 @@
+use kafka_app::Loggable;
+use serde::Serialize;
+
+#[derive(Serialize, Loggable)]
+#[loggable(level = "INFO")]
+struct TupleLog(String);
+
+#[derive(Serialize, Loggable)]
+#[loggable(level = "INFO")]
+enum EnumLog {
+    Message(String),
+}
+
+fn main() {}
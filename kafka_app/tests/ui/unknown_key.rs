@@ -0,0 +1,10 @@
+use kafka_app::Loggable;
+use serde::Serialize;
+
+#[derive(Serialize, Loggable)]
+#[loggable(level = "INFO", key = "user_id")]
+struct AuditLog {
+    message: String,
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use kafka_app::Loggable;
+use serde::Serialize;
+
+#[derive(Serialize, Loggable)]
+#[loggable(level = "INFO")]
+struct AuditLog {
+    message: String,
+    #[loggable(skip)]
+    user: String,
+}
+
+fn main() {}
@@ -0,0 +1,312 @@
+//! `#[derive(Loggable)]` for `kafka_app`, which re-exports it next to the trait.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    Data, DeriveInput, Field, Fields, GenericArgument, LitStr, PathArguments, Type,
+    parse_macro_input,
+};
+
+/// Implements `kafka_app::Loggable` for a struct with named fields and generates a builder.
+///
+/// ```ignore
+/// #[derive(Serialize, Loggable)]
+/// #[loggable(topic = "payment_logs", key = "order_id", level = "WARN")]
+/// struct PaymentLog {
+///     level: String,
+///     message: String,
+///     hostname: String,
+///     timestamp: DateTime<Utc>,
+///     order_id: u64,
+///     #[loggable(default)]
+///     retries: u32,
+///     card_brand: Option<String>,
+/// }
+///
+/// let log = PaymentLog::builder().message("card declined").order_id(42u64).build()?;
+/// ```
+///
+/// Every struct attribute is optional:
+/// - `topic`: the log always goes to this topic, with the routing prefix, instead of being
+///   routed.
+/// - `key`: a field whose `Display` text is the partition key, instead of the topic's
+///   partition strategy.
+/// - `level`: picks the log type, and fills in a `level` field left unset. One of `TRACE`,
+///   `DEBUG`, `INFO`, `NOTICE`, `WARN`, `WARNING`, `ERROR`, `ERR`, `CRITICAL`, `CRIT`, `ALERT`,
+///   `EMERGENCY`, `EMERG`, `FATAL` or `PANIC`, in any case. Without it the struct needs a
+///   `level` field, and the log type follows its value.
+///
+/// A `timestamp: DateTime<Utc>` field is used as the record timestamp.
+///
+/// The struct's fields are its own, so its logs can only be produced as JSON: a topic that
+/// uses the Avro or Protobuf log schemas fails the send.
+///
+/// `PaymentLog::builder()` has a setter per field taking `impl Into<T>` (the inner type for
+/// `Option` fields). `build` fills an unset `hostname` with the machine's hostname, `timestamp`
+/// with the current time and `level` from the attribute. `Option` fields and fields marked
+/// `#[loggable(default)]` fall back to their default; any other unset field makes `build`
+/// fail with `kafka_app::MissingField`.
+#[proc_macro_derive(Loggable, attributes(loggable))]
+pub fn derive_loggable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Levels `level = "..."` accepts. `kafka_app::lines::level_type` reads any string, so a typo
+/// would quietly become an info log.
+const LEVELS: [&str; 15] = [
+    "TRACE",
+    "DEBUG",
+    "INFO",
+    "NOTICE",
+    "WARN",
+    "WARNING",
+    "ERROR",
+    "ERR",
+    "CRITICAL",
+    "CRIT",
+    "ALERT",
+    "EMERGENCY",
+    "EMERG",
+    "FATAL",
+    "PANIC",
+];
+
+#[derive(Default)]
+struct StructAttrs {
+    topic: Option<LitStr>,
+    key: Option<LitStr>,
+    level: Option<LitStr>,
+}
+
+fn struct_attrs(input: &DeriveInput) -> syn::Result<StructAttrs> {
+    let mut attrs = StructAttrs::default();
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("loggable"))
+    {
+        attr.parse_nested_meta(|meta| {
+            let slot = if meta.path.is_ident("topic") {
+                &mut attrs.topic
+            } else if meta.path.is_ident("key") {
+                &mut attrs.key
+            } else if meta.path.is_ident("level") {
+                &mut attrs.level
+            } else {
+                return Err(
+                    meta.error("unsupported loggable attribute, expected topic, key or level")
+                );
+            };
+            *slot = Some(meta.value()?.parse()?);
+            Ok(())
+        })?;
+    }
+    if let Some(level) = &attrs.level
+        && !LEVELS.contains(&level.value().to_ascii_uppercase().as_str())
+    {
+        return Err(syn::Error::new_spanned(
+            level,
+            format!(
+                "unknown level `{}`, expected one of {}",
+                level.value(),
+                LEVELS.join(", ")
+            ),
+        ));
+    }
+    Ok(attrs)
+}
+
+fn has_default(field: &Field) -> syn::Result<bool> {
+    let mut default = false;
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("loggable"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("default") {
+                default = true;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported loggable field attribute, expected default"))
+            }
+        })?;
+    }
+    Ok(default)
+}
+
+/// `T` when `ty` is `Option<T>`.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if path.qself.is_some() || segment.ident != "Option" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.first() {
+        Some(GenericArgument::Type(inner)) if args.args.len() == 1 => Some(inner),
+        _ => None,
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let attrs = struct_attrs(&input)?;
+    let name = &input.ident;
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    name,
+                    "Loggable needs a struct with named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "Loggable can only be derived for structs",
+            ));
+        }
+    };
+    let has_field = |wanted: &str| {
+        fields
+            .iter()
+            .any(|field| field.ident.as_ref().is_some_and(|ident| ident == wanted))
+    };
+
+    let log_type = match &attrs.level {
+        Some(level) => quote! { ::kafka_app::lines::level_type(#level) },
+        None if has_field("level") => quote! { ::kafka_app::lines::level_type(&self.level) },
+        None => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "Loggable needs a `level` field or a #[loggable(level = \"...\")] attribute",
+            ));
+        }
+    };
+    let timestamp_fn = has_field("timestamp").then(|| {
+        quote! {
+            fn timestamp(&self) -> ::core::option::Option<::kafka_app::__private::DateTime<::kafka_app::__private::Utc>> {
+                ::core::option::Option::Some(self.timestamp)
+            }
+        }
+    });
+    let topic_fn = attrs.topic.as_ref().map(|topic| {
+        quote! {
+            fn topic(&self) -> ::core::option::Option<&str> {
+                ::core::option::Option::Some(#topic)
+            }
+        }
+    });
+    let key_fn = match &attrs.key {
+        Some(key) if !has_field(&key.value()) => {
+            return Err(syn::Error::new_spanned(
+                key,
+                format!("no field named `{}` to use as the key", key.value()),
+            ));
+        }
+        Some(key) => {
+            let field = format_ident!("{}", key.value());
+            Some(quote! {
+                fn key(&self) -> ::core::option::Option<::std::vec::Vec<u8>> {
+                    ::core::option::Option::Some(::std::string::ToString::to_string(&self.#field).into_bytes())
+                }
+            })
+        }
+        None => None,
+    };
+
+    let builder = format_ident!("{}Builder", name);
+    let builder_doc = format!("Builds a [`{}`].", name);
+    let vis = &input.vis;
+    let generics = &input.generics;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let mut slots = Vec::new();
+    let mut setters = Vec::new();
+    let mut values = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named field");
+        let ty = &field.ty;
+        let inner = option_inner(ty);
+        let stored = inner.unwrap_or(ty);
+        slots.push(quote! { #ident: ::core::option::Option<#stored> });
+        setters.push(quote! {
+            pub fn #ident(mut self, value: impl ::core::convert::Into<#stored>) -> Self {
+                self.#ident = ::core::option::Option::Some(value.into());
+                self
+            }
+        });
+
+        let missing = ident.to_string();
+        let value = if inner.is_some() {
+            quote! { self.#ident }
+        } else if has_default(field)? {
+            quote! { self.#ident.unwrap_or_default() }
+        } else if ident == "hostname" {
+            quote! { self.#ident.unwrap_or_else(|| ::kafka_app::helper::get_hostname().into()) }
+        } else if ident == "timestamp" {
+            quote! { self.#ident.unwrap_or_else(|| ::kafka_app::__private::Utc::now().into()) }
+        } else if let (true, Some(level)) = (ident == "level", &attrs.level) {
+            quote! { self.#ident.unwrap_or_else(|| #level.into()) }
+        } else {
+            quote! { self.#ident.ok_or(::kafka_app::MissingField(#missing))? }
+        };
+        values.push(quote! { #ident: #value });
+    }
+    let idents = fields.iter().map(|field| &field.ident);
+
+    Ok(quote! {
+        impl #impl_generics ::kafka_app::Loggable for #name #ty_generics #where_clause {
+            fn log_type(&self) -> ::kafka_app::LogType {
+                #log_type
+            }
+
+            fn fits_log_schema(&self) -> bool {
+                false
+            }
+
+            #timestamp_fn
+            #topic_fn
+            #key_fn
+        }
+
+        #[doc = #builder_doc]
+        #vis struct #builder #generics #where_clause {
+            #(#slots,)*
+        }
+
+        impl #impl_generics ::core::default::Default for #builder #ty_generics #where_clause {
+            fn default() -> Self {
+                Self {
+                    #(#idents: ::core::option::Option::None,)*
+                }
+            }
+        }
+
+        impl #impl_generics #builder #ty_generics #where_clause {
+            #(#setters)*
+
+            pub fn build(self) -> ::core::result::Result<#name #ty_generics, ::kafka_app::MissingField> {
+                ::core::result::Result::Ok(#name {
+                    #(#values,)*
+                })
+            }
+        }
+
+        impl #impl_generics #name #ty_generics #where_clause {
+            pub fn builder() -> #builder #ty_generics {
+                ::core::default::Default::default()
+            }
+        }
+    })
+}
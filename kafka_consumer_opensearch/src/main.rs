@@ -1,17 +1,40 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use kafka_app::avro::SchemaRegistryConfig;
+use kafka_app::constant::{CONTENT_TYPE_HEADER, SCHEMA_REGISTRY_URL};
+use kafka_app::encoding::PayloadDecoder;
+use kafka_app::oversize::Reassembler;
 use rdkafka::config::ClientConfig;
 use rdkafka::consumer::{Consumer, StreamConsumer};
-use rdkafka::message::{Message};
-use serde::{Serialize, Deserialize};
-use serde_json::json;
+use rdkafka::message::{BorrowedMessage, Headers, Message};
+use serde_json::{Map, Value, json};
 use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
 
-// This struct should match the LogEntry in your producer
-#[derive(Serialize, Deserialize, Debug)]
-struct LogEntry {
-    level: String,
-    message: String,
-    hostname: String,
-    timestamp: u64,
+// The producer (kafka_app) says how each payload is encoded in the `content-type` header:
+// JSON, Protobuf with the message type as a parameter, or Avro, whose schema is fetched from the
+// Schema Registry at SCHEMA_REGISTRY_URL.
+fn header_value<'a>(msg: &'a BorrowedMessage<'a>, name: &str) -> Option<&'a str> {
+    msg.headers()?
+        .iter()
+        .find(|header| header.key == name)
+        .and_then(|header| header.value)
+        .and_then(|value| std::str::from_utf8(value).ok())
+}
+
+// Every other header (log type, schema version, hostname, trace and span ids, and any the
+// application added) is indexed under `headers`.
+fn header_fields(msg: &BorrowedMessage<'_>) -> Map<String, Value> {
+    let Some(headers) = msg.headers() else {
+        return Map::new();
+    };
+    headers
+        .iter()
+        .filter(|header| header.key != CONTENT_TYPE_HEADER)
+        .filter_map(|header| {
+            let value = std::str::from_utf8(header.value?).ok()?;
+            Some((header.key.to_string(), json!(value)))
+        })
+        .collect()
 }
 
 #[tokio::main]
@@ -28,7 +51,7 @@ async fn main() {
 
     // Base64 encode the credentials for Basic Auth
     let auth_string = format!("{}:{}", opensearch_user, opensearch_password);
-    let encoded_auth = base64::encode(auth_string.as_bytes());
+    let encoded_auth = STANDARD.encode(auth_string.as_bytes());
     let auth_header_value = format!("Basic {}", encoded_auth);
 
     let consumer: StreamConsumer = ClientConfig::new()
@@ -49,34 +72,60 @@ async fn main() {
         .build()
         .expect("Failed to create HTTP client");
 
+    let schema_registry = std::env::var(SCHEMA_REGISTRY_URL)
+        .ok()
+        .map(|url| SchemaRegistryConfig {
+            url,
+            ..SchemaRegistryConfig::default()
+        });
+    let decoder = PayloadDecoder::new(schema_registry).expect("Failed to create payload decoder");
+
+    // Logs the producer split for being too large arrive as several records.
+    let mut reassembler = Reassembler::default();
 
     loop {
         match consumer.recv().await {
             Ok(msg) => {
-                let payload_str = match msg.payload_view::<str>() {
-                    Some(Ok(s)) => s,
-                    Some(Err(e)) => {
-                        eprintln!("Error deserializing message payload: {:?}", e);
-                        continue;
-                    },
+                let payload = match msg.payload() {
+                    Some(payload) => payload,
                     None => {
                         println!("Received message with no payload");
                         continue;
                     }
                 };
+                let evicted = reassembler.evicted();
+                let reassembled = reassembler.push(|name| header_value(&msg, name), payload);
+                if reassembler.evicted() > evicted {
+                    eprintln!(
+                        "Gave up on {} split logs whose chunks did not all arrive",
+                        reassembler.evicted() - evicted
+                    );
+                }
+                let payload = match reassembled {
+                    Some(payload) => payload,
+                    // The rest of a split log is still to come.
+                    None => continue,
+                };
+                let content_type = header_value(&msg, CONTENT_TYPE_HEADER);
 
-                println!("Received message: {}", payload_str);
-
-                match serde_json::from_str::<LogEntry>(payload_str) {
-                    Ok(log_entry) => {
-                        // Send to OpenSearch
-                        let opensearch_doc = json!({
-                            "level": log_entry.level,
-                            "message": log_entry.message,
-                            "hostname": log_entry.hostname,
-                            "timestamp": log_entry.timestamp,
-                            "@timestamp": chrono::Utc::now().to_rfc3339() // Add a standard timestamp for OpenSearch
-                        });
+                match decoder.decode(content_type, &payload).await {
+                    Ok(mut opensearch_doc) => {
+                        println!("Received message: {}", opensearch_doc);
+
+                        // Add a standard timestamp for OpenSearch: the event time the producer
+                        // set as the record timestamp, or now for records without one.
+                        let event_time = msg
+                            .timestamp()
+                            .to_millis()
+                            .and_then(chrono::DateTime::from_timestamp_millis)
+                            .unwrap_or_else(chrono::Utc::now);
+                        if let Some(doc) = opensearch_doc.as_object_mut() {
+                            doc.insert("@timestamp".to_string(), json!(event_time.to_rfc3339()));
+                            let headers = header_fields(&msg);
+                            if !headers.is_empty() {
+                                doc.insert("headers".to_string(), Value::Object(headers));
+                            }
+                        }
 
                         let client_clone = http_client.clone();
                         let opensearch_url_clone = opensearch_url.to_string();
@@ -104,7 +153,7 @@ async fn main() {
                         });
                     },
                     Err(e) => {
-                        eprintln!("Failed to parse JSON log: {} Error: {}", payload_str, e);
+                        eprintln!("Failed to decode log (content-type {:?}): {}", content_type, e);
                     }
                 }
                 // Manually commit offset after successful processing
@@ -0,0 +1,338 @@
+//! Joins the lines of another program's output into events, so a stack trace becomes a single
+//! log, and reads the level a plain-text line names.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use regex::Regex;
+use serde::Deserialize;
+
+/// Indented frames (Java `at ...`, Rust backtraces), Java's `Caused by:` / `... N more` and the
+/// trailer of a Rust panic.
+pub const DEFAULT_CONTINUATION_PATTERN: &str =
+    r"^(\s|Caused by:|\.\.\. \d+ more|stack backtrace:|note: run with)";
+
+/// Only this many leading words of a plain-text line are searched for a level.
+const TEXT_LEVEL_WORDS: usize = 4;
+
+/// How consecutive lines are joined into one event, so a stack trace becomes a single log.
+///
+/// - With `start_pattern`, a line matching it starts a new event and every other line is
+///   appended to the current one. `continuation_pattern` is then ignored.
+/// - Otherwise, with `continuation_pattern`, a line matching it is appended to the current event
+///   and every other line starts a new one.
+/// - With neither (an empty pattern counts as unset), lines are joined until none arrives for
+///   `timeout_ms`.
+///
+/// In every mode an event is complete once no line has been added for `timeout_ms`, or once it
+/// holds `max_lines` lines.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MultilineConfig {
+    pub start_pattern: Option<String>,
+    pub continuation_pattern: Option<String>,
+    pub timeout_ms: u64,
+    pub max_lines: usize,
+}
+
+impl Default for MultilineConfig {
+    fn default() -> Self {
+        Self {
+            start_pattern: None,
+            continuation_pattern: Some(DEFAULT_CONTINUATION_PATTERN.into()),
+            timeout_ms: 1000,
+            max_lines: 500,
+        }
+    }
+}
+
+/// Joins lines into events according to a [`MultilineConfig`]. Lines go in through `push` and
+/// events come out once a later line shows they are complete, or through `flush_expired` and
+/// `finish` when no such line arrives.
+pub struct MultilineAggregator {
+    start: Option<Regex>,
+    continuation: Option<Regex>,
+    timeout: Duration,
+    max_lines: usize,
+    pending: String,
+    pending_lines: usize,
+    last_line_at: Instant,
+}
+
+impl MultilineAggregator {
+    pub fn new(cfg: &MultilineConfig) -> anyhow::Result<Self> {
+        anyhow::ensure!(cfg.max_lines > 0, "multiline max_lines must be at least 1");
+        let compile = |pattern: &Option<String>| {
+            pattern
+                .as_deref()
+                .filter(|raw| !raw.is_empty())
+                .map(|raw| {
+                    Regex::new(raw).with_context(|| format!("invalid multiline pattern {}", raw))
+                })
+                .transpose()
+        };
+        Ok(Self {
+            start: compile(&cfg.start_pattern)?,
+            continuation: compile(&cfg.continuation_pattern)?,
+            timeout: Duration::from_millis(cfg.timeout_ms),
+            max_lines: cfg.max_lines,
+            pending: String::new(),
+            pending_lines: 0,
+            last_line_at: Instant::now(),
+        })
+    }
+
+    /// Adds a line. Returns the previous event when this line starts a new one.
+    pub fn push(&mut self, line: &str) -> Option<String> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        self.last_line_at = Instant::now();
+        if self.pending_lines == 0 {
+            self.start_event(line);
+            return None;
+        }
+        if self.continues(line) && self.pending_lines < self.max_lines {
+            self.pending.push('\n');
+            self.pending.push_str(line);
+            self.pending_lines += 1;
+            return None;
+        }
+        let event = std::mem::take(&mut self.pending);
+        self.start_event(line);
+        Some(event)
+    }
+
+    /// Returns the pending event if no line has been added to it for the timeout.
+    pub fn flush_expired(&mut self, now: Instant) -> Option<String> {
+        match self.deadline() {
+            Some(deadline) if now >= deadline => self.finish(),
+            _ => None,
+        }
+    }
+
+    /// Returns the pending event regardless of the timeout, e.g. at the end of the input.
+    pub fn finish(&mut self) -> Option<String> {
+        if self.pending_lines == 0 {
+            return None;
+        }
+        self.pending_lines = 0;
+        Some(std::mem::take(&mut self.pending))
+    }
+
+    /// When the pending event times out, if there is one.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.is_pending().then(|| self.last_line_at + self.timeout)
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending_lines > 0
+    }
+
+    fn start_event(&mut self, line: &str) {
+        self.pending.clear();
+        self.pending.push_str(line);
+        self.pending_lines = 1;
+    }
+
+    fn continues(&self, line: &str) -> bool {
+        match (&self.start, &self.continuation) {
+            (Some(start), _) => !start.is_match(line),
+            (None, Some(continuation)) => continuation.is_match(line),
+            (None, None) => true,
+        }
+    }
+}
+
+/// The first of the leading words of a plain-text line that names a level, e.g. `ERROR` in
+/// `2024-05-01 12:00:00 [ERROR] disk full`.
+pub fn text_level(line: &str) -> Option<String> {
+    line.split_whitespace()
+        .take(TEXT_LEVEL_WORDS)
+        .map(|word| word.trim_matches(|c: char| !c.is_ascii_alphabetic()))
+        .find(|word| {
+            matches!(
+                word.to_ascii_uppercase().as_str(),
+                "TRACE"
+                    | "DEBUG"
+                    | "INFO"
+                    | "NOTICE"
+                    | "WARN"
+                    | "WARNING"
+                    | "ERROR"
+                    | "ERR"
+                    | "FATAL"
+                    | "CRITICAL"
+                    | "CRIT"
+            )
+        })
+        .map(str::to_ascii_uppercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aggregator(cfg: MultilineConfig) -> MultilineAggregator {
+        MultilineAggregator::new(&cfg).unwrap()
+    }
+
+    /// Pushes every line, then finishes, and returns the events in order.
+    fn events(aggregator: &mut MultilineAggregator, lines: &[&str]) -> Vec<String> {
+        let mut events: Vec<String> = lines
+            .iter()
+            .filter_map(|line| aggregator.push(line))
+            .collect();
+        events.extend(aggregator.finish());
+        events
+    }
+
+    #[test]
+    fn the_default_joins_java_and_rust_traces() {
+        let mut aggregator = aggregator(MultilineConfig::default());
+        let events = events(
+            &mut aggregator,
+            &[
+                "ERROR request failed",
+                "java.lang.IllegalStateException: boom",
+                "    at com.example.Handler.handle(Handler.java:42)",
+                "Caused by: java.io.IOException: closed",
+                "    ... 12 more",
+                "thread 'main' panicked at src/main.rs:2:5:",
+                "stack backtrace:",
+                "   0: rust_begin_unwind",
+                "note: run with `RUST_BACKTRACE=full` for a verbose backtrace.",
+                "INFO next request",
+            ],
+        );
+        assert_eq!(
+            events,
+            [
+                "ERROR request failed",
+                "java.lang.IllegalStateException: boom\n    at com.example.Handler.handle(Handler.java:42)\nCaused by: java.io.IOException: closed\n    ... 12 more",
+                "thread 'main' panicked at src/main.rs:2:5:\nstack backtrace:\n   0: rust_begin_unwind\nnote: run with `RUST_BACKTRACE=full` for a verbose backtrace.",
+                "INFO next request",
+            ]
+        );
+    }
+
+    #[test]
+    fn a_start_pattern_starts_each_event() {
+        let mut aggregator = aggregator(MultilineConfig {
+            start_pattern: Some(r"^\d{4}-\d{2}-\d{2} ".into()),
+            // Ignored when a start pattern is set.
+            continuation_pattern: Some("^never$".into()),
+            ..MultilineConfig::default()
+        });
+        let events = events(
+            &mut aggregator,
+            &[
+                "2024-05-01 first",
+                "not indented, still part of it",
+                "2024-05-01 second",
+            ],
+        );
+        assert_eq!(
+            events,
+            [
+                "2024-05-01 first\nnot indented, still part of it",
+                "2024-05-01 second"
+            ]
+        );
+    }
+
+    #[test]
+    fn a_custom_continuation_pattern_appends_matching_lines() {
+        let mut aggregator = aggregator(MultilineConfig {
+            continuation_pattern: Some(r"^\+ ".into()),
+            ..MultilineConfig::default()
+        });
+        let events = events(&mut aggregator, &["one", "+ more", "  indented", "+ tail"]);
+        assert_eq!(events, ["one\n+ more", "  indented\n+ tail"]);
+    }
+
+    #[test]
+    fn without_patterns_lines_are_joined_until_the_timeout() {
+        let mut aggregator = aggregator(MultilineConfig {
+            continuation_pattern: Some(String::new()),
+            ..MultilineConfig::default()
+        });
+        assert_eq!(aggregator.push("one"), None);
+        assert_eq!(aggregator.push("two"), None);
+        assert_eq!(aggregator.finish().as_deref(), Some("one\ntwo"));
+    }
+
+    #[test]
+    fn flush_expired_returns_the_event_once_the_timeout_passed() {
+        let mut aggregator = aggregator(MultilineConfig {
+            timeout_ms: 100,
+            ..MultilineConfig::default()
+        });
+        assert_eq!(aggregator.deadline(), None);
+        aggregator.push("ERROR boom");
+        aggregator.push("    at frame");
+        let deadline = aggregator.deadline().unwrap();
+
+        assert_eq!(
+            aggregator.flush_expired(deadline - Duration::from_millis(1)),
+            None
+        );
+        assert!(aggregator.is_pending());
+        assert_eq!(
+            aggregator.flush_expired(deadline).as_deref(),
+            Some("ERROR boom\n    at frame")
+        );
+        assert!(!aggregator.is_pending());
+        assert_eq!(aggregator.flush_expired(deadline), None);
+    }
+
+    #[test]
+    fn max_lines_caps_an_event() {
+        let mut aggregator = aggregator(MultilineConfig {
+            max_lines: 2,
+            ..MultilineConfig::default()
+        });
+        let events = events(&mut aggregator, &["head", " a", " b", " c"]);
+        assert_eq!(events, ["head\n a", " b\n c"]);
+    }
+
+    #[test]
+    fn line_endings_are_trimmed() {
+        let mut aggregator = aggregator(MultilineConfig::default());
+        let events = events(&mut aggregator, &["head\r\n", "  frame\n"]);
+        assert_eq!(events, ["head\n  frame"]);
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        assert!(
+            MultilineAggregator::new(&MultilineConfig {
+                max_lines: 0,
+                ..MultilineConfig::default()
+            })
+            .is_err()
+        );
+        assert!(
+            MultilineAggregator::new(&MultilineConfig {
+                start_pattern: Some("(".into()),
+                ..MultilineConfig::default()
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn text_level_reads_the_leading_words() {
+        assert_eq!(
+            text_level("2024-05-01 12:00:00 [ERROR] disk full").as_deref(),
+            Some("ERROR")
+        );
+        assert_eq!(text_level("warn: low memory").as_deref(), Some("WARN"));
+        assert_eq!(
+            text_level("Critical failure in module").as_deref(),
+            Some("CRITICAL")
+        );
+        // Only the first four words are searched.
+        assert_eq!(text_level("a b c d error"), None);
+        assert_eq!(text_level("informational message"), None);
+        assert_eq!(text_level(""), None);
+    }
+}